@@ -0,0 +1,19 @@
+//! Feeds arbitrary text to the deb822 parser used for control files and
+//! `.dsc`s.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/deb822.rs"]
+mod deb822;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data)
+        && let Ok(paragraphs) = deb822::parse(text)
+    {
+        for paragraph in &paragraphs {
+            let _ = paragraph.get("Depends").map(deb822::relationship_names);
+        }
+    }
+});
@@ -0,0 +1,26 @@
+//! Feeds arbitrary bytes to the deb readers as if they were a `.deb`: they
+//! must return an error naming the bad member, never panic.
+#![no_main]
+
+use std::io::Write;
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/errors.rs"]
+mod errors;
+
+#[allow(dead_code)]
+#[path = "../../src/archive.rs"]
+mod archive;
+
+fuzz_target!(|data: &[u8]| {
+    let mut deb = tempfile::NamedTempFile::new().unwrap();
+    deb.write_all(data).unwrap();
+    let path = deb.path();
+
+    let _ = archive::member_names(path);
+    // Both go through `with_member`, for the control and the data member
+    let _ = archive::read_control_files(path);
+    let _ = archive::list_member(path, "data");
+});
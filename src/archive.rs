@@ -0,0 +1,400 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use flate2::read::MultiGzDecoder;
+use ruzstd::decoding::StreamingDecoder;
+use xz2::read::XzDecoder;
+
+use crate::errors::AppError;
+
+/// Compression of a `control.tar*`/`data.tar*` member or a tarball package,
+/// from its name.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Compression {
+    None,
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    fn of_member(name: &str) -> Option<Compression> {
+        if name.ends_with(".apk") {
+            return Some(Compression::Gzip);
+        }
+        match name.rsplit_once(".tar") {
+            Some((_, "")) => Some(Compression::None),
+            Some((_, ".gz")) => Some(Compression::Gzip),
+            Some((_, ".xz")) => Some(Compression::Xz),
+            Some((_, ".zst")) => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    fn decoder<'a, R: Read + 'a>(self, member: &str, reader: R) -> Result<Box<dyn Read + 'a>, AppError> {
+        Ok(match self {
+            Compression::None => Box::new(reader),
+            // apk packages are several gzip streams back to back
+            Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            Compression::Xz => Box::new(XzDecoder::new(reader)),
+            Compression::Zstd => Box::new(
+                StreamingDecoder::new(reader).map_err(|e| AppError::Extract(format!("{}: {}", member, e)))?,
+            ),
+        })
+    }
+}
+
+fn open_tarball(path: &Path) -> Result<tar::Archive<Box<dyn Read>>, AppError> {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let compression = Compression::of_member(&name)
+        .ok_or_else(|| AppError::Extract(format!("Unsupported compression for {}", name)))?;
+    let file = File::open(path).map_err(|e| AppError::Extract(format!("{}: {}", path.display(), e)))?;
+    let mut tar = tar::Archive::new(compression.decoder(&name, BufReader::new(file))?);
+    // apk segments are concatenated tars, only the last with an end marker
+    tar.set_ignore_zeros(true);
+    Ok(tar)
+}
+
+/// Unpacks a package that is one compressed tarball (`.pkg.tar.zst`,
+/// `.apk`) into
+/// `dest`, metadata files included.
+pub fn unpack_tarball(path: &Path, dest: &Path) -> Result<(), AppError> {
+    let mut tar = open_tarball(path)?;
+    tar.set_preserve_permissions(true);
+    tar.set_overwrite(true);
+    tar.unpack(dest)
+        .map_err(|e| AppError::Extract(format!("Failed to unpack {}: {}", path.display(), e)))
+}
+
+/// Reads the file `name` out of `tar`, stopping as soon as it is found.
+/// `label` names the archive in errors.
+fn find_bytes<R: Read>(tar: &mut tar::Archive<R>, name: &str, label: &str) -> Result<Option<Vec<u8>>, AppError> {
+    let entries = tar
+        .entries()
+        .map_err(|e| AppError::Extract(format!("{}: {}", label, e)))?;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| AppError::Extract(format!("{}: {}", label, e)))?;
+        let is_match = entry
+            .path()
+            .is_ok_and(|p| p.strip_prefix("./").unwrap_or(&p) == Path::new(name));
+        if is_match {
+            let mut content = Vec::new();
+            entry
+                .read_to_end(&mut content)
+                .map_err(|e| AppError::Extract(format!("{} in {}: {}", name, label, e)))?;
+            return Ok(Some(content));
+        }
+    }
+    Ok(None)
+}
+
+/// Reads the text file `name` out of `tar`, like `find_bytes`.
+fn find_file<R: Read>(tar: &mut tar::Archive<R>, name: &str, label: &str) -> Result<Option<String>, AppError> {
+    find_bytes(tar, name, label)?
+        .map(|content| {
+            String::from_utf8(content).map_err(|e| AppError::Extract(format!("{} in {}: {}", name, label, e)))
+        })
+        .transpose()
+}
+
+/// Paths of the regular files in `tar`, as stored.
+fn file_paths<R: Read>(tar: &mut tar::Archive<R>, label: &str) -> Result<Vec<String>, AppError> {
+    let entries = tar
+        .entries()
+        .map_err(|e| AppError::Extract(format!("{}: {}", label, e)))?;
+    let mut paths = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| AppError::Extract(format!("{}: {}", label, e)))?;
+        if entry.header().entry_type().is_file()
+            && let Ok(path) = entry.path()
+        {
+            paths.push(path.to_string_lossy().to_string());
+        }
+    }
+    Ok(paths)
+}
+
+/// Lists the files of a tarball package without unpacking it; top-level
+/// metadata files (`.PKGINFO`, ...) included.
+pub fn list_tarball(path: &Path) -> Result<Vec<String>, AppError> {
+    let mut tar = open_tarball(path)?;
+    file_paths(&mut tar, &path.display().to_string())
+}
+
+/// A member name as stored, without the space padding or the `/` GNU
+/// `ar` ends names with.
+fn member_name(identifier: &[u8]) -> String {
+    String::from_utf8_lossy(identifier).trim_end_matches([' ', '/']).to_string()
+}
+
+/// An `ar` error, placed after the last member read fine.
+fn malformed(deb_path: &Path, previous: Option<&str>, error: std::io::Error) -> AppError {
+    let place = previous.map_or("in its first member header".to_string(), |m| format!("after member {}", m));
+    AppError::Extract(format!("{} is not a well-formed ar archive {}: {}", deb_path.display(), place, error))
+}
+
+/// Hands the first `<prefix>.tar*` member of the deb at `deb_path`,
+/// wherever it sits among the others, to `read` with its decompressor and
+/// a label for errors; `None` if the deb has no such member.
+fn with_member<T>(
+    deb_path: &Path,
+    prefix: &str,
+    read: impl FnOnce(&str, Box<dyn Read + '_>) -> Result<T, AppError>,
+) -> Result<Option<T>, AppError> {
+    let file = File::open(deb_path).map_err(|e| AppError::Extract(format!("{}: {}", deb_path.display(), e)))?;
+    let mut deb = ar::Archive::new(BufReader::new(file));
+    let mut previous: Option<String> = None;
+
+    while let Some(entry) = deb.next_entry() {
+        let entry = entry.map_err(|e| malformed(deb_path, previous.as_deref(), e))?;
+        let member = member_name(entry.header().identifier());
+        if !member.starts_with(&format!("{}.tar", prefix)) {
+            previous = Some(member);
+            continue;
+        }
+        let label = format!("{} of {}", member, deb_path.display());
+        let compression = Compression::of_member(&member).ok_or_else(|| {
+            AppError::Extract(format!("{}: unsupported compression (expected .tar, .tar.gz, .tar.xz or .tar.zst)", label))
+        })?;
+        return read(&label, compression.decoder(&label, entry)?).map(Some);
+    }
+    Ok(None)
+}
+
+/// Lists the files in the first `<prefix>.tar*` member of a deb.
+pub fn list_member(deb_path: &Path, prefix: &str) -> Result<Vec<String>, AppError> {
+    with_member(deb_path, prefix, |label, reader| file_paths(&mut tar::Archive::new(reader), label))?.ok_or_else(|| {
+        AppError::Extract(format!("No {}.tar* member in {}", prefix, deb_path.display()))
+    })
+}
+
+/// Reads the top-level file `name` (e.g. `.PKGINFO`) from a tarball
+/// package.
+pub fn read_tarball_file(path: &Path, name: &str) -> Result<Option<String>, AppError> {
+    let mut tar = open_tarball(path)?;
+    find_file(&mut tar, name, &path.display().to_string())
+}
+
+/// Reads the file `name` (e.g. `conffiles`) from the first `<prefix>.tar*`
+/// member of the deb at `deb_path`; `None` if either is missing.
+pub fn read_member_bytes(deb_path: &Path, prefix: &str, name: &str) -> Result<Option<Vec<u8>>, AppError> {
+    Ok(with_member(deb_path, prefix, |label, reader| find_bytes(&mut tar::Archive::new(reader), name, label))?.flatten())
+}
+
+/// Like `read_member_bytes`, for a text file.
+pub fn read_member_file(deb_path: &Path, prefix: &str, name: &str) -> Result<Option<String>, AppError> {
+    read_member_bytes(deb_path, prefix, name)?
+        .map(|content| {
+            String::from_utf8(content)
+                .map_err(|e| AppError::Extract(format!("{} in {}: {}", name, deb_path.display(), e)))
+        })
+        .transpose()
+}
+
+/// The metadata files of a deb's `control.tar*` app2nix reads.
+#[derive(Debug, Default)]
+pub struct ControlFiles {
+    pub control: String,
+    pub conffiles: Option<String>,
+    pub postinst: Option<String>,
+}
+
+/// Reads `control`, `conffiles` and `postinst` from the deb at `deb_path`
+/// in one pass over its `control.tar*`, whatever its compression.
+pub fn read_control_files(deb_path: &Path) -> Result<ControlFiles, AppError> {
+    with_member(deb_path, "control", |label, reader| {
+        let mut tar = tar::Archive::new(reader);
+        let entries = tar.entries().map_err(|e| AppError::Extract(format!("{}: {}", label, e)))?;
+        let mut control = None;
+        let mut files = ControlFiles::default();
+        for entry in entries {
+            let mut entry = entry.map_err(|e| AppError::Extract(format!("{}: {}", label, e)))?;
+            let name = entry.path().map(|p| p.to_string_lossy().trim_start_matches("./").to_string());
+            let slot = match name.as_deref() {
+                Ok("control") => &mut control,
+                Ok("conffiles") => &mut files.conffiles,
+                Ok("postinst") => &mut files.postinst,
+                _ => continue,
+            };
+            let mut content = String::new();
+            entry
+                .read_to_string(&mut content)
+                .map_err(|e| AppError::Extract(format!("{} in {}: {}", name.unwrap_or_default(), label, e)))?;
+            *slot = Some(content);
+        }
+        files.control = control.ok_or_else(|| AppError::Extract(format!("No control file in {}", label)))?;
+        Ok(files)
+    })?
+    .ok_or_else(|| AppError::Extract(format!("No control.tar* member in {}", deb_path.display())))
+}
+
+/// The member names of the deb at `deb_path`, in archive order, read
+/// from the `ar` headers alone.
+pub fn member_names(deb_path: &Path) -> Result<Vec<String>, AppError> {
+    let file = File::open(deb_path).map_err(|e| AppError::Extract(format!("{}: {}", deb_path.display(), e)))?;
+    let mut deb = ar::Archive::new(BufReader::new(file));
+    let mut names: Vec<String> = Vec::new();
+    while let Some(entry) = deb.next_entry() {
+        let entry = entry.map_err(|e| malformed(deb_path, names.last().map(String::as_str), e))?;
+        names.push(member_name(entry.header().identifier()));
+    }
+    Ok(names)
+}
+
+/// Streams the first `<prefix>.tar*` member of the deb at `deb_path`
+/// through its decompressor straight into `dest`, so the compressed member
+/// is never written to disk. Returns which member it was (`data.tar.xz of
+/// <deb>`).
+pub fn unpack_member(deb_path: &Path, prefix: &str, dest: &Path) -> Result<String, AppError> {
+    with_member(deb_path, prefix, |label, reader| {
+        let mut tar = tar::Archive::new(reader);
+        tar.set_preserve_permissions(true);
+        tar.set_overwrite(true);
+        tar.unpack(dest).map_err(|e| AppError::Extract(format!("Failed to unpack {}: {}", label, e)))?;
+        Ok(label.to_string())
+    })?
+    .ok_or_else(|| AppError::Extract(format!("No {}.tar* member in {}", prefix, deb_path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::Compression as GzLevel;
+    use flate2::write::GzEncoder;
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    /// A tar stream of `files`; the builder stores paths without `./`.
+    fn tarball(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *content).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), GzLevel::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// One `ar` member: its 60-byte header with `name` as stored, then
+    /// `data` padded to an even length.
+    fn member(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut bytes = format!("{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", name, 0, 0, 0, 100644, data.len()).into_bytes();
+        bytes.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            bytes.push(b'\n');
+        }
+        bytes
+    }
+
+    fn deb(members: &[Vec<u8>]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"!<arch>\n").unwrap();
+        for member in members {
+            file.write_all(member).unwrap();
+        }
+        file
+    }
+
+    fn control_tar() -> Vec<u8> {
+        gzip(&tarball(&[
+            ("./control", b"Package: demo\nVersion: 1.0\n"),
+            ("./postinst", b"#!/bin/sh\n"),
+        ]))
+    }
+
+    #[test]
+    fn reads_members_in_any_order() {
+        let deb = deb(&[
+            member("debian-binary", b"2.0\n"),
+            member("data.tar.gz", &gzip(&tarball(&[("./usr/bin/demo", b"\x7fELF")]))),
+            member("control.tar.gz", &control_tar()),
+        ]);
+        let files = read_control_files(deb.path()).unwrap();
+        assert_eq!(files.control, "Package: demo\nVersion: 1.0\n");
+        assert_eq!(files.postinst.as_deref(), Some("#!/bin/sh\n"));
+        assert_eq!(files.conffiles, None);
+        assert_eq!(list_member(deb.path(), "data").unwrap(), ["usr/bin/demo"]);
+    }
+
+    #[test]
+    fn reads_an_uncompressed_data_tar() {
+        let deb = deb(&[
+            member("debian-binary", b"2.0\n"),
+            member("control.tar.gz", &control_tar()),
+            member("data.tar", &tarball(&[("./opt/demo/demo", b"demo")])),
+        ]);
+        assert_eq!(list_member(deb.path(), "data").unwrap(), ["opt/demo/demo"]);
+        assert_eq!(
+            read_member_file(deb.path(), "data", "opt/demo/demo").unwrap().as_deref(),
+            Some("demo")
+        );
+    }
+
+    #[test]
+    fn strips_gnu_padding_from_member_names() {
+        let deb = deb(&[
+            member("debian-binary/", b"2.0\n"),
+            member("control.tar.gz/", &control_tar()),
+            member("data.tar/", &tarball(&[])),
+        ]);
+        assert_eq!(member_names(deb.path()).unwrap(), ["debian-binary", "control.tar.gz", "data.tar"]);
+        assert!(read_control_files(deb.path()).is_ok());
+    }
+
+    #[test]
+    fn resolves_gnu_long_name_table() {
+        let table = b"control.tar.gz/\ndata.tar.gz/\n";
+        let deb = deb(&[
+            member("//", table),
+            member("debian-binary/", b"2.0\n"),
+            member("/0", &control_tar()),
+            member("/16", &gzip(&tarball(&[("./usr/bin/demo", b"demo")]))),
+        ]);
+        assert_eq!(member_names(deb.path()).unwrap(), ["debian-binary", "control.tar.gz", "data.tar.gz"]);
+        assert_eq!(read_control_files(deb.path()).unwrap().postinst.as_deref(), Some("#!/bin/sh\n"));
+        assert_eq!(list_member(deb.path(), "data").unwrap(), ["usr/bin/demo"]);
+    }
+
+    #[test]
+    fn reports_a_truncated_first_header() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"!<arch>\ndebian-binary   0").unwrap();
+        let error = member_names(file.path()).unwrap_err().to_string();
+        assert!(error.contains("not a well-formed ar archive in its first member header"), "{}", error);
+    }
+
+    #[test]
+    fn reports_the_member_before_a_truncated_header() {
+        let mut bytes = member("debian-binary", b"2.0\n");
+        bytes.extend_from_slice(&member("control.tar.gz", &control_tar())[..30]);
+        let deb = deb(&[bytes]);
+        let error = read_control_files(deb.path()).unwrap_err().to_string();
+        assert!(error.contains("after member debian-binary"), "{}", error);
+    }
+
+    #[test]
+    fn names_the_member_with_an_unknown_compression() {
+        let deb = deb(&[member("debian-binary", b"2.0\n"), member("control.tar.lz", b"")]);
+        let error = read_control_files(deb.path()).unwrap_err().to_string();
+        assert!(error.contains("control.tar.lz of"), "{}", error);
+        assert!(error.contains("unsupported compression"), "{}", error);
+    }
+
+    #[test]
+    fn reports_a_missing_member() {
+        let deb = deb(&[member("debian-binary", b"2.0\n"), member("data.tar", &tarball(&[]))]);
+        let error = read_control_files(deb.path()).unwrap_err().to_string();
+        assert!(error.contains("No control.tar* member"), "{}", error);
+    }
+}
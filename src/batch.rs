@@ -0,0 +1,293 @@
+use std::collections::VecDeque;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use log::info;
+use serde::Deserialize;
+
+use crate::cli::{self, BatchOptions, OptionValue};
+use crate::configuration;
+use crate::errors::AppError;
+
+/// The log each package's run leaves in its directory.
+const LOG_FILE: &str = "app2nix.log";
+
+/// A manifest: options for every package, then the packages.
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default, rename = "package")]
+    packages: Vec<ManifestPackage>,
+}
+
+/// A `[[package]]` entry: the input (or several, merged), the directory
+/// it is generated in and its own options.
+#[derive(Deserialize)]
+struct ManifestPackage {
+    #[serde(default)]
+    input: Option<String>,
+    #[serde(default)]
+    inputs: Vec<String>,
+    #[serde(default)]
+    dir: Option<String>,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+/// A package ready to run: its output directory and full argument list.
+struct Job {
+    dir: PathBuf,
+    args: Vec<String>,
+}
+
+struct Outcome {
+    dir: PathBuf,
+    seconds: f64,
+    /// The exit code and last log line of a failed run.
+    failure: Option<(Option<i32>, String)>,
+}
+
+fn is_url(input: &str) -> bool {
+    input.contains("://")
+}
+
+/// `foo_1.0_amd64.deb` is generated in `foo_1.0_amd64`.
+fn default_dir(input: &str) -> String {
+    let file = input.trim_end_matches('/').rsplit('/').next().unwrap_or(input);
+    [".pkg.tar.zst", ".pkg.tar.xz", ".deb", ".apk", ".snap", ".dsc"]
+        .iter()
+        .find_map(|ext| file.strip_suffix(ext))
+        .unwrap_or(file)
+        .to_string()
+}
+
+/// Makes local paths, both inputs and the values of path options (given
+/// as `--flag value` or `--flag=value`), relative to the manifest rather
+/// than to where each package is generated.
+fn absolute_args(args: &[String], base: &Path) -> Vec<String> {
+    // --analysis-json names an output, which belongs with the package
+    let takes_path = |flag: &str| {
+        flag != "--analysis-json"
+            && cli::OPTIONS.iter().any(|(name, value, _, _)| *name == flag && matches!(value, OptionValue::Path(_)))
+    };
+    // A switch, whose directory can only be joined to it
+    let joined_path = |flag: &str| takes_path(flag) || flag == "--keep-workdir";
+    let absolute = |value: &str| {
+        if is_url(value) || Path::new(value).is_absolute() {
+            value.to_string()
+        } else {
+            base.join(value).display().to_string()
+        }
+    };
+    let mut result = Vec::new();
+    let mut previous: Option<&str> = None;
+    for arg in args {
+        match previous {
+            Some(flag) if takes_path(flag) => result.push(absolute(arg)),
+            _ => match arg.split_once('=') {
+                Some((flag, value)) if joined_path(flag) => result.push(format!("{}={}", flag, absolute(value))),
+                _ => result.push(arg.clone()),
+            },
+        }
+        previous = Some(arg.as_str());
+    }
+    result
+}
+
+fn read_manifest(path: &str, output_dir: &Path) -> Result<Vec<Job>, AppError> {
+    let content = fs::read_to_string(path).map_err(|e| AppError::Input(format!("{}: {}", path, e)))?;
+    let manifest: Manifest =
+        toml::from_str(&content).map_err(|e| AppError::Input(format!("Failed to parse {}: {}", path, e)))?;
+    let base = fs::canonicalize(path)
+        .ok()
+        .and_then(|p| p.parent().map(Path::to_path_buf))
+        .unwrap_or_default();
+
+    let mut jobs: Vec<Job> = Vec::new();
+    for (index, package) in manifest.packages.into_iter().enumerate() {
+        let inputs: Vec<String> = package.input.into_iter().chain(package.inputs).collect();
+        let Some(first) = inputs.first() else {
+            return Err(AppError::Input(format!("{}: package {} has no input", path, index + 1)));
+        };
+        if inputs.iter().any(|i| i == "-") {
+            return Err(AppError::Input(format!("{}: batch packages cannot be read from stdin", path)));
+        }
+        let dir = output_dir.join(package.dir.unwrap_or_else(|| default_dir(first)));
+        if jobs.iter().any(|j| j.dir == dir) {
+            return Err(AppError::Input(format!(
+                "{}: two packages are generated in {}; give one a dir",
+                path,
+                dir.display()
+            )));
+        }
+        let inputs: Vec<String> = inputs
+            .iter()
+            .map(|i| if is_url(i) || Path::new(i).is_absolute() { i.clone() } else { base.join(i).display().to_string() })
+            .collect();
+        let options: Vec<String> = manifest.args.iter().chain(&package.args).cloned().collect();
+        let mut args = inputs;
+        args.extend(absolute_args(&options, &base));
+        // Mistakes in the manifest are reported before anything runs
+        let command_line: Vec<String> = ["app2nix".to_string()].into_iter().chain(args.iter().cloned()).collect();
+        cli::parse_args(&command_line).map_err(|e| AppError::Usage(format!("{}: {}: {}", path, first, e)))?;
+        jobs.push(Job { dir, args });
+    }
+    if jobs.is_empty() {
+        return Err(AppError::Input(format!("{} lists no [[package]]", path)));
+    }
+    Ok(jobs)
+}
+
+/// Runs one package in its directory, with a temporary directory of its
+/// own for downloads and extraction and its log kept next to the output.
+fn run_job(program: &Path, job: &Job) -> Outcome {
+    let started = Instant::now();
+    let failed = |message: String| Outcome {
+        dir: job.dir.clone(),
+        seconds: started.elapsed().as_secs_f64(),
+        failure: Some((None, message)),
+    };
+    if let Err(e) = fs::create_dir_all(&job.dir) {
+        return failed(format!("{}: {}", job.dir.display(), e));
+    }
+    let tmp = match tempfile::tempdir() {
+        Ok(tmp) => tmp,
+        Err(e) => return failed(format!("Failed to create a temporary directory: {}", e)),
+    };
+    let log = match fs::File::create(job.dir.join(LOG_FILE)) {
+        Ok(log) => log,
+        Err(e) => return failed(format!("{}: {}", job.dir.join(LOG_FILE).display(), e)),
+    };
+    let mut command = Command::new(program);
+    command
+        .args(&job.args)
+        .current_dir(&job.dir)
+        .env("APP2NIX_TMPDIR", tmp.path())
+        .env("APP2NIX_NON_INTERACTIVE", "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(log);
+    if let Some(config) = configuration::absolute_config_path() {
+        command.env("APP2NIX_CONFIG", config);
+    }
+    let status = match command.status() {
+        Ok(status) => status,
+        Err(e) => return failed(format!("Failed to run app2nix: {}", e)),
+    };
+    let failure = (!status.success()).then(|| {
+        let log = fs::read_to_string(job.dir.join(LOG_FILE)).unwrap_or_default();
+        let last = log.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("no output").to_string();
+        (status.code(), last)
+    });
+    Outcome {
+        dir: job.dir.clone(),
+        seconds: started.elapsed().as_secs_f64(),
+        failure,
+    }
+}
+
+/// `app2nix batch <manifest.toml>`: generates every package of the
+/// manifest in its own directory, `--jobs` at a time, and reports how
+/// each one went.
+pub fn run_batch_command(options: &BatchOptions) -> Result<(), AppError> {
+    let output_dir = PathBuf::from(options.output_dir.as_deref().unwrap_or("."));
+    let jobs = read_manifest(&options.manifest, &output_dir)?;
+    let program = env::current_exe().map_err(|e| AppError::Generate(format!("Cannot find app2nix itself: {}", e)))?;
+    let workers = options
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+        .min(jobs.len());
+    info!("Generating {} packages, {} at a time...", jobs.len(), workers);
+
+    let total = jobs.len();
+    let queue = Arc::new(Mutex::new(jobs.into_iter().collect::<VecDeque<Job>>()));
+    let outcomes = Arc::new(Mutex::new(Vec::new()));
+    let handles: Vec<_> = (0..workers)
+        .map(|_| {
+            let (queue, outcomes, program) = (Arc::clone(&queue), Arc::clone(&outcomes), program.clone());
+            thread::spawn(move || {
+                loop {
+                    let Some(job) = queue.lock().unwrap_or_else(|e| e.into_inner()).pop_front() else {
+                        break;
+                    };
+                    let outcome = run_job(&program, &job);
+                    match &outcome.failure {
+                        None => info!("{} done ({:.1}s)", outcome.dir.display(), outcome.seconds),
+                        Some(_) => info!("{} failed ({:.1}s)", outcome.dir.display(), outcome.seconds),
+                    }
+                    outcomes.lock().unwrap_or_else(|e| e.into_inner()).push(outcome);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        let _ = handle.join();
+    }
+
+    let mut outcomes = std::mem::take(&mut *outcomes.lock().unwrap_or_else(|e| e.into_inner()));
+    outcomes.sort_by(|a, b| a.dir.cmp(&b.dir));
+    for outcome in &outcomes {
+        match &outcome.failure {
+            None => println!("ok    {} ({:.1}s)", outcome.dir.display(), outcome.seconds),
+            Some((code, last)) => println!(
+                "FAIL  {} ({:.1}s, exit {}): {}",
+                outcome.dir.display(),
+                outcome.seconds,
+                code.map_or("-".to_string(), |c| c.to_string()),
+                last
+            ),
+        }
+    }
+    let failed = outcomes.iter().filter(|o| o.failure.is_some()).count();
+    if failed > 0 {
+        return Err(AppError::Generate(format!(
+            "{} of {} packages failed; see {} in their directories",
+            failed, total, LOG_FILE
+        )));
+    }
+    info!("All {} packages were generated.", total);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebases_path_values_in_both_spellings() {
+        let args: Vec<String> = [
+            "--template",
+            "t.in",
+            "--template=t.in",
+            "--keep-workdir=work",
+            "--keep-workdir",
+            "--tmpdir=/tmp",
+            "--analysis-json=a.json",
+            "--name=x=y",
+            "https://example.com/b.deb",
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+        assert_eq!(
+            absolute_args(&args, Path::new("/m")),
+            [
+                "--template",
+                "/m/t.in",
+                "--template=/m/t.in",
+                "--keep-workdir=/m/work",
+                "--keep-workdir",
+                "--tmpdir=/tmp",
+                "--analysis-json=a.json",
+                "--name=x=y",
+                "https://example.com/b.deb",
+            ]
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::configuration;
+use crate::hashing;
+use crate::plugins::{self, PluginStage};
+use crate::structs::{PackageInfo, ScanOptions};
+
+/// Everything besides the deb itself that changes the analysis; a cached
+/// entry is only reused when all of it matches.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct CacheKey {
+    tool_version: String,
+    target_system: String,
+    skip_deps: bool,
+    max_scan_size: Option<u64>,
+    /// Post-extract plugins can change what the scan sees.
+    #[serde(default)]
+    post_extract_plugins: Vec<String>,
+    /// The libraries config's sha256, as its mappings decide what the
+    /// libraries resolve to.
+    #[serde(default)]
+    libraries_config: String,
+}
+
+impl CacheKey {
+    pub fn new(target_system: &str, options: &ScanOptions) -> CacheKey {
+        CacheKey {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            target_system: target_system.to_string(),
+            skip_deps: options.skip_deps,
+            max_scan_size: options.max_scan_size,
+            post_extract_plugins: plugins::commands(PluginStage::PostExtract),
+            libraries_config: hashing::sha256(Path::new(&configuration::get_config_path()))
+                .map(|d| hashing::to_hex(&d))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CacheEntry {
+    key: CacheKey,
+    package_info: PackageInfo,
+}
+
+/// `$XDG_CACHE_HOME/app2nix`, falling back to `~/.cache/app2nix`.
+pub fn cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = configuration::env_setting("APP2NIX_CACHE_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    let base = env::var("XDG_CACHE_HOME")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var("HOME").ok().map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(base.join("app2nix"))
+}
+
+fn entry_path(sha256_hex: &str) -> Option<PathBuf> {
+    cache_dir().map(|dir| dir.join(format!("{}.json", sha256_hex)))
+}
+
+/// The analysis of the deb with digest `sha256_hex`, if one was stored
+/// under the same key.
+pub fn load(sha256_hex: &str, key: &CacheKey) -> Option<PackageInfo> {
+    let path = entry_path(sha256_hex)?;
+    let content = fs::read_to_string(&path).ok()?;
+    let entry: CacheEntry = match serde_json::from_str(&content) {
+        Ok(entry) => entry,
+        Err(e) => {
+            debug!("Ignoring unreadable cache entry {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    (entry.key == *key).then_some(entry.package_info)
+}
+
+/// Best effort: a cache that cannot be written only costs a rescan.
+pub fn store(sha256_hex: &str, key: &CacheKey, package_info: &PackageInfo) {
+    let Some(path) = entry_path(sha256_hex) else {
+        return;
+    };
+    let entry = json!({ "key": key, "package_info": package_info });
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, entry.to_string()));
+    match written {
+        Ok(()) => debug!("Cached analysis at {}", path.display()),
+        Err(e) => debug!("Could not write cache entry {}: {}", path.display(), e),
+    }
+}
+
+/// Every readable cached analysis, whatever its key.
+pub fn all() -> Vec<PackageInfo> {
+    let Some(entries) = cache_dir().and_then(|dir| fs::read_dir(dir).ok()) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().is_some_and(|x| x == "json"))
+        .filter_map(|e| fs::read_to_string(e.path()).ok())
+        .filter_map(|content| serde_json::from_str::<CacheEntry>(&content).ok())
+        .map(|entry| entry.package_info)
+        .collect()
+}
@@ -0,0 +1,72 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use flate2::read::MultiGzDecoder;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::structs::ChangelogEntry;
+
+/// `demo (1.2.3-1) unstable; urgency=medium`
+static HEADER: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\S+ \(([^)]+)\) ([^;]+);").unwrap());
+/// ` -- Jane Doe <jane@example.com>  Mon, 01 Jan 2024 12:00:00 +0000`
+static TRAILER: Lazy<Regex> = Lazy::new(|| Regex::new(r"^ -- (.+?)  (.+)$").unwrap());
+
+/// The changelog a Debian package ships, relative to its payload root.
+pub fn path(package: &str) -> String {
+    format!("usr/share/doc/{}/changelog.Debian.gz", package)
+}
+
+/// The newest entry of a gzipped changelog; `None` if it cannot be read.
+pub fn latest_entry_gz(bytes: &[u8]) -> Option<ChangelogEntry> {
+    let mut text = String::new();
+    MultiGzDecoder::new(bytes).read_to_string(&mut text).ok()?;
+    latest_entry(&text)
+}
+
+/// The first entry of a Debian changelog: the header line, the indented
+/// change lines and the ` -- ` trailer.
+pub fn latest_entry(text: &str) -> Option<ChangelogEntry> {
+    let mut lines = text.lines().skip_while(|l| l.trim().is_empty());
+    let header = HEADER.captures(lines.next()?)?;
+    let mut entry = ChangelogEntry {
+        version: header[1].to_string(),
+        distribution: header[2].trim().to_string(),
+        ..ChangelogEntry::default()
+    };
+    for line in lines {
+        if let Some(trailer) = TRAILER.captures(line) {
+            entry.maintainer = trailer[1].to_string();
+            entry.date = trailer[2].trim().to_string();
+            return Some(entry);
+        }
+        if !line.trim().is_empty() {
+            entry.changes.push(line.trim().to_string());
+        }
+    }
+    // An entry without its trailer is cut off, not the newest one
+    None
+}
+
+/// The newest entry of the first `changelog.Debian.gz` among `files`,
+/// payload-relative paths under `root`.
+pub fn from_tree(root: &Path, files: &[String]) -> Option<ChangelogEntry> {
+    files
+        .iter()
+        .filter(|f| f.starts_with("usr/share/doc/") && f.ends_with("/changelog.Debian.gz"))
+        .find_map(|f| latest_entry_gz(&fs::read(root.join(f)).ok()?))
+}
+
+/// One line for the log: `1.2.3-1 (unstable, <date>) by <who>: <changes>`.
+pub fn summary(entry: &ChangelogEntry) -> String {
+    let changes: Vec<&str> = entry.changes.iter().map(|c| c.trim_start_matches("* ").trim_end_matches('.')).collect();
+    format!(
+        "{} ({}, {}) by {}: {}",
+        entry.version,
+        entry.distribution,
+        entry.date,
+        entry.maintainer,
+        if changes.is_empty() { "no changes listed".to_string() } else { changes.join("; ") }
+    )
+}
@@ -0,0 +1,1376 @@
+use std::time::Duration;
+
+use crate::completions::Shell;
+use crate::configuration;
+use crate::hashing;
+use crate::logger::LogFormat;
+use crate::naming;
+use crate::readfile_nix;
+use crate::snapshot;
+use crate::structs::{
+    ArchivePackage, AudioBackend, ConflictPolicy, DisplayServer, Fetcher, GenerateOptions, Hook, OptLayout, OutputFormat, Preset, SbomFormat,
+    ScanOptions, SourcePin, UnpackStrategy,
+};
+
+pub enum CliCommand {
+    Generate(Box<CliOptions>),
+    Resolve(Vec<String>),
+    Diff { old: String, new: String },
+    Outdated(Vec<String>),
+    Verify(String),
+    Try(TryOptions),
+    Watch(WatchOptions),
+    Serve { listen: String },
+    Batch(BatchOptions),
+    Doctor,
+    ConfigLint { path: Option<String>, offline: bool },
+    Completions(Shell),
+}
+
+/// `app2nix try`: what to start in the sandbox and for how long.
+pub struct TryOptions {
+    pub target: String,
+    /// Program in `bin/` to start, if not the package's own.
+    pub bin: Option<String>,
+    /// Stop the program after this long; it runs until it exits otherwise.
+    pub timeout: Option<Duration>,
+    /// Arguments for the program, after `--`.
+    pub args: Vec<String>,
+}
+
+/// `app2nix watch`: the generate command line to run again whenever one
+/// of its files changes.
+pub struct WatchOptions {
+    /// The inputs and generate options, as they would follow `app2nix`.
+    pub args: Vec<String>,
+    /// Further generate options, read again on every change.
+    pub args_file: Option<String>,
+    /// Build the expression after each regeneration.
+    pub build: bool,
+    /// How often the files are checked.
+    pub interval: Duration,
+}
+
+/// `app2nix batch`: the manifest and how to work through it.
+pub struct BatchOptions {
+    pub manifest: String,
+    /// Packages generated at once; the number of CPUs by default.
+    pub jobs: Option<usize>,
+    /// Directory the packages' directories are created in.
+    pub output_dir: Option<String>,
+}
+
+/// Where `app2nix serve` listens without `--listen`; only local clients
+/// can reach it.
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+
+/// Subcommands and what they do, for the shell completions.
+pub const COMMANDS: &[(&str, &str)] = &[
+    ("resolve", "Print ranked nixpkgs candidates for library names"),
+    ("diff", "Compare two packages or two generated .nix files"),
+    ("outdated", "Check generated default.nix files for newer upstream versions"),
+    ("verify", "Build default.nix and check every ELF file finds its libraries"),
+    ("try", "Start the built app in a bubblewrap sandbox and diagnose failures"),
+    ("watch", "Regenerate whenever the template, config or options change"),
+    ("serve", "Answer HTTP requests for expressions of package URLs"),
+    ("batch", "Generate every package of a manifest, several at a time"),
+    ("doctor", "Check tools, nix-index, nix settings, directories and network"),
+    ("config", "Lint the libraries config (config lint)"),
+    ("completions", "Print a bash, zsh or fish completion script"),
+];
+
+/// What follows an option on the command line, with the name the usage
+/// gives it.
+pub enum OptionValue {
+    Switch,
+    Path(&'static str),
+    Text(&'static str),
+    OneOf(&'static str, &'static [&'static str]),
+}
+
+impl OptionValue {
+    fn name(&self) -> Option<&'static str> {
+        match self {
+            OptionValue::Switch => None,
+            OptionValue::Path(name) | OptionValue::Text(name) | OptionValue::OneOf(name, _) => Some(name),
+        }
+    }
+}
+
+/// The generate options: the flag, its value, a one-line description for
+/// the shell completions and the usage text, if it says more (lines
+/// wrapped by hand). The usage and the completions are built from this
+/// table; keep it in step with `parse_generate_args`.
+pub const OPTIONS: &[(&str, OptionValue, &str, &str)] = &[
+    ("--skip-deps", OptionValue::Switch, "Skip automatic dependency resolution", ""),
+    (
+        "--target-system",
+        OptionValue::OneOf("<sys>", &["x86_64-linux", "aarch64-linux", "i686-linux", "armv7l-linux", "riscv64-linux"]),
+        "Nix system to generate for",
+        "Nix system to generate for (default: host, e.g. x86_64-linux)",
+    ),
+    (
+        "--name",
+        OptionValue::Text("<name>"),
+        "Package name to use instead of the control file's",
+        "Package name to use instead of the control file's (also the wrapper name)",
+    ),
+    ("--pname", OptionValue::Text("<pname>"), "Derivation pname only", "Derivation pname only, keeping the package name for the wrapper"),
+    ("--version", OptionValue::Text("<version>"), "Version to use instead of the control file's", ""),
+    ("--force", OptionValue::Switch, "Generate even if nixpkgs already has the package", ""),
+    (
+        "--skip-nixpkgs-check",
+        OptionValue::Switch,
+        "Do not look the package up in nixpkgs",
+        "Do not look the package up in nixpkgs (needs no network)",
+    ),
+    ("--overwrite", OptionValue::Switch, "Overwrite existing output files", ""),
+    ("--backup", OptionValue::Switch, "Keep existing output files as .bak", "Keep existing output files as <file>.bak before writing"),
+    (
+        "--strict",
+        OptionValue::Switch,
+        "Fail when libraries cannot be resolved",
+        "Fail instead of warning when libraries cannot be resolved\n\
+         or the binary scan fails",
+    ),
+    (
+        "--sbom",
+        OptionValue::OneOf("<spdx|cyclonedx>", &["spdx", "cyclonedx"]),
+        "Also write an SBOM",
+        "Also write an SBOM (sbom.spdx.json / sbom.cdx.json)",
+    ),
+    (
+        "--analysis-json",
+        OptionValue::Path("<file>"),
+        "Also write the scan and resolution result as JSON",
+        "Also write the scan and resolution result (binaries, libraries,\n\
+         resolved attrs) as JSON",
+    ),
+    (
+        "--format",
+        OptionValue::OneOf("<format>", &["home-manager", "nixos-module", "docker", "flake", "vm-test"]),
+        "Also write another expression",
+        "Also write another expression; may be repeated:\n\
+         home-manager (hm-module.nix), nixos-module (module.nix),\n\
+         docker (docker.nix, an OCI image), flake (flake.nix),\n\
+         vm-test (test.nix, a nixosTest that starts the main program)",
+    ),
+    (
+        "--vm-test-seconds",
+        OptionValue::Text("<n>"),
+        "How long the VM test's program must stay up",
+        "How long the VM test's program must keep running (default: 10)",
+    ),
+    (
+        "--format-with",
+        OptionValue::OneOf("<cmd>", &["nixfmt", "alejandra"]),
+        "Formatter for the written .nix files",
+        "Run a formatter over the written .nix files (e.g. nixfmt, alejandra -q)",
+    ),
+    (
+        "--pin",
+        OptionValue::OneOf("nvfetcher", &["nvfetcher"]),
+        "Pin the source with nvfetcher",
+        "Add the source to nvfetcher.toml and read it from _sources/ in default.nix",
+    ),
+    (
+        "--fetch",
+        OptionValue::OneOf("<fetcher>", &["fetchurl", "requireFile"]),
+        "Fetcher of the src; requireFile for EULA or login-gated downloads",
+        "fetchurl (default) or requireFile, for files behind a EULA or a login:\n\
+         the user downloads the file from its URL and adds it to the store",
+    ),
+    ("--vuln-check", OptionValue::Switch, "Query OSV for known CVEs in bundled libraries", ""),
+    (
+        "--osv-db",
+        OptionValue::Path("<dir>"),
+        "Local OSV export to use instead of the API",
+        "Use a local OSV export instead of the API (implies --vuln-check)",
+    ),
+    ("--nix-ld", OptionValue::Switch, "Also write nix-ld.nix", "Also write nix-ld.nix with programs.nix-ld.libraries for the binaries"),
+    (
+        "--etc-module",
+        OptionValue::Switch,
+        "Also write etc.nix for the package's /etc files",
+        "Also write etc.nix mapping the package's /etc files to environment.etc",
+    ),
+    ("--closure-size", OptionValue::Switch, "Estimate the runtime closure size", "Estimate the runtime closure size of the buildInputs"),
+    (
+        "--dry-run",
+        OptionValue::Switch,
+        "Print the expressions instead of writing files",
+        "Print the expressions and a resolution report instead of writing files",
+    ),
+    (
+        "--seed-comment",
+        OptionValue::Switch,
+        "Head each expression with the tool version and input hash",
+        "Start every written expression with a comment naming the app2nix\n\
+         version and each input's sha256, to tell which run produced it",
+    ),
+    (
+        "--provenance",
+        OptionValue::Switch,
+        "Record how the expression was produced in a comment and passthru.app2nix",
+        "Like --seed-comment, adding the command line (credentials masked),\n\
+         nixpkgs revision and date (SOURCE_DATE_EPOCH if set); default.nix\n\
+         also gets them as passthru.app2nix",
+    ),
+    (
+        "--allow-unfree-note",
+        OptionValue::Switch,
+        "Explain NIXPKGS_ALLOW_UNFREE next to an unfree license",
+        "When the license is unfree (unknown, proprietary or missing), add a\n\
+         comment on building it with NIXPKGS_ALLOW_UNFREE=1 or allowUnfree",
+    ),
+    (
+        "--changelog-url",
+        OptionValue::Text("<url>"),
+        "Record the vendor's changelog URL as passthru.changelog",
+        "Add passthru.changelog = <url>, where the vendor lists what changed",
+    ),
+    (
+        "--review",
+        OptionValue::Switch,
+        "Review and edit dependencies before writing",
+        "Review metadata and dependencies (remap, remove, add) before writing",
+    ),
+    (
+        "--keep-baseline",
+        OptionValue::Switch,
+        "Keep unreferenced baseline buildInputs",
+        "Keep baseline buildInputs that no binary references",
+    ),
+    (
+        "--opt-layout",
+        OptionValue::OneOf("<layout>", &["flatten", "opt", "lib"]),
+        "Where opt/<vendor> is installed",
+        "Install opt/<vendor> as $out/<vendor> (flatten, default),\n\
+         $out/opt/<vendor> (opt) or $out/lib/<name> (lib)",
+    ),
+    (
+        "--unpack-strategy",
+        OptionValue::OneOf("<s>", &["tar", "dpkg"]),
+        "How the unpackPhase extracts a deb",
+        "Extract a deb with ar and tar (tar, default) or with\n\
+         dpkg-deb -x (dpkg)",
+    ),
+    ("--bin-links", OptionValue::Switch, "Symlink every installed executable into $out/bin", ""),
+    (
+        "--split-outputs",
+        OptionValue::Switch,
+        "Move shared libraries and docs to lib and doc outputs",
+        "Build out, lib and doc outputs: lib/*.so* goes to $lib,\n\
+         share/doc, share/man and share/info to $doc",
+    ),
+    (
+        "--prefer-system-libs",
+        OptionValue::Switch,
+        "Replace bundled libraries nixpkgs provides",
+        "Delete bundled libraries nixpkgs provides (e.g. libssl, libicu)\n\
+         and link against the nixpkgs builds instead",
+    ),
+    (
+        "--bundled-conflicts",
+        OptionValue::OneOf("<policy>", &["prefer-bundled", "prefer-nix", "ask"]),
+        "Which copy of a needed library both bundled and in nixpkgs is linked",
+        "For needed libraries both bundled and in nixpkgs: prefer-nix\n\
+         (default) deletes the bundled copy, prefer-bundled keeps it\n\
+         and leaves nixpkgs out, ask decides each on the terminal",
+    ),
+    (
+        "--extra-build-input",
+        OptionValue::Text("<attr>"),
+        "Add a nixpkgs attr to buildInputs",
+        "Add a nixpkgs attr to buildInputs and the wrapper's library path;\n\
+         may be repeated (e.g. ffmpeg, xdg-utils)",
+    ),
+    (
+        "--extra-native-build-input",
+        OptionValue::Text("<attr>"),
+        "Add a nixpkgs attr to nativeBuildInputs",
+        "Add a nixpkgs attr to nativeBuildInputs; may be repeated",
+    ),
+    (
+        "--preset",
+        OptionValue::OneOf("<gtk|qt5|qt6|games>", &["gtk", "qt5", "qt6", "games"]),
+        "Add the inputs such apps commonly need",
+        "Add what such apps commonly need beyond their linked libraries:\n\
+         GSettings schemas and wrapGAppsHook3, Qt plugins and\n\
+         wrapQtAppsHook, or OpenGL, Vulkan, SDL2 and sound",
+    ),
+    (
+        "--exclude-dep",
+        OptionValue::Text("<attr>"),
+        "Keep a nixpkgs attr out of buildInputs",
+        "Keep a nixpkgs attr out of buildInputs and the library path,\n\
+         even if the scan or the baseline adds it; may be repeated",
+    ),
+    (
+        "--wrapper-env",
+        OptionValue::Text("<NAME=VALUE>"),
+        "Set NAME=VALUE in the app's wrapper",
+        "Set a variable in the app's wrapper; $out and other shell\n\
+         variables are expanded at build time; may be repeated",
+    ),
+    (
+        "--post-install-file",
+        OptionValue::Path("<file>"),
+        "Append a snippet to installPhase",
+        "Append the file's shell snippet to installPhase",
+    ),
+    ("--pre-fixup-file", OptionValue::Path("<file>"), "Add a snippet to preFixup", "Add the file's snippet to preFixup"),
+    (
+        "--post-fixup-file",
+        OptionValue::Path("<file>"),
+        "Add a snippet to postFixup",
+        "Add the file's snippet to postFixup; each may be repeated and\n\
+         is spliced in as written, so ${...} is Nix interpolation",
+    ),
+    (
+        "--template",
+        OptionValue::Path("<file>"),
+        "Template to render default.nix from instead of the built-in one",
+        "Render default.nix from this template instead of the built-in one\n\
+         for the package (see templates/ for the {placeholders})",
+    ),
+    (
+        "--audio",
+        OptionValue::OneOf("<server>", &["pipewire", "pulse", "jack"]),
+        "Sound server to wire the app for",
+        "Sound server to wire the app for: pipewire, pulse or jack\n\
+         (default: guessed from the audio libraries it uses)",
+    ),
+    (
+        "--wayland",
+        OptionValue::Switch,
+        "Run Electron, GTK and Qt apps natively on Wayland",
+        "Run Electron, GTK and Qt apps natively on Wayland (X11 as fallback)",
+    ),
+    ("--x11-only", OptionValue::Switch, "Keep Electron, GTK and Qt apps on X11", "Keep Electron, GTK and Qt apps on X11 (XWayland)"),
+    (
+        "--mirror",
+        OptionValue::Text("<url>"),
+        "Another URL of the package to fall back to",
+        "Another URL of the package, tried when the download fails and\n\
+         listed in fetchurl's urls; may be repeated. One ending in /\n\
+         is a directory the file name is appended to",
+    ),
+    (
+        "--header",
+        OptionValue::Text("<header>"),
+        "Request header for the download, e.g. a bearer token",
+        "Send a request header with the download (e.g. 'Authorization:\n\
+         Bearer <token>'); may be repeated",
+    ),
+    (
+        "--user",
+        OptionValue::Text("<user[:password]>"),
+        "Basic auth user[:password] for the download",
+        "Basic auth for the download; the password defaults to\n\
+         $APP2NIX_PASSWORD, then a prompt",
+    ),
+    (
+        "--netrc",
+        OptionValue::Switch,
+        "Log in to the download with ~/.netrc",
+        "Log in to the download with the host's ~/.netrc entry.\n\
+         With any of these, fetchurl reads the credentials from\n\
+         environment variables in netrcPhase",
+    ),
+    (
+        "--presign",
+        OptionValue::Switch,
+        "Fetch s3:// and gs:// inputs through a presigned URL",
+        "Fetch s3:// and gs:// inputs through a presigned URL (valid for\n\
+         7 days) instead of requireFile",
+    ),
+    (
+        "--expected-sha256",
+        OptionValue::Text("<hash>"),
+        "SHA-256 the downloaded package must have",
+        "SHA-256 (hex or SRI) the downloaded package must have; a file\n\
+         left by an earlier run is also checked against it, or else\n\
+         against the server's Content-Length, and fetched again if it differs",
+    ),
+    (
+        "--filename",
+        OptionValue::Text("<name>"),
+        "File name for a package read from stdin",
+        "Save a package read from stdin (-) as <name> (default: stdin.<ext>,\n\
+         by the archive's magic bytes)",
+    ),
+    (
+        "--debian",
+        OptionValue::Text("<pkg>[=<ver>]"),
+        "Fetch <package>[=<version>] from snapshot.debian.org",
+        "Use this Debian binary package (newest version by default),\n\
+         pinned to its immutable snapshot.debian.org URL",
+    ),
+    (
+        "--ppa",
+        OptionValue::Text("<spec>"),
+        "Fetch ppa:<owner>/<ppa>/<package>[=<version>] from Launchpad",
+        "Use a package from a Launchpad PPA,\n\
+         ppa:<owner>/<ppa>/<package>[=<version>], pinned to its\n\
+         published URL and SHA-256",
+    ),
+    (
+        "--auto-shell",
+        OptionValue::Switch,
+        "Re-run inside nix-shell when tools are missing",
+        "Re-run inside nix-shell when nix-locate or unsquashfs are missing",
+    ),
+    (
+        "--refresh",
+        OptionValue::Switch,
+        "Rescan even if the analysis is cached",
+        "Rescan even if ~/.cache/app2nix has an analysis of this deb",
+    ),
+    (
+        "--max-scan-size",
+        OptionValue::Text("<size>"),
+        "Skip larger ELF files when scanning",
+        "Skip ELF files larger than <size> (e.g. 200M) when scanning",
+    ),
+    (
+        "--tmpdir",
+        OptionValue::Path("<dir>"),
+        "Extract below this directory",
+        "Extract below <dir> (default: $APP2NIX_TMPDIR, then $TMPDIR)",
+    ),
+    (
+        "--keep-workdir",
+        OptionValue::Switch,
+        "Keep the extracted package tree",
+        "Keep the extracted package tree for inspection, in <dir> with\n\
+         --keep-workdir=<dir>",
+    ),
+];
+
+/// Options every command takes, removed by `take_logging_flags`.
+pub const GLOBAL_OPTIONS: &[(&str, OptionValue, &str, &str)] = &[
+    ("-q", OptionValue::Switch, "Only print warnings and errors", ""),
+    (
+        "--quiet",
+        OptionValue::Switch,
+        "Only print errors and a one-line result",
+        "Only print errors, then one line (JSON with --log-format json)\n\
+         with the outcome on stdout. Without a terminal, log lines are\n\
+         undecorated: Info:/Warning:/Error: and no emoji or >>>",
+    ),
+    ("-v", OptionValue::Switch, "Print per-file detail", ""),
+    ("-vv", OptionValue::Switch, "Print everything", ""),
+    (
+        "--log-format",
+        OptionValue::OneOf("<text|json>", &["text", "json"]),
+        "Log line format on stderr",
+        "Log line format on stderr (default: text)",
+    ),
+];
+
+/// Options of the subcommands: the subcommand, the flag, its value and a
+/// one-line description.
+pub const COMMAND_OPTIONS: &[(&str, &str, OptionValue, &str)] = &[
+    ("try", "--bin", OptionValue::Text("<name>"), "Program in bin/ to start instead of the package's own"),
+    ("try", "--timeout", OptionValue::Text("<secs>"), "Stop the program after this many seconds"),
+    ("watch", "--build", OptionValue::Switch, "Build the expression after each regeneration"),
+    ("watch", "--interval", OptionValue::Text("<secs>"), "How often the files are checked (default: 1)"),
+    ("watch", "--args-file", OptionValue::Path("<file>"), "Further options, read again on every change"),
+    ("serve", "--listen", OptionValue::Text("<addr:port>"), "Address to listen on (default: 127.0.0.1:8080)"),
+    ("batch", "--jobs", OptionValue::Text("<n>"), "Packages generated at once (default: the CPU count)"),
+    ("batch", "--output-dir", OptionValue::Path("<dir>"), "Directory the packages' directories are created in"),
+    ("config", "--offline", OptionValue::Switch, "Skip the check for attrs missing from nixpkgs"),
+];
+
+pub struct CliOptions {
+    /// The package, followed by any packages merged into it.
+    pub inputs: Vec<String>,
+    pub target_system: Option<String>,
+    /// Replace the name and version read from the package.
+    pub name: Option<String>,
+    pub version: Option<String>,
+    /// Generate even if nixpkgs already has the package.
+    pub force: bool,
+    /// Replace existing output files.
+    pub overwrite: bool,
+    /// Do not look the package up in nixpkgs.
+    pub skip_nixpkgs_check: bool,
+    /// Move existing output files to `<file>.bak` instead of refusing.
+    pub backup: bool,
+    pub strict: bool,
+    pub sbom: Option<SbomFormat>,
+    /// Where to write the package analysis as JSON.
+    pub analysis_json: Option<String>,
+    pub formats: Vec<OutputFormat>,
+    /// Formatter command run over every written expression.
+    pub format_with: Option<String>,
+    pub vuln_check: bool,
+    pub osv_db: Option<String>,
+    pub closure_size: bool,
+    pub nix_ld: bool,
+    pub etc_module: bool,
+    pub refresh: bool,
+    pub auto_shell: bool,
+    /// Show the review screen before writing anything.
+    pub review: bool,
+    /// Print the expressions instead of writing any file.
+    pub dry_run: bool,
+    /// Head every expression with the tool version and the inputs' hashes.
+    pub seed_comment: bool,
+    /// Also record the command line, nixpkgs revision and date, in the
+    /// header and in `passthru.app2nix`.
+    pub provenance: bool,
+    /// The comment `--seed-comment` or `--provenance` writes, once the
+    /// inputs are hashed.
+    pub header: Option<String>,
+    /// Settles libraries both bundled and in nixpkgs.
+    pub conflict_policy: ConflictPolicy,
+    /// Further URLs of a downloaded package, tried after it and listed in
+    /// its fetchurl. One ending in `/` is a directory holding the file.
+    pub mirrors: Vec<String>,
+    /// Digests a downloaded package must match one of, from
+    /// `--expected-sha256`.
+    pub expected_sha256: Vec<Vec<u8>>,
+    /// Name the package read from stdin (`-`) is saved as.
+    pub stdin_filename: Option<String>,
+    /// Package to look up and download in place of an input.
+    pub archive_package: Option<ArchivePackage>,
+    /// Fetch object store inputs through a presigned URL rather than
+    /// `requireFile`.
+    pub presign: bool,
+    /// Snippet files for the build phases; read into `generate.hooks`.
+    pub hook_files: Vec<(Hook, String)>,
+    /// Template file for default.nix; read into `generate.template`.
+    pub template_file: Option<String>,
+    pub scan: ScanOptions,
+    pub generate: GenerateOptions,
+}
+
+pub fn print_usage(program: &str) {
+    eprintln!("Usage: {} <url_or_path>... [options]", program);
+    eprintln!("       {} --debian <package>[=<version>] [options]", program);
+    eprintln!("       {} --ppa ppa:<owner>/<ppa>/<package>[=<version>] [options]", program);
+    eprintln!("       {} resolve <soname>...", program);
+    eprintln!("       {} diff <old> <new>", program);
+    eprintln!("       {} outdated <default.nix|dir>...", program);
+    eprintln!("       {} verify <store-path|default.nix>", program);
+    eprintln!("       {} try <store-path|default.nix>{} [-- <args>...]", program, command_options("try"));
+    eprintln!("       {} watch <url_or_path>...{} [options]", program, command_options("watch"));
+    eprintln!("       {} serve{}", program, command_options("serve"));
+    eprintln!("       {} batch <manifest.toml>{}", program, command_options("batch"));
+    eprintln!("       {} doctor", program);
+    eprintln!("       {} config lint{} [<libraries.json|.toml>]", program, command_options("config"));
+    eprintln!("       {} completions <bash|zsh|fish>", program);
+    eprintln!();
+    eprintln!("Commands:");
+    eprintln!("  resolve <soname>...      Print ranked nixpkgs candidates for library names");
+    eprintln!("  diff <old> <new>         Compare two packages or two generated .nix files");
+    eprintln!("  outdated <path>...       Report generated default.nix files (or every one below a");
+    eprintln!("                           directory) with a newer upstream version, from the source");
+    eprintln!("                           their updateScript polls or else Repology");
+    eprintln!("  verify <path>            Build default.nix (or take a store path) and run ldd on every");
+    eprintln!("                           ELF file, reporting libraries not found or from /usr/lib");
+    eprintln!("  try <path>               Start the built program in a bubblewrap sandbox with a scratch");
+    eprintln!("                           HOME and suggest fixes for the failures its stderr shows");
+    eprintln!("  watch <url_or_path>...   Generate, then generate again whenever the --template, phase");
+    eprintln!("                           snippets, libraries config, local inputs or --args-file (more");
+    eprintln!("                           options, one or more per line) change; the scan is reused from");
+    eprintln!("                           the cache. --build runs nix-build after each regeneration");
+    eprintln!("  serve                    Serve an HTTP API (default: 127.0.0.1:8080): POST /generate with");
+    eprintln!("                           {{\"url\": \"https://...\", \"args\": [...]}} returns the expression,");
+    eprintln!("                           the analysis JSON and the log; options reading local files or");
+    eprintln!("                           running commands are refused. GET /health answers ok");
+    eprintln!("  batch <manifest.toml>    Generate each [[package]] (input or inputs, dir, args; top-level args");
+    eprintln!("                           apply to all) in <output-dir>/<dir>, --jobs at a time (default:");
+    eprintln!("                           the CPU count), keeping each run's log in its app2nix.log");
+    eprintln!("  doctor                   Check the tools, nix-index database, experimental features,");
+    eprintln!("                           cache directories, network and libraries config, with fixes");
+    eprintln!("  config lint [<file>]     Check libraries.toml/.json: schema, duplicate and shadowed entries,");
+    eprintln!("                           attrs missing from nixpkgs (skipped with --offline) and");
+    eprintln!("                           mappings no cached scan needed");
+    eprintln!("  completions <shell>      Print a bash, zsh or fish completion script");
+    eprintln!();
+    eprintln!("Arguments:");
+    eprintln!("  <url_or_path>            URL or local path of a .deb, Arch .pkg.tar.zst, Alpine .apk or .snap");
+    eprintln!("                           or of a Debian source .dsc, for a build-from-source skeleton.");
+    eprintln!("                           - reads the package from stdin, e.g. piped from curl.");
+    eprintln!("                           A directory (an installed /opt tree, an extracted AppImage) is");
+    eprintln!("                           scanned as it is and copied with builtins.path.");
+    eprintln!("                           s3://bucket/key and gs://bucket/object are downloaded with the");
+    eprintln!("                           aws and gcloud CLIs and fetched with requireFile (or --presign).");
+    eprintln!("                           Further packages (e.g. an app's -data and -libs debs) are");
+    eprintln!("                           merged into the first one's derivation; one package per");
+    eprintln!("                           architecture (amd64 and arm64 debs) gives a per-system src");
+    eprintln!();
+    eprintln!("Options:");
+    print_options(OPTIONS);
+    eprintln!();
+    eprintln!("Global options:");
+    print_options(GLOBAL_OPTIONS);
+    eprintln!();
+    eprintln!("Environment:");
+    for (name, effect) in configuration::ENV_VARS {
+        eprintln!("  {:<24} {}", name, effect);
+    }
+    eprintln!();
+    eprintln!("Exit codes:");
+    eprintln!("  0                        Success");
+    eprintln!("  1                        Invalid command line");
+    eprintln!("  2                        Bad input (unsupported format, not found, wrong architecture, in nixpkgs)");
+    eprintln!("  3                        Download failed");
+    eprintln!("  4                        Package could not be extracted or read");
+    eprintln!("  5                        Libraries could not be resolved (resolve, --strict)");
+    eprintln!("  6                        Writing the expression or SBOM failed");
+    eprintln!();
+    eprintln!("Examples:");
+    eprintln!("  {} https://example.com/package.deb", program);
+    eprintln!("  {} /home/user/downloads/package.deb", program);
+    eprintln!("  {} ./package.deb --skip-deps", program);
+    eprintln!("  {} ./package_arm64.deb --target-system aarch64-linux", program);
+    eprintln!("  {} ./package-1.0-1-x86_64.pkg.tar.zst", program);
+    eprintln!("  {} ./package_1.0_amd64.snap", program);
+    eprintln!("  curl -fsSL https://example.com/package.deb | {} - --filename package.deb", program);
+    eprintln!("  {} ./app_1.0_amd64.deb ./app-data_1.0_all.deb ./app-libs_1.0_amd64.deb", program);
+    eprintln!("  {} https://deb.debian.org/debian/pool/main/h/hello/hello_2.10-3.dsc", program);
+    eprintln!("  {} resolve libgbm.so.1 libnss3.so", program);
+    eprintln!("  {} diff app_1.0_amd64.deb app_1.1_amd64.deb", program);
+}
+
+/// The options of subcommand `command` as the usage shows them:
+/// ` [--jobs <n>] [--output-dir <dir>]`.
+fn command_options(command: &str) -> String {
+    COMMAND_OPTIONS
+        .iter()
+        .filter(|(name, _, _, _)| *name == command)
+        .map(|(_, flag, value, _)| match value.name() {
+            Some(name) => format!(" [{} {}]", flag, name),
+            None => format!(" [{}]", flag),
+        })
+        .collect()
+}
+
+/// Prints `options` in two columns; a flag and value too wide for the
+/// first gets a line of its own.
+fn print_options(options: &[(&str, OptionValue, &str, &str)]) {
+    for (flag, value, summary, help) in options {
+        let synopsis = match value.name() {
+            Some(name) => format!("{} {}", flag, name),
+            None => flag.to_string(),
+        };
+        let mut lines = if help.is_empty() { summary.lines() } else { help.lines() };
+        if synopsis.len() > 24 {
+            eprintln!("  {}", synopsis);
+        } else {
+            eprintln!("  {:<24} {}", synopsis, lines.next().unwrap_or_default());
+        }
+        for line in lines {
+            eprintln!("  {:<24} {}", "", line);
+        }
+    }
+}
+
+/// Returns the value of `--flag value` or `--flag=value`, advancing `i`.
+fn flag_value(args: &[String], i: &mut usize, flag: &str) -> Result<Option<String>, String> {
+    let arg = &args[*i];
+    if let Some(value) = arg.strip_prefix(&format!("{}=", flag)) {
+        return Ok(Some(value.to_string()));
+    }
+    if arg != flag {
+        return Ok(None);
+    }
+    *i += 1;
+    args.get(*i)
+        .cloned()
+        .map(Some)
+        .ok_or_else(|| format!("{} requires a value", flag))
+}
+
+/// Parses a byte count with an optional binary suffix: `512K`, `200M`, `1G`.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let trimmed = value.trim().trim_end_matches(['B', 'b']).trim_end_matches('i');
+    let (digits, multiplier) = match trimmed.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&trimmed[..trimmed.len() - 1], 1u64 << 10),
+        Some('M') => (&trimmed[..trimmed.len() - 1], 1 << 20),
+        Some('G') => (&trimmed[..trimmed.len() - 1], 1 << 30),
+        _ => (trimmed, 1),
+    };
+    digits
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("Invalid size: {} (expected e.g. 512K, 200M, 1G)", value))
+}
+
+/// Whether `arg` is an option whose value is the next argument.
+fn takes_value(arg: &str) -> bool {
+    OPTIONS.iter().any(|(flag, value, _, _)| *flag == arg && value.name().is_some())
+        || COMMAND_OPTIONS.iter().any(|(_, flag, value, _)| *flag == arg && value.name().is_some())
+}
+
+/// Removes the global logging flags from `args`, up to a `--`, and returns
+/// the net verbosity and the log format. Values of other options (`--name
+/// -v`) and arguments after `--` (`app2nix try app -- -v`) are left alone.
+pub fn take_logging_flags(args: &mut Vec<String>) -> Result<(i32, LogFormat), String> {
+    let mut verbosity = 0;
+    let mut format = match configuration::env_setting("APP2NIX_LOG_FORMAT").as_deref() {
+        None | Some("text") => LogFormat::Text,
+        Some("json") => LogFormat::Json,
+        Some(other) => return Err(format!("Unknown APP2NIX_LOG_FORMAT: {} (expected text or json)", other)),
+    };
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-q" => verbosity -= 1,
+            "--quiet" => verbosity -= 2,
+            "-v" => verbosity += 1,
+            "-vv" => verbosity += 2,
+            "--" => break,
+            arg if arg != "--log-format" && takes_value(arg) => {
+                i += 2;
+                continue;
+            }
+            _ => {
+                let start = i;
+                let Some(value) = flag_value(args, &mut i, "--log-format")? else {
+                    i += 1;
+                    continue;
+                };
+                format = match value.as_str() {
+                    "text" => LogFormat::Text,
+                    "json" => LogFormat::Json,
+                    other => return Err(format!("Unknown log format: {} (expected text or json)", other)),
+                };
+                args.drain(start..=i);
+                i = start;
+                continue;
+            }
+        }
+        args.remove(i);
+    }
+
+    Ok((verbosity, format))
+}
+
+/// A nixpkgs attr given on the command line, with any `pkgs.` prefix dropped.
+pub fn attr_value(flag: &str, value: String) -> Result<String, String> {
+    let attr = value.trim().trim_start_matches("pkgs.");
+    if !naming::is_attr_path(attr) {
+        return Err(format!("{} expects a nixpkgs attribute such as ffmpeg or xorg.libXtst (got: {})", flag, value));
+    }
+    Ok(attr.to_string())
+}
+
+/// A `NAME=VALUE` wrapper variable.
+pub fn env_value(flag: &str, value: &str) -> Result<(String, String), String> {
+    let (name, value) = value
+        .split_once('=')
+        .filter(|(name, _)| {
+            name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+        .ok_or_else(|| format!("{} expects NAME=VALUE with a shell variable name (got: {})", flag, value))?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+pub fn parse_args(args: &[String]) -> Result<CliCommand, String> {
+    if args.get(1).map(String::as_str) == Some("resolve") {
+        let libs = args[2..].to_vec();
+        if libs.is_empty() {
+            return Err("resolve requires at least one library name".to_string());
+        }
+        return Ok(CliCommand::Resolve(libs));
+    }
+    if args.get(1).map(String::as_str) == Some("diff") {
+        return match &args[2..] {
+            [old, new] => Ok(CliCommand::Diff {
+                old: old.clone(),
+                new: new.clone(),
+            }),
+            _ => Err("diff requires exactly two inputs".to_string()),
+        };
+    }
+
+    if args.get(1).map(String::as_str) == Some("outdated") {
+        let paths = args[2..].to_vec();
+        if paths.is_empty() {
+            return Err("outdated requires at least one default.nix or directory".to_string());
+        }
+        return Ok(CliCommand::Outdated(paths));
+    }
+
+    if args.get(1).map(String::as_str) == Some("verify") {
+        return match &args[2..] {
+            [target] => Ok(CliCommand::Verify(target.clone())),
+            _ => Err("verify requires one store path or default.nix".to_string()),
+        };
+    }
+
+    if args.get(1).map(String::as_str) == Some("try") {
+        return parse_try_args(args).map(CliCommand::Try);
+    }
+
+    if args.get(1).map(String::as_str) == Some("watch") {
+        return parse_watch_args(args).map(CliCommand::Watch);
+    }
+
+    if args.get(1).map(String::as_str) == Some("serve") {
+        return match args.get(2..).unwrap_or_default() {
+            [] => Ok(CliCommand::Serve { listen: DEFAULT_LISTEN.to_string() }),
+            [flag, listen] if flag == "--listen" => Ok(CliCommand::Serve { listen: listen.clone() }),
+            _ => Err("serve takes only --listen <addr:port>".to_string()),
+        };
+    }
+
+    if args.get(1).map(String::as_str) == Some("batch") {
+        return parse_batch_args(args).map(CliCommand::Batch);
+    }
+
+    if args.get(1).map(String::as_str) == Some("doctor") {
+        return match args.len() {
+            2 => Ok(CliCommand::Doctor),
+            _ => Err("doctor takes no arguments".to_string()),
+        };
+    }
+
+    if args.get(1).map(String::as_str) == Some("config") {
+        if args.get(2).map(String::as_str) != Some("lint") {
+            return Err("config requires a subcommand: lint".to_string());
+        }
+        let offline = args[3..].iter().any(|a| a == "--offline");
+        return match args[3..].iter().filter(|a| *a != "--offline").collect::<Vec<_>>().as_slice() {
+            [] => Ok(CliCommand::ConfigLint { path: None, offline }),
+            [path] if !path.starts_with("--") => Ok(CliCommand::ConfigLint {
+                path: Some(path.to_string()),
+                offline,
+            }),
+            _ => Err("config lint takes --offline and at most one file".to_string()),
+        };
+    }
+
+    if args.get(1).map(String::as_str) == Some("completions") {
+        return match args.get(2..).unwrap_or_default() {
+            [shell] => Ok(CliCommand::Completions(match shell.as_str() {
+                "bash" => Shell::Bash,
+                "zsh" => Shell::Zsh,
+                "fish" => Shell::Fish,
+                other => return Err(format!("Unknown shell: {} (expected bash, zsh or fish)", other)),
+            })),
+            _ => Err("completions requires a shell: bash, zsh or fish".to_string()),
+        };
+    }
+
+    parse_generate_args(args).map(|options| CliCommand::Generate(Box::new(options)))
+}
+
+fn parse_try_args(args: &[String]) -> Result<TryOptions, String> {
+    let mut target = None;
+    let mut bin = None;
+    let mut timeout = None;
+    let mut i = 2;
+    while i < args.len() {
+        let arg = &args[i];
+        if arg == "--" {
+            break;
+        } else if let Some(value) = flag_value(args, &mut i, "--bin")? {
+            bin = Some(value);
+        } else if let Some(value) = flag_value(args, &mut i, "--timeout")? {
+            let seconds = value
+                .parse::<u64>()
+                .map_err(|_| format!("--timeout expects a number of seconds (got: {})", value))?;
+            timeout = Some(Duration::from_secs(seconds));
+        } else if arg.starts_with("--") {
+            return Err(format!("Unknown option for try: {}", arg));
+        } else if target.is_none() {
+            target = Some(arg.clone());
+        } else {
+            return Err("try takes one store path or default.nix; pass program arguments after --".to_string());
+        }
+        i += 1;
+    }
+    Ok(TryOptions {
+        target: target.ok_or("try requires a store path or default.nix")?,
+        bin,
+        timeout,
+        args: args.get(i + 1..).unwrap_or_default().to_vec(),
+    })
+}
+
+fn parse_batch_args(args: &[String]) -> Result<BatchOptions, String> {
+    let mut manifest = None;
+    let mut jobs = None;
+    let mut output_dir = None;
+    let mut i = 2;
+    while i < args.len() {
+        let arg = &args[i];
+        if let Some(value) = flag_value(args, &mut i, "--jobs")? {
+            jobs = Some(
+                value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format!("--jobs expects a positive number (got: {})", value))?,
+            );
+        } else if let Some(value) = flag_value(args, &mut i, "--output-dir")? {
+            output_dir = Some(value);
+        } else if arg.starts_with("--") {
+            return Err(format!("Unknown option for batch: {}", arg));
+        } else if manifest.is_none() {
+            manifest = Some(arg.clone());
+        } else {
+            return Err("batch takes one manifest".to_string());
+        }
+        i += 1;
+    }
+    Ok(BatchOptions {
+        manifest: manifest.ok_or("batch requires a manifest")?,
+        jobs,
+        output_dir,
+    })
+}
+
+fn parse_watch_args(args: &[String]) -> Result<WatchOptions, String> {
+    let mut generate_args = Vec::new();
+    let mut args_file = None;
+    let mut build = false;
+    let mut interval = Duration::from_secs(1);
+    let mut i = 2;
+    while i < args.len() {
+        if args[i] == "--build" {
+            build = true;
+        } else if let Some(value) = flag_value(args, &mut i, "--interval")? {
+            let seconds = value
+                .parse::<u64>()
+                .ok()
+                .filter(|s| *s > 0)
+                .ok_or_else(|| format!("--interval expects a positive number of seconds (got: {})", value))?;
+            interval = Duration::from_secs(seconds);
+        } else if let Some(value) = flag_value(args, &mut i, "--args-file")? {
+            args_file = Some(value);
+        } else {
+            generate_args.push(args[i].clone());
+        }
+        i += 1;
+    }
+    // Mistakes on the command line are reported now rather than on every change
+    let command_line: Vec<String> = args[..1].iter().chain(&generate_args).cloned().collect();
+    let options = parse_generate_args(&command_line)?;
+    if options.inputs.iter().any(|i| i == "-") {
+        return Err("watch cannot read its package from stdin".to_string());
+    }
+    Ok(WatchOptions {
+        args: generate_args,
+        args_file,
+        build,
+        interval,
+    })
+}
+
+fn parse_generate_args(args: &[String]) -> Result<CliOptions, String> {
+    let mut inputs = Vec::new();
+    let mut target_system = None;
+    let mut name = None;
+    let mut version = None;
+    let mut force = false;
+    let mut overwrite = false;
+    let mut skip_nixpkgs_check = configuration::skip_nixpkgs_check();
+    let mut backup = false;
+    let mut strict = false;
+    let mut sbom = None;
+    let mut analysis_json = None;
+    let mut formats = Vec::new();
+    let mut hook_files = Vec::new();
+    let mut template_file = None;
+    let mut mirrors = Vec::new();
+    let mut presign = false;
+    let mut expected_sha256 = Vec::new();
+    let mut stdin_filename = None;
+    let mut archive_package = None;
+    let mut format_with = None;
+    let mut vuln_check = false;
+    let mut osv_db = None;
+    let mut closure_size = false;
+    let mut nix_ld = false;
+    let mut etc_module = false;
+    let mut refresh = false;
+    let mut auto_shell = false;
+    let mut review = false;
+    let mut dry_run = false;
+    let mut seed_comment = false;
+    let mut provenance = false;
+    let mut conflict_policy = ConflictPolicy::default();
+    let mut scan = ScanOptions::default();
+    let mut generate = GenerateOptions::default();
+
+    let mut i = 1;
+    while i < args.len() {
+        let arg = &args[i];
+        if arg == "--skip-deps" {
+            scan.skip_deps = true;
+        } else if arg == "--force" {
+            force = true;
+        } else if arg == "--overwrite" {
+            overwrite = true;
+        } else if arg == "--skip-nixpkgs-check" {
+            skip_nixpkgs_check = true;
+        } else if arg == "--backup" {
+            backup = true;
+        } else if arg == "--strict" {
+            strict = true;
+        } else if arg == "--vuln-check" {
+            vuln_check = true;
+        } else if arg == "--closure-size" {
+            closure_size = true;
+        } else if arg == "--nix-ld" {
+            nix_ld = true;
+        } else if arg == "--etc-module" {
+            etc_module = true;
+        } else if arg == "--refresh" {
+            refresh = true;
+        } else if arg == "--auto-shell" {
+            auto_shell = true;
+        } else if arg == "--review" {
+            review = true;
+        } else if arg == "--dry-run" {
+            dry_run = true;
+        } else if arg == "--seed-comment" {
+            seed_comment = true;
+        } else if arg == "--provenance" {
+            provenance = true;
+        } else if arg == "--presign" {
+            presign = true;
+        } else if arg == "--netrc" {
+            generate.auth.netrc = true;
+        } else if arg == "--keep-baseline" {
+            generate.keep_baseline = true;
+        } else if arg == "--allow-unfree-note" {
+            generate.unfree_note = true;
+        } else if arg == "--bin-links" {
+            generate.bin_links = true;
+        } else if arg == "--split-outputs" {
+            generate.split_outputs = true;
+        } else if arg == "--prefer-system-libs" {
+            generate.prefer_system_libs = true;
+        } else if arg == "--wayland" || arg == "--x11-only" {
+            let display = if arg == "--wayland" { DisplayServer::Wayland } else { DisplayServer::X11 };
+            if generate.display.is_some_and(|d| d != display) {
+                return Err("--wayland and --x11-only are mutually exclusive".to_string());
+            }
+            generate.display = Some(display);
+        } else if arg == "--keep-workdir" {
+            scan.keep_workdir = true;
+        } else if let Some(size) = flag_value(args, &mut i, "--max-scan-size")? {
+            scan.max_scan_size = Some(parse_size(&size)?);
+        } else if let Some(dir) = flag_value(args, &mut i, "--tmpdir")? {
+            scan.tmpdir = Some(dir);
+        } else if let Some(dir) = arg.strip_prefix("--keep-workdir=") {
+            if dir.is_empty() {
+                return Err("--keep-workdir= requires a directory".to_string());
+            }
+            scan.keep_workdir = true;
+            scan.workdir = Some(dir.to_string());
+        } else if let Some(value) = flag_value(args, &mut i, "--osv-db")? {
+            vuln_check = true;
+            osv_db = Some(value);
+        } else if let Some(value) = flag_value(args, &mut i, "--target-system")? {
+            target_system = Some(value);
+        } else if let Some(value) = flag_value(args, &mut i, "--name")? {
+            name = Some(value);
+        } else if let Some(value) = flag_value(args, &mut i, "--pname")? {
+            generate.pname = Some(value);
+        } else if let Some(value) = flag_value(args, &mut i, "--version")? {
+            version = Some(value);
+        } else if let Some(value) = flag_value(args, &mut i, "--opt-layout")? {
+            generate.opt_layout = match value.as_str() {
+                "flatten" => OptLayout::Flatten,
+                "opt" => OptLayout::Opt,
+                "lib" => OptLayout::Lib,
+                other => return Err(format!("Unknown opt layout: {} (expected flatten, opt or lib)", other)),
+            };
+        } else if let Some(value) = flag_value(args, &mut i, "--unpack-strategy")? {
+            generate.unpack_strategy = match value.as_str() {
+                "tar" => UnpackStrategy::Tar,
+                "dpkg" => UnpackStrategy::Dpkg,
+                other => return Err(format!("Unknown unpack strategy: {} (expected tar or dpkg)", other)),
+            };
+        } else if let Some(value) = flag_value(args, &mut i, "--audio")? {
+            generate.audio = Some(match value.as_str() {
+                "pipewire" => AudioBackend::Pipewire,
+                "pulse" | "pulseaudio" => AudioBackend::Pulse,
+                "jack" => AudioBackend::Jack,
+                other => return Err(format!("Unknown audio server: {} (expected pipewire, pulse or jack)", other)),
+            });
+        } else if let Some(value) = flag_value(args, &mut i, "--vm-test-seconds")? {
+            generate.vm_test_seconds = Some(
+                value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| format!("--vm-test-seconds expects a positive number of seconds (got: {})", value))?,
+            );
+        } else if let Some(value) = flag_value(args, &mut i, "--format-with")? {
+            format_with = Some(value);
+        } else if let Some(value) = flag_value(args, &mut i, "--format")? {
+            let format = match value.as_str() {
+                "home-manager" | "hm" => OutputFormat::HomeManager,
+                "nixos-module" | "nixos" => OutputFormat::NixosModule,
+                "docker" | "oci" => OutputFormat::Docker,
+                "flake" => OutputFormat::Flake,
+                "vm-test" | "nixos-test" => OutputFormat::VmTest,
+                other => {
+                    return Err(format!(
+                        "Unknown output format: {} (expected home-manager, nixos-module, docker, flake or vm-test)",
+                        other
+                    ));
+                }
+            };
+            if !formats.contains(&format) {
+                formats.push(format);
+            }
+        } else if let Some(value) = flag_value(args, &mut i, "--extra-build-input")? {
+            generate.extra_build_inputs.push(attr_value("--extra-build-input", value)?);
+        } else if let Some(value) = flag_value(args, &mut i, "--extra-native-build-input")? {
+            generate.extra_native_build_inputs.push(attr_value("--extra-native-build-input", value)?);
+        } else if let Some(value) = flag_value(args, &mut i, "--preset")? {
+            generate.preset = Some(
+                Preset::from_name(&value)
+                    .ok_or_else(|| format!("Unknown preset: {} (expected gtk, qt5, qt6 or games)", value))?,
+            );
+        } else if let Some(value) = flag_value(args, &mut i, "--exclude-dep")? {
+            generate.exclude_deps.push(attr_value("--exclude-dep", value)?);
+        } else if let Some(value) = flag_value(args, &mut i, "--wrapper-env")? {
+            let (name, value) = env_value("--wrapper-env", &value)?;
+            generate.wrapper_env.retain(|(n, _)| *n != name);
+            generate.wrapper_env.push((name, value));
+        } else if let Some(value) = flag_value(args, &mut i, "--bundled-conflicts")? {
+            conflict_policy = match value.as_str() {
+                "prefer-bundled" => ConflictPolicy::PreferBundled,
+                "prefer-nix" => ConflictPolicy::PreferNix,
+                "ask" => ConflictPolicy::Ask,
+                other => {
+                    return Err(format!(
+                        "Unknown conflict policy: {} (expected prefer-bundled, prefer-nix or ask)",
+                        other
+                    ));
+                }
+            };
+        } else if let Some(value) = flag_value(args, &mut i, "--post-install-file")? {
+            hook_files.push((Hook::PostInstall, value));
+        } else if let Some(value) = flag_value(args, &mut i, "--pre-fixup-file")? {
+            hook_files.push((Hook::PreFixup, value));
+        } else if let Some(value) = flag_value(args, &mut i, "--post-fixup-file")? {
+            hook_files.push((Hook::PostFixup, value));
+        } else if let Some(value) = flag_value(args, &mut i, "--template")? {
+            template_file = Some(value);
+        } else if let Some(value) = flag_value(args, &mut i, "--mirror")? {
+            if !["http://", "https://", "ftp://"].iter().any(|scheme| value.starts_with(scheme)) {
+                return Err(format!("--mirror expects an http(s) or ftp URL (got: {})", value));
+            }
+            mirrors.push(value);
+        } else if let Some(value) = flag_value(args, &mut i, "--header")? {
+            let name = value.split_once(':').map_or("", |(name, _)| name.trim());
+            if name.is_empty() {
+                return Err(format!("--header expects 'Name: value' (got: {})", value));
+            }
+            // An RFC 7230 token, as it ends up in a curl config line of the
+            // expression
+            if !name.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)) {
+                return Err(format!("--header name is not a valid HTTP header name (got: {})", name));
+            }
+            generate.auth.headers.push(value);
+        } else if let Some(value) = flag_value(args, &mut i, "--user")? {
+            if value.is_empty() || value.starts_with(':') {
+                return Err("--user expects user or user:password".to_string());
+            }
+            generate.auth.user = Some(value);
+        } else if let Some(value) = flag_value(args, &mut i, "--filename")? {
+            if value.contains('/') || readfile_nix::detect_package_type(&value).is_none() {
+                return Err(format!(
+                    "--filename expects a .deb, .pkg.tar.{{zst,xz}}, .apk or .snap file name (got: {})",
+                    value
+                ));
+            }
+            stdin_filename = Some(value);
+        } else if let Some(value) = flag_value(args, &mut i, "--debian")? {
+            archive_package = Some(snapshot::parse_debian(&value)?);
+        } else if let Some(value) = flag_value(args, &mut i, "--ppa")? {
+            archive_package = Some(snapshot::parse_ppa(&value)?);
+        } else if let Some(value) = flag_value(args, &mut i, "--expected-sha256")? {
+            let digest = hashing::parse_sha256(value.trim())
+                .ok_or_else(|| format!("--expected-sha256 expects a hex or sha256-<base64> hash (got: {})", value))?;
+            expected_sha256.push(digest);
+        } else if let Some(value) = flag_value(args, &mut i, "--pin")? {
+            generate.pin = Some(match value.as_str() {
+                "nvfetcher" => SourcePin::Nvfetcher,
+                "npins" => {
+                    return Err("npins pins git trees and tarballs it unpacks, not package files; use --pin nvfetcher"
+                        .to_string());
+                }
+                other => return Err(format!("Unknown pinning tool: {} (expected nvfetcher)", other)),
+            });
+        } else if let Some(value) = flag_value(args, &mut i, "--changelog-url")? {
+            generate.changelog_url = Some(value);
+        } else if let Some(value) = flag_value(args, &mut i, "--fetch")? {
+            generate.fetcher = match value.as_str() {
+                "fetchurl" => Fetcher::Fetchurl,
+                "requireFile" => Fetcher::RequireFile,
+                other => return Err(format!("Unknown fetcher: {} (expected fetchurl or requireFile)", other)),
+            };
+        } else if let Some(value) = flag_value(args, &mut i, "--analysis-json")? {
+            analysis_json = Some(value);
+        } else if let Some(value) = flag_value(args, &mut i, "--sbom")? {
+            sbom = Some(match value.as_str() {
+                "spdx" => SbomFormat::Spdx,
+                "cyclonedx" | "cdx" => SbomFormat::CycloneDx,
+                other => return Err(format!("Unknown SBOM format: {} (expected spdx or cyclonedx)", other)),
+            });
+        } else if arg.starts_with("--") {
+            return Err(format!("Unknown option: {}", arg));
+        } else {
+            inputs.push(arg.clone());
+        }
+        i += 1;
+    }
+
+    if scan.tmpdir.is_none() {
+        scan.tmpdir = configuration::env_setting("APP2NIX_TMPDIR");
+    }
+    if target_system.is_none() {
+        target_system = configuration::env_setting("APP2NIX_TARGET_SYSTEM");
+    }
+
+    if archive_package.is_some() && !inputs.is_empty() {
+        return Err("--debian and --ppa take the place of <url_or_path>".to_string());
+    }
+    if inputs.is_empty() && archive_package.is_none() {
+        return Err("Missing <url_or_path> argument".to_string());
+    }
+    if generate.fetcher == Fetcher::RequireFile && generate.pin.is_some() {
+        return Err("--fetch requireFile and --pin both replace the src; use one".to_string());
+    }
+    if strict && scan.skip_deps {
+        return Err("--strict checks the resolved libraries, which --skip-deps leaves out".to_string());
+    }
+
+    Ok(CliOptions {
+        inputs,
+        target_system,
+        name,
+        version,
+        force,
+        overwrite,
+        skip_nixpkgs_check,
+        backup,
+        strict,
+        sbom,
+        analysis_json,
+        formats,
+        format_with,
+        vuln_check,
+        osv_db,
+        closure_size,
+        nix_ld,
+        etc_module,
+        refresh,
+        auto_shell,
+        review,
+        dry_run,
+        seed_comment,
+        provenance,
+        header: None,
+        conflict_policy,
+        mirrors,
+        expected_sha256,
+        stdin_filename,
+        archive_package,
+        presign,
+        hook_files,
+        template_file,
+        scan,
+        generate,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split(' ').map(String::from).collect()
+    }
+
+    #[test]
+    fn takes_logging_flags_anywhere_before_a_double_dash() {
+        let mut line = args("app2nix -v app.deb --quiet --log-format=json --strict -vv");
+        let (verbosity, format) = take_logging_flags(&mut line).unwrap();
+        assert_eq!(verbosity, 1);
+        assert!(matches!(format, LogFormat::Json));
+        assert_eq!(line, args("app2nix app.deb --strict"));
+    }
+
+    /// A value the option's parser accepts, as far as its type tells.
+    fn sample(value: &OptionValue) -> Option<&'static str> {
+        match value {
+            OptionValue::Switch => None,
+            OptionValue::Path(_) => Some("file"),
+            OptionValue::Text(_) => Some("1"),
+            OptionValue::OneOf(_, values) => Some(values[0]),
+        }
+    }
+
+    #[test]
+    fn accepts_only_token_header_names() {
+        let header = |value: &str| {
+            let mut line = args("app2nix app.deb --header");
+            line.push(value.to_string());
+            parse_args(&line).map(|_| ())
+        };
+        assert!(header("Authorization: Bearer x y").is_ok());
+        assert!(header("X-Api_Key.1: ${v}").is_ok());
+        assert!(header("Bad Name: x").is_err());
+        assert!(header("X\"$(id)\": x").is_err());
+        assert!(header(": x").is_err());
+    }
+
+    #[test]
+    fn parses_every_listed_generate_option() {
+        for (flag, value, _, _) in OPTIONS {
+            let mut line = args("app2nix app.deb");
+            line.push(flag.to_string());
+            line.extend(sample(value).map(String::from));
+            if let Err(error) = parse_args(&line) {
+                assert!(!error.starts_with("Unknown option"), "{}: {}", flag, error);
+            }
+        }
+    }
+
+    #[test]
+    fn parses_every_listed_command_option() {
+        for (command, flag, value, _) in COMMAND_OPTIONS {
+            let mut line = args(match *command {
+                "config" => "app2nix config lint",
+                "serve" => "app2nix serve",
+                _ => "app2nix",
+            });
+            if line.len() == 1 {
+                line.extend([command.to_string(), "app.deb".to_string()]);
+            }
+            line.push(flag.to_string());
+            line.extend(sample(value).map(String::from));
+            assert!(parse_args(&line).is_ok(), "{} {}", command, flag);
+        }
+    }
+
+    #[test]
+    fn leaves_option_values_and_program_arguments_alone() {
+        let mut line = args("app2nix app.deb --name -v --jobs -q -q");
+        assert_eq!(take_logging_flags(&mut line).unwrap().0, -1);
+        assert_eq!(line, args("app2nix app.deb --name -v --jobs -q"));
+
+        let mut line = args("app2nix try app.deb -v -- -v --log-format json");
+        assert_eq!(take_logging_flags(&mut line).unwrap().0, 1);
+        assert_eq!(line, args("app2nix try app.deb -- -v --log-format json"));
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::process::Command;
+
+use log::info;
+use serde_json::Value;
+
+use crate::configuration;
+
+const BINARY_CACHE: &str = "https://cache.nixos.org";
+const HEAVIEST_SHOWN: usize = 5;
+
+pub struct ClosureEstimate {
+    pub total_bytes: u64,
+    pub path_count: usize,
+    /// Top-level store paths with their own closure size, largest first.
+    pub heaviest: Vec<(String, u64)>,
+}
+
+/// `nix path-info --json` is an array of objects in older Nix and an object
+/// keyed by store path in newer ones; normalize to (path, info) pairs.
+fn path_infos(json: Value) -> Vec<(String, Value)> {
+    match json {
+        Value::Array(entries) => entries
+            .into_iter()
+            .filter_map(|e| Some((e.get("path")?.as_str()?.to_string(), e)))
+            .collect(),
+        Value::Object(map) => map.into_iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Evaluates the output paths of `attrs` in one go, so nothing is built and
+/// the paths can be queried from the binary cache.
+fn out_paths(attrs: &[String], system: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let attr_list = attrs
+        .iter()
+        .map(|a| format!("\"{}\"", a))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let apply = format!(
+        "pkgs: map (a: (pkgs.lib.getAttrFromPath (pkgs.lib.splitString \".\" a) pkgs).outPath) [ {} ]",
+        attr_list
+    );
+    let output = Command::new("nix")
+        .args(["eval", "--json", &format!("{}#legacyPackages.{}", configuration::nixpkgs_flake(), system), "--apply", &apply])
+        .env("NIX_CONFIG", "experimental-features = nix-command flakes")
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("nix eval failed: {}", String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+fn nix_path_info(extra_args: &[&str], installables: &[String]) -> Result<Vec<(String, Value)>, Box<dyn Error>> {
+    let output = Command::new("nix")
+        .args(["path-info", "--json", "--store", BINARY_CACHE])
+        .args(extra_args)
+        .args(installables)
+        .env("NIX_CONFIG", "experimental-features = nix-command flakes")
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("nix path-info failed: {}", String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(path_infos(serde_json::from_slice(&output.stdout)?))
+}
+
+fn size_of(info: &Value, field: &str) -> u64 {
+    info.get(field).and_then(|v| v.as_u64()).unwrap_or(0)
+}
+
+/// Sizes the runtime closure of `attrs` as substituted from the binary
+/// cache, without building or downloading anything.
+pub fn estimate(attrs: &[String], system: &str) -> Result<ClosureEstimate, Box<dyn Error>> {
+    let installables = out_paths(attrs, system)?;
+
+    // Shared dependencies are only counted once in the union of closures
+    let closure: HashMap<String, u64> = nix_path_info(&["--recursive"], &installables)?
+        .into_iter()
+        .map(|(path, info)| (path, size_of(&info, "narSize")))
+        .collect();
+
+    let mut heaviest: Vec<(String, u64)> = nix_path_info(&["--closure-size"], &installables)?
+        .into_iter()
+        .map(|(path, info)| (path, size_of(&info, "closureSize")))
+        .collect();
+    heaviest.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    heaviest.truncate(HEAVIEST_SHOWN);
+
+    Ok(ClosureEstimate {
+        total_bytes: closure.values().sum(),
+        path_count: closure.len(),
+        heaviest,
+    })
+}
+
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+pub fn print_report(estimate: &ClosureEstimate) {
+    info!(
+        "Estimated runtime closure: {} ({} store paths)",
+        human_size(estimate.total_bytes),
+        estimate.path_count
+    );
+    info!("Heaviest buildInputs:");
+    for (path, size) in &estimate.heaviest {
+        info!("{:>10}  {}", human_size(*size), path);
+    }
+}
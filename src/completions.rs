@@ -0,0 +1,234 @@
+use crate::cli::{COMMAND_OPTIONS, COMMANDS, GLOBAL_OPTIONS, OPTIONS, OptionValue};
+
+/// Shells `app2nix completions` writes a script for.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+const SHELLS: &str = "bash zsh fish";
+
+/// The completion script for `shell`, built from the subcommand and option
+/// tables in `cli`.
+pub fn script(shell: Shell) -> String {
+    match shell {
+        Shell::Bash => bash(),
+        Shell::Zsh => zsh(),
+        Shell::Fish => fish(),
+    }
+}
+
+fn command_names() -> String {
+    COMMANDS.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(" ")
+}
+
+/// `watch` passes the generate options on to each run.
+const PASSES_OPTIONS: &str = "watch";
+
+/// The generate and global options: flag, value and description.
+fn generate_options() -> impl Iterator<Item = (&'static str, &'static OptionValue, &'static str)> {
+    OPTIONS
+        .iter()
+        .chain(GLOBAL_OPTIONS)
+        .map(|(flag, value, description, _)| (*flag, value, *description))
+}
+
+/// The options of subcommand `command`.
+fn own_options(command: &str) -> impl Iterator<Item = (&'static str, &'static OptionValue, &'static str)> {
+    COMMAND_OPTIONS
+        .iter()
+        .filter(move |(name, _, _, _)| *name == command)
+        .map(|(_, flag, value, description)| (*flag, value, *description))
+}
+
+/// Subcommands with options of their own, in table order.
+fn option_commands() -> Vec<&'static str> {
+    let mut commands: Vec<&str> = Vec::new();
+    for (name, _, _, _) in COMMAND_OPTIONS {
+        if !commands.contains(name) {
+            commands.push(name);
+        }
+    }
+    commands
+}
+
+/// Quotes `text` for a single-quoted shell word.
+fn single_quoted(text: &str) -> String {
+    text.replace('\'', "'\\''")
+}
+
+fn bash() -> String {
+    let flags = |options: &mut dyn Iterator<Item = (&str, &OptionValue, &str)>| {
+        options.map(|(flag, _, _)| flag).collect::<Vec<_>>().join(" ")
+    };
+    let value_cases: String = generate_options()
+        .chain(COMMAND_OPTIONS.iter().map(|(_, flag, value, description)| (*flag, value, *description)))
+        .filter_map(|(flag, value, _)| {
+            let reply = match value {
+                OptionValue::Switch => return None,
+                OptionValue::Path(_) => "\n            COMPREPLY=($(compgen -f -- \"$cur\"))".to_string(),
+                OptionValue::Text(_) => String::new(),
+                OptionValue::OneOf(_, values) => {
+                    format!("\n            COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", values.join(" "))
+                }
+            };
+            Some(format!("        {}){}\n            return ;;\n", flag, reply))
+        })
+        .collect();
+    let command_cases: String = option_commands()
+        .into_iter()
+        .map(|command| {
+            let generate = if command == PASSES_OPTIONS { " $flags" } else { "" };
+            format!("        {})\n            flags=\"{}{}\" ;;\n", command, flags(&mut own_options(command)), generate)
+        })
+        .collect();
+    format!(
+        r#"# bash completion for app2nix; load with: source <(app2nix completions bash)
+_app2nix() {{
+    COMPREPLY=()
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    local flags="{flags}"
+
+    case "$prev" in
+{value_cases}    esac
+
+    case "${{COMP_WORDS[1]}}" in
+        completions)
+            [ "$COMP_CWORD" -eq 2 ] && COMPREPLY=($(compgen -W "{shells}" -- "$cur"))
+            return ;;
+        resolve)
+            return ;;
+{command_cases}    esac
+
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "$flags" -- "$cur"))
+    else
+        COMPREPLY=($(compgen -f -- "$cur"))
+        if [ "$COMP_CWORD" -eq 1 ]; then
+            COMPREPLY+=($(compgen -W "{commands}" -- "$cur"))
+        fi
+    fi
+}}
+complete -o filenames -F _app2nix app2nix
+"#,
+        value_cases = value_cases,
+        command_cases = command_cases,
+        shells = SHELLS,
+        flags = flags(&mut generate_options()),
+        commands = command_names(),
+    )
+}
+
+fn zsh() -> String {
+    let zsh_quoted = |text: &str| single_quoted(text).replace('[', "\\[").replace(']', "\\]");
+    let specs = |options: &mut dyn Iterator<Item = (&str, &OptionValue, &str)>, indent: &str| -> String {
+        options
+            .map(|(flag, value, description)| {
+                let action = match value {
+                    OptionValue::Switch => String::new(),
+                    OptionValue::Path(_) => ":path:_files".to_string(),
+                    OptionValue::Text(_) => ":value: ".to_string(),
+                    OptionValue::OneOf(_, values) => format!(":value:({})", values.join(" ")),
+                };
+                format!("{}'{}[{}]{}' \\\n", indent, flag, zsh_quoted(description), action)
+            })
+            .collect()
+    };
+    let commands: String = COMMANDS
+        .iter()
+        .map(|(name, description)| format!("    '{}:{}'\n", name, single_quoted(description)))
+        .collect();
+    let command_cases: String = option_commands()
+        .into_iter()
+        .map(|command| {
+            let mut own = specs(&mut own_options(command), "        ");
+            if command == PASSES_OPTIONS {
+                own.push_str(&specs(&mut generate_options(), "        "));
+            }
+            format!("    {})\n      _arguments -s \\\n{}        '*:file:_files'\n      return ;;\n", command, own)
+        })
+        .collect();
+    format!(
+        r#"#compdef app2nix
+# zsh completion for app2nix; save as _app2nix in a directory on $fpath
+
+_app2nix() {{
+  local -a commands
+  commands=(
+{commands}  )
+
+  case $words[2] in
+    completions)
+      (( CURRENT == 3 )) && _values 'shell' {shells}
+      return ;;
+    resolve)
+      return ;;
+{command_cases}  esac
+
+  _arguments -s \
+{specs}    '*:package:->inputs'
+
+  if [[ $state == inputs ]]; then
+    (( CURRENT == 2 )) && _describe 'command' commands
+    _files
+  fi
+}}
+
+_app2nix "$@"
+"#,
+        commands = commands,
+        shells = SHELLS,
+        command_cases = command_cases,
+        specs = specs(&mut generate_options(), "    "),
+    )
+}
+
+fn fish() -> String {
+    let mut lines = vec![
+        "# fish completion for app2nix; save as ~/.config/fish/completions/app2nix.fish".to_string(),
+        format!(
+            "complete -c app2nix -n '__fish_seen_subcommand_from completions' -f -a '{}'",
+            SHELLS
+        ),
+        "complete -c app2nix -n '__fish_seen_subcommand_from resolve' -f".to_string(),
+    ];
+    for (name, description) in COMMANDS {
+        lines.push(format!(
+            "complete -c app2nix -n __fish_use_subcommand -a {} -d '{}'",
+            name,
+            single_quoted(description)
+        ));
+    }
+    let option = |flag: &str, value: &OptionValue, description: &str, condition: String| {
+        let name = match flag.strip_prefix("--") {
+            Some(long) => format!("-l {}", long),
+            None => format!("-s {}", flag.trim_start_matches('-')),
+        };
+        let takes = match value {
+            OptionValue::Switch => String::new(),
+            OptionValue::Path(_) => " -r".to_string(),
+            OptionValue::Text(_) => " -x".to_string(),
+            OptionValue::OneOf(_, values) => format!(" -x -a '{}'", values.join(" ")),
+        };
+        format!(
+            "complete -c app2nix{} {}{} -d '{}'",
+            condition,
+            name,
+            takes,
+            single_quoted(description)
+        )
+    };
+    for (flag, value, description) in generate_options() {
+        lines.push(option(flag, value, description, String::new()));
+    }
+    for command in option_commands() {
+        for (flag, value, description) in own_options(command) {
+            let condition = format!(" -n '__fish_seen_subcommand_from {}'", command);
+            lines.push(option(flag, value, description, condition));
+        }
+    }
+    format!("{}\n", lines.join("\n"))
+}
@@ -0,0 +1,424 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::process::Command;
+
+use log::{info, warn};
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+
+use crate::cache;
+use crate::cli;
+use crate::configuration;
+use crate::errors::AppError;
+use crate::naming;
+use crate::structs::Preset;
+
+/// Top-level keys of the libraries config and whether they must be present.
+const KEYS: [(&str, bool); 7] = [
+    ("system_libs", true),
+    ("lib_to_pkg_map", true),
+    ("python_modules", false),
+    ("debian_packages", false),
+    ("ranking", false),
+    ("overrides", false),
+    ("hooks", false),
+];
+
+/// A JSON or TOML value with object entries in file order, duplicates kept
+/// (JSON only; TOML refuses them), which serde's maps would silently
+/// collapse to the last one.
+enum Node {
+    Object(Vec<(String, Node)>),
+    Array(Vec<Node>),
+    String(String),
+    Other(&'static str),
+}
+
+impl Node {
+    fn kind(&self) -> &'static str {
+        match self {
+            Node::Object(_) => "an object",
+            Node::Array(_) => "an array",
+            Node::String(_) => "a string",
+            Node::Other(kind) => kind,
+        }
+    }
+}
+
+struct NodeVisitor;
+
+impl<'de> Visitor<'de> for NodeVisitor {
+    type Value = Node;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any value")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Node, A::Error> {
+        let mut entries = Vec::new();
+        while let Some((key, value)) = map.next_entry::<String, Node>()? {
+            entries.push((key, value));
+        }
+        Ok(Node::Object(entries))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Node, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element::<Node>()? {
+            items.push(item);
+        }
+        Ok(Node::Array(items))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Node, E> {
+        Ok(Node::String(value.to_string()))
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<Node, E> {
+        Ok(Node::Other("a boolean"))
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<Node, E> {
+        Ok(Node::Other("a number"))
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<Node, E> {
+        Ok(Node::Other("a number"))
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<Node, E> {
+        Ok(Node::Other("a number"))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Node, E> {
+        Ok(Node::Other("null"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Node, D::Error> {
+        deserializer.deserialize_any(NodeVisitor)
+    }
+}
+
+#[derive(Default)]
+struct Findings {
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+/// The string entries of `key`'s object, checking its shape and reporting
+/// keys listed more than once (the last one wins when loaded).
+fn string_map(key: &str, node: &Node, findings: &mut Findings) -> Vec<(String, String)> {
+    let Node::Object(entries) = node else {
+        findings.errors.push(format!("{} must be an object, not {}", key, node.kind()));
+        return Vec::new();
+    };
+    let mut map = Vec::new();
+    let mut seen: HashMap<&str, &str> = HashMap::new();
+    for (name, value) in entries {
+        let Node::String(value) = value else {
+            findings.errors.push(format!("{}.{} must be a string, not {}", key, name, value.kind()));
+            continue;
+        };
+        if let Some(earlier) = seen.insert(name, value) {
+            let message = format!("{}.{} is listed twice ({}, then {}); the last one wins", key, name, earlier, value);
+            if earlier == value {
+                findings.warnings.push(message);
+            } else {
+                findings.errors.push(message);
+            }
+        }
+        map.push((name.clone(), value.clone()));
+    }
+    map
+}
+
+/// The strings of the `key` array, checking its shape.
+fn string_list(key: &str, node: &Node, findings: &mut Findings) -> Vec<String> {
+    let Node::Array(items) = node else {
+        findings.errors.push(format!("{} must be an array, not {}", key, node.kind()));
+        return Vec::new();
+    };
+    let mut list = Vec::new();
+    for item in items {
+        match item {
+            Node::String(value) => list.push(value.clone()),
+            other => findings.errors.push(format!("{} entries must be strings, not {}", key, other.kind())),
+        }
+    }
+    list
+}
+
+/// Checks an attr names something plausible in nixpkgs; `label` says
+/// where it was found.
+fn check_attr(label: &str, attr: &str, findings: &mut Findings) {
+    if attr.starts_with("pkgs.") {
+        findings.warnings.push(format!(
+            "{}: {} starts with pkgs., which is dropped when the config is loaded",
+            label, attr
+        ));
+    }
+    if !naming::is_attr_path(configuration::without_pkgs_prefix(attr)) {
+        findings.errors.push(format!("{}: {:?} is not a nixpkgs attribute path", label, attr));
+    }
+}
+
+/// Checks each mapped attr names something plausible in nixpkgs.
+fn check_attrs(key: &str, map: &[(String, String)], findings: &mut Findings) {
+    for (name, attr) in map {
+        check_attr(&format!("{}.{}", key, name), attr, findings);
+    }
+}
+
+/// Which of `attrs` are missing from nixpkgs, or `None` if nix cannot
+/// evaluate it.
+fn missing_attrs(attrs: &[String]) -> Option<Vec<String>> {
+    let list = attrs.iter().map(|a| format!("\"{}\"", a)).collect::<Vec<_>>().join(" ");
+    let apply = format!(
+        "pkgs: builtins.filter (a: !(pkgs.lib.hasAttrByPath (pkgs.lib.splitString \".\" a) pkgs)) [ {} ]",
+        list
+    );
+    let system = format!("{}-linux", std::env::consts::ARCH);
+    let output = Command::new("nix")
+        .args(["eval", "--json", &format!("{}#legacyPackages.{}", configuration::nixpkgs_flake(), system), "--apply", &apply])
+        .env("NIX_CONFIG", "experimental-features = nix-command flakes")
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+    serde_json::from_slice(&output.stdout).ok()
+}
+
+/// `app2nix config lint [--offline] [<libraries.json|libraries.toml>]`:
+/// checks the libraries config's schema, duplicate and shadowed entries,
+/// that mapped attrs exist in nixpkgs (unless `offline`), and which
+/// mappings no cached scan has needed.
+pub fn run_lint_command(path: Option<&str>, offline: bool) -> Result<(), AppError> {
+    let path = path.map_or_else(configuration::get_config_path, str::to_string);
+    let content = fs::read_to_string(&path).map_err(|e| AppError::Input(format!("Failed to read {}: {}", path, e)))?;
+    let root: Node = if configuration::is_toml(&path) {
+        toml::from_str(&content).map_err(|e| AppError::Input(format!("{} is not valid TOML: {}", path, e)))?
+    } else {
+        serde_json::from_str(&content).map_err(|e| AppError::Input(format!("{} is not valid JSON: {}", path, e)))?
+    };
+    let Node::Object(sections) = root else {
+        return Err(AppError::Input(format!("{} must hold an object, not {}", path, root.kind())));
+    };
+
+    let mut findings = Findings::default();
+    for (key, _) in &sections {
+        if !KEYS.iter().any(|(known, _)| known == key) {
+            findings.warnings.push(format!("unknown key {} is ignored", key));
+        }
+    }
+    for (key, required) in KEYS {
+        if required && !sections.iter().any(|(k, _)| k == key) {
+            findings.errors.push(format!("{} is missing", key));
+        }
+    }
+    let section = |key: &str| sections.iter().rev().find(|(k, _)| k == key).map(|(_, node)| node);
+
+    let mut system_libs = BTreeSet::new();
+    match section("system_libs") {
+        Some(Node::Array(items)) => {
+            for item in items {
+                match item {
+                    Node::String(lib) if !system_libs.insert(lib.clone()) => {
+                        findings.warnings.push(format!("system_libs lists {} twice", lib));
+                    }
+                    Node::String(lib) if !lib.contains(".so") => {
+                        findings.warnings.push(format!("system_libs: {} does not look like a soname", lib));
+                    }
+                    Node::String(_) => {}
+                    other => findings.errors.push(format!("system_libs entries must be strings, not {}", other.kind())),
+                }
+            }
+        }
+        Some(other) => findings.errors.push(format!("system_libs must be an array, not {}", other.kind())),
+        None => {}
+    }
+
+    let libs = section("lib_to_pkg_map").map(|n| string_map("lib_to_pkg_map", n, &mut findings)).unwrap_or_default();
+    let python = section("python_modules").map(|n| string_map("python_modules", n, &mut findings)).unwrap_or_default();
+    let debian = section("debian_packages").map(|n| string_map("debian_packages", n, &mut findings)).unwrap_or_default();
+    check_attrs("lib_to_pkg_map", &libs, &mut findings);
+    check_attrs("python_modules", &python, &mut findings);
+    check_attrs("debian_packages", &debian, &mut findings);
+
+    let mut ranked = Vec::new();
+    match section("ranking") {
+        Some(Node::Object(entries)) => {
+            for (key, node) in entries {
+                let name = format!("ranking.{}", key);
+                if key != "prefer" && key != "avoid" {
+                    findings.warnings.push(format!("unknown key {} is ignored", name));
+                    continue;
+                }
+                for attr in string_list(&name, node, &mut findings) {
+                    check_attr(&name, &attr, &mut findings);
+                    ranked.push(attr);
+                }
+            }
+        }
+        Some(other) => findings.errors.push(format!("ranking must be an object, not {}", other.kind())),
+        None => {}
+    }
+
+    let mut overridden = Vec::new();
+    match section("overrides") {
+        Some(Node::Object(packages)) => {
+            for (package, node) in packages {
+                let Node::Object(entries) = node else {
+                    findings.errors.push(format!("overrides.{} must be an object, not {}", package, node.kind()));
+                    continue;
+                };
+                for (key, node) in entries {
+                    let name = format!("overrides.{}.{}", package, key);
+                    match key.as_str() {
+                        "lib_to_pkg_map" => {
+                            let map = string_map(&name, node, &mut findings);
+                            check_attrs(&name, &map, &mut findings);
+                            overridden.extend(map);
+                        }
+                        "extra_build_inputs" | "extra_native_build_inputs" | "exclude_deps" => {
+                            for attr in string_list(&name, node, &mut findings) {
+                                check_attr(&name, &attr, &mut findings);
+                                overridden.push((name.clone(), attr));
+                            }
+                        }
+                        "env" => {
+                            for (variable, value) in string_map(&name, node, &mut findings) {
+                                if let Err(e) = cli::env_value(&name, &format!("{}={}", variable, value)) {
+                                    findings.errors.push(e);
+                                }
+                            }
+                        }
+                        "preset" => match node {
+                            Node::String(preset) if Preset::from_name(preset).is_none() => findings
+                                .errors
+                                .push(format!("{}: unknown preset {} (expected gtk, qt5, qt6 or games)", name, preset)),
+                            Node::String(_) => {}
+                            other => findings.errors.push(format!("{} must be a string, not {}", name, other.kind())),
+                        },
+                        _ => findings.warnings.push(format!("unknown key {} is ignored", name)),
+                    }
+                }
+            }
+        }
+        Some(other) => findings.errors.push(format!("overrides must be an object, not {}", other.kind())),
+        None => {}
+    }
+    for (lib, attr) in &libs {
+        if !lib.contains(".so") {
+            findings.warnings.push(format!("lib_to_pkg_map: {} does not look like a soname", lib));
+        }
+        // System libraries are skipped before the map is consulted
+        if system_libs.contains(lib) {
+            findings.warnings.push(format!(
+                "lib_to_pkg_map.{} -> {} is never used: {} is also in system_libs",
+                lib, attr, lib
+            ));
+        }
+    }
+
+    match section("hooks") {
+        Some(Node::Object(entries)) => {
+            for (stage, node) in entries {
+                let name = format!("hooks.{}", stage);
+                if !["post_extract", "deps", "render"].contains(&stage.as_str()) {
+                    findings.warnings.push(format!("unknown key {} is ignored", name));
+                    continue;
+                }
+                for command in string_list(&name, node, &mut findings) {
+                    match command.split_whitespace().next() {
+                        None => findings.errors.push(format!("{} lists an empty command", name)),
+                        Some(program) if !crate::has_tool(program) => {
+                            findings.warnings.push(format!("{}: {} is not on PATH", name, program));
+                        }
+                        Some(_) => {}
+                    }
+                }
+            }
+        }
+        Some(other) => findings.errors.push(format!("hooks must be an object, not {}", other.kind())),
+        None => {}
+    }
+
+    if offline {
+        info!("Skipping the nixpkgs attribute check (--offline).");
+    } else {
+        let mut attrs: Vec<String> = libs
+            .iter()
+            .chain(&debian)
+            .chain(&overridden)
+            .map(|(_, attr)| attr)
+            .chain(&ranked)
+            .map(|attr| configuration::without_pkgs_prefix(attr).to_string())
+            .chain(python.iter().map(|(_, attr)| format!("python3Packages.{}", configuration::without_pkgs_prefix(attr))))
+            .filter(|attr| naming::is_attr_path(attr))
+            .collect();
+        attrs.sort();
+        attrs.dedup();
+        match missing_attrs(&attrs) {
+            Some(missing) => {
+                for attr in missing {
+                    let users: Vec<&str> = libs
+                        .iter()
+                        .chain(&debian)
+                        .chain(&overridden)
+                        .filter(|(_, a)| configuration::without_pkgs_prefix(a) == attr)
+                        .chain(python.iter().filter(|(_, a)| format!("python3Packages.{}", configuration::without_pkgs_prefix(a)) == attr))
+                        .map(|(name, _)| name.as_str())
+                        .collect();
+                    if users.is_empty() {
+                        findings.errors.push(format!("{} is not in nixpkgs (listed in ranking)", attr));
+                    } else {
+                        findings.errors.push(format!("{} is not in nixpkgs (mapped from {})", attr, users.join(", ")));
+                    }
+                }
+            }
+            None => warn!("nix could not evaluate nixpkgs; mapped attributes were not checked (or pass --offline)."),
+        }
+    }
+
+    // Only scans on this machine are known; an entry may serve others
+    let scans = cache::all();
+    if scans.is_empty() {
+        info!("No cached scans to look for unused entries in.");
+    } else {
+        let used: BTreeSet<&String> =
+            scans.iter().flat_map(|s| s.needed_libs.iter().chain(&s.referenced_libs)).collect();
+        let unused: Vec<&str> =
+            libs.iter().filter(|(lib, _)| !used.contains(lib)).map(|(lib, _)| lib.as_str()).collect();
+        if !unused.is_empty() {
+            findings.warnings.push(format!(
+                "{} lib_to_pkg_map mapping(s) not needed by any of the {} cached scans: {}",
+                unused.len(),
+                scans.len(),
+                unused.join(", ")
+            ));
+        }
+    }
+
+    report(&path, findings)
+}
+
+/// Prints the findings; any error fails the lint.
+fn report(path: &str, findings: Findings) -> Result<(), AppError> {
+    for error in &findings.errors {
+        println!("{}: error: {}", path, error);
+    }
+    for warning in &findings.warnings {
+        println!("{}: warning: {}", path, warning);
+    }
+    if !findings.errors.is_empty() {
+        return Err(AppError::Input(format!(
+            "{} has {} error(s) and {} warning(s)",
+            path,
+            findings.errors.len(),
+            findings.warnings.len()
+        )));
+    }
+    info!("{} is valid ({} warning(s)).", path, findings.warnings.len());
+    Ok(())
+}
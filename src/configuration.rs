@@ -1,44 +1,134 @@
+use std::env;
 use std::sync::OnceLock;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::error::Error;
 use std::fs;
 
-use serde_json;
+use log::{debug, warn};
 
-use crate::structs::{LibrariesConfig};
+use crate::structs::{LibrariesConfig, PackageOverride, PluginHooks, RankingPreferences};
 
 pub static LIBRARIES_CONFIG: OnceLock<LibrariesConfig> = OnceLock::new();
 
 pub const LIBRARIES_JSON_PATH: &str = "libraries.json";
+/// Takes precedence over libraries.json in the same directory.
+pub const LIBRARIES_TOML_PATH: &str = "libraries.toml";
 
 
-fn get_config_path() -> String {
-    let paths = [
-        LIBRARIES_JSON_PATH.to_string(),
-        format!("../{}", LIBRARIES_JSON_PATH),
-        format!("{}/{}", env!("CARGO_MANIFEST_DIR"), LIBRARIES_JSON_PATH),
-    ];
+/// Environment variables standing in for flags and settings, for CI jobs
+/// where argv or dotfiles are awkward to change; a flag given as well wins.
+pub const ENV_VARS: &[(&str, &str)] = &[
+    ("APP2NIX_CONFIG", "libraries config file, instead of searching for libraries.toml/.json"),
+    ("APP2NIX_CACHE_DIR", "scan cache directory (default: $XDG_CACHE_HOME/app2nix)"),
+    ("APP2NIX_NIXPKGS", "flake searched and evaluated for nixpkgs attributes (default: nixpkgs)"),
+    ("APP2NIX_NON_INTERACTIVE", "never prompt: --review fails, --bundled-conflicts ask prefers nixpkgs"),
+    ("APP2NIX_TARGET_SYSTEM", "default for --target-system"),
+    ("APP2NIX_LOG_FORMAT", "default for --log-format"),
+    ("APP2NIX_TMPDIR", "default for --tmpdir"),
+    ("APP2NIX_PASSWORD", "password for --user"),
+    ("APP2NIX_SKIP_NIXPKGS_CHECK", "skip the nixpkgs lookup, as --skip-nixpkgs-check does (offline and CI runs)"),
+];
 
-    for path in &paths {
-        if Path::new(path).exists() {
-            return path.clone();
+/// The value of `name`, unless unset or empty.
+pub fn env_setting(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+/// Flake reference app2nix's own `nix search` and `nix eval` calls use.
+pub fn nixpkgs_flake() -> String {
+    env_setting("APP2NIX_NIXPKGS").unwrap_or_else(|| "nixpkgs".to_string())
+}
+
+/// `name` is set to anything but 0, false or no.
+fn env_switch(name: &str) -> bool {
+    env_setting(name).is_some_and(|v| !matches!(v.to_ascii_lowercase().as_str(), "0" | "false" | "no"))
+}
+
+/// `APP2NIX_NON_INTERACTIVE` is set to anything but 0, false or no.
+pub fn non_interactive() -> bool {
+    env_switch("APP2NIX_NON_INTERACTIVE")
+}
+
+/// `APP2NIX_SKIP_NIXPKGS_CHECK` is set to anything but 0, false or no.
+pub fn skip_nixpkgs_check() -> bool {
+    env_switch("APP2NIX_SKIP_NIXPKGS_CHECK")
+}
+
+pub fn get_config_path() -> String {
+    if let Some(path) = env_setting("APP2NIX_CONFIG") {
+        return path;
+    }
+    let dirs = [".".to_string(), "..".to_string(), env!("CARGO_MANIFEST_DIR").to_string()];
+
+    for dir in &dirs {
+        for file in [LIBRARIES_TOML_PATH, LIBRARIES_JSON_PATH] {
+            let path = if dir == "." { file.to_string() } else { format!("{}/{}", dir, file) };
+            if Path::new(&path).exists() {
+                return path;
+            }
         }
     }
 
     LIBRARIES_JSON_PATH.to_string()
 }
 
+/// The config in use as an absolute path, for app2nix processes started
+/// in another directory; `None` if there is no config file.
+pub fn absolute_config_path() -> Option<PathBuf> {
+    fs::canonicalize(get_config_path()).ok()
+}
+
+/// Whether `path` is read as TOML rather than JSON.
+pub fn is_toml(path: &str) -> bool {
+    path.ends_with(".toml")
+}
+
 pub fn load_libraries_config() -> Result<LibrariesConfig, Box<dyn Error>> {
     let config_path = get_config_path();
+    debug!("Loading libraries config from {}", config_path);
     let content = fs::read_to_string(&config_path)
         .map_err(|e| format!("Failed to read {}: {}", config_path, e))?;
 
-    let config: LibrariesConfig = serde_json::from_str(&content)
-        .map_err(|e| format!("Failed to parse {}: {}", config_path, e))?;
+    let mut config: LibrariesConfig = if is_toml(&config_path) {
+        toml::from_str(&content).map_err(|e| format!("Failed to parse {}: {}", config_path, e))?
+    } else {
+        serde_json::from_str(&content).map_err(|e| format!("Failed to parse {}: {}", config_path, e))?
+    };
+    strip_pkgs_prefixes(&mut config);
 
     Ok(config)
 }
 
+/// `attr` without a leading `pkgs.`, which the generated expression adds
+/// itself.
+pub fn without_pkgs_prefix(attr: &str) -> &str {
+    attr.strip_prefix("pkgs.").unwrap_or(attr)
+}
+
+/// Drops a leading `pkgs.` from every attr in `config`, so
+/// `pkgs.xorg.libX11` does not come out as `pkgs.pkgs.xorg.libX11` (or
+/// `pkgsi686Linux.pkgs.xorg.libX11`).
+fn strip_pkgs_prefixes(config: &mut LibrariesConfig) {
+    let strip = |attr: &mut String| {
+        if let Some(stripped) = attr.strip_prefix("pkgs.") {
+            *attr = stripped.to_string();
+        }
+    };
+    config.lib_to_pkg_map.values_mut().for_each(strip);
+    config.python_modules.values_mut().for_each(strip);
+    config.debian_packages.values_mut().for_each(strip);
+    config.ranking.prefer.iter_mut().chain(&mut config.ranking.avoid).for_each(strip);
+    for package in config.overrides.values_mut() {
+        package.lib_to_pkg_map.values_mut().for_each(strip);
+        package
+            .extra_build_inputs
+            .iter_mut()
+            .chain(&mut package.extra_native_build_inputs)
+            .chain(&mut package.exclude_deps)
+            .for_each(strip);
+    }
+}
+
 pub fn is_system_lib(lib_name: &str) -> bool {
     get_libraries_config().system_libs.contains(&lib_name.to_string())
 }
@@ -47,10 +137,32 @@ pub fn get_pkg_for_lib(lib_name: &str) -> Option<&'static String> {
     get_libraries_config().lib_to_pkg_map.get(lib_name)
 }
 
+pub fn get_python_module_pkg(module: &str) -> Option<&'static String> {
+    get_libraries_config().python_modules.get(module)
+}
+
+pub fn get_debian_package_pkg(package: &str) -> Option<&'static String> {
+    get_libraries_config().debian_packages.get(package)
+}
+
+pub fn get_ranking() -> &'static RankingPreferences {
+    &get_libraries_config().ranking
+}
+
+/// The overrides for a package, looked up by its name as the package
+/// spells it.
+pub fn get_package_override(name: &str) -> Option<&'static PackageOverride> {
+    get_libraries_config().overrides.get(name)
+}
+
+pub fn get_hooks() -> &'static PluginHooks {
+    &get_libraries_config().hooks
+}
+
 fn get_libraries_config() -> &'static LibrariesConfig {
     LIBRARIES_CONFIG.get_or_init(|| {
         load_libraries_config().unwrap_or_else(|e| {
-            eprintln!("Warning: Failed to load libraries config: {}. Using defaults.", e);
+            warn!("Failed to load libraries config: {}. Using defaults.", e);
             LibrariesConfig {
                 system_libs: vec![
                     "libc.so.6".to_string(),
@@ -63,8 +175,17 @@ fn get_libraries_config() -> &'static LibrariesConfig {
                     "ld-linux-x86-64.so.2".to_string(),
                     "libgcc_s.so.1".to_string(),
                     "libstdc++.so.6".to_string(),
+                    "libc.musl-x86_64.so.1".to_string(),
+                    "libc.musl-aarch64.so.1".to_string(),
+                    "libc.musl-armv7.so.1".to_string(),
+                    "libc.musl-x86.so.1".to_string(),
                 ],
                 lib_to_pkg_map: std::collections::HashMap::new(),
+                python_modules: std::collections::HashMap::new(),
+                debian_packages: std::collections::HashMap::new(),
+                ranking: RankingPreferences::default(),
+                overrides: std::collections::HashMap::new(),
+                hooks: PluginHooks::default(),
             }
         })
     })
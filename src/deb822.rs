@@ -0,0 +1,132 @@
+/// One paragraph of a deb822 file (a deb's `control`, a `.dsc`): its
+/// fields in file order.
+#[derive(Debug, Default)]
+pub struct Paragraph {
+    fields: Vec<(String, String)>,
+}
+
+impl Paragraph {
+    /// The value of field `name`, matched case-insensitively: the rest of
+    /// its first line, then its continuation lines without their leading
+    /// space, joined with `\n`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of a folded field (`Depends`, `Build-Depends`), its lines
+    /// joined into one.
+    pub fn folded(&self, name: &str) -> Option<String> {
+        self.get(name).map(|value| value.split_whitespace().collect::<Vec<_>>().join(" "))
+    }
+}
+
+/// Parses `content` into its blank-line separated paragraphs, skipping `#`
+/// comment lines. A line that is neither a field nor a continuation is an
+/// error naming its line number.
+pub fn parse(content: &str) -> Result<Vec<Paragraph>, String> {
+    let mut paragraphs = Vec::new();
+    let mut current = Paragraph::default();
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            if !current.fields.is_empty() {
+                paragraphs.push(std::mem::take(&mut current));
+            }
+        } else if line.starts_with('#') {
+            continue;
+        } else if let Some(continuation) = line.strip_prefix([' ', '\t']) {
+            let (_, value) = current
+                .fields
+                .last_mut()
+                .ok_or_else(|| format!("line {}: continuation line before any field", number + 1))?;
+            value.push('\n');
+            value.push_str(continuation.trim_end());
+        } else {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| format!("line {}: expected a `Field: value` line, got {:?}", number + 1, line))?;
+            if name.contains(char::is_whitespace) || name.is_empty() {
+                return Err(format!("line {}: malformed field name {:?}", number + 1, name));
+            }
+            if current.get(name).is_some() {
+                return Err(format!("line {}: duplicate field {}", number + 1, name));
+            }
+            current.fields.push((name.to_string(), value.trim().to_string()));
+        }
+    }
+    if !current.fields.is_empty() {
+        paragraphs.push(current);
+    }
+    Ok(paragraphs)
+}
+
+/// Package names of a relationship field (`Depends`, `Build-Depends`),
+/// taking the first of each set of alternatives and dropping version,
+/// architecture and profile qualifiers.
+pub fn relationship_names(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .filter_map(|dep| {
+            let first = dep.split('|').next()?.trim();
+            let name = first.split([' ', '(', '[', '<', ':']).next()?.trim();
+            (!name.is_empty()).then(|| name.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_continuation_lines_and_skips_comments() {
+        let paragraphs = parse(
+            "Package: foo\n# a comment\nDescription: short\n first line\n  indented\nVersion: 1.0\n",
+        )
+        .unwrap();
+        assert_eq!(paragraphs.len(), 1);
+        assert_eq!(paragraphs[0].get("description"), Some("short\nfirst line\n indented"));
+        assert_eq!(paragraphs[0].get("VERSION"), Some("1.0"));
+        assert_eq!(paragraphs[0].get("Depends"), None);
+    }
+
+    #[test]
+    fn splits_paragraphs_on_blank_lines() {
+        let paragraphs = parse("\nSource: foo\n\n\nPackage: foo\n \t\nPackage: foo-doc\n").unwrap();
+        let names: Vec<_> = paragraphs
+            .iter()
+            .map(|paragraph| paragraph.get("Package").or(paragraph.get("Source")))
+            .collect();
+        assert_eq!(names, [Some("foo"), Some("foo"), Some("foo-doc")]);
+    }
+
+    #[test]
+    fn folds_relationship_fields() {
+        let paragraphs = parse("Depends: libc6 (>= 2.34),\n libfoo1 | libfoo2,\n bar:any [amd64]\n").unwrap();
+        let depends = paragraphs[0].folded("Depends").unwrap();
+        assert_eq!(depends, "libc6 (>= 2.34), libfoo1 | libfoo2, bar:any [amd64]");
+        assert_eq!(relationship_names(&depends), ["libc6", "libfoo1", "bar"]);
+    }
+
+    #[test]
+    fn rejects_a_duplicate_field() {
+        let error = parse("Package: foo\npackage: bar\n").unwrap_err();
+        assert_eq!(error, "line 2: duplicate field package");
+    }
+
+    #[test]
+    fn rejects_a_continuation_before_any_field() {
+        let error = parse("# header\n continued\n").unwrap_err();
+        assert_eq!(error, "line 2: continuation line before any field");
+    }
+
+    #[test]
+    fn names_the_line_of_a_malformed_field() {
+        let error = parse("Package: foo\nnot a field\n").unwrap_err();
+        assert!(error.starts_with("line 2: expected a `Field: value` line"), "{error}");
+        let error = parse("Package: foo\n\nBad Name: x\n").unwrap_err();
+        assert_eq!(error, "line 3: malformed field name \"Bad Name\"");
+    }
+}
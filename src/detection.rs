@@ -0,0 +1,449 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use walkdir::WalkDir;
+
+use crate::configuration::get_python_module_pkg;
+use crate::elf::has_elf_magic;
+use crate::structs::{AppKind, DataKind, DesktopEntry, ExtraFiles, Integration};
+
+static PYTHON_IMPORT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?m)^\s*(?:from\s+([A-Za-z_]\w*)[\w.]*\s+import|import\s+([A-Za-z_]\w*))").unwrap()
+});
+
+fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+fn has_pe_header(path: &Path) -> bool {
+    let mut magic = [0u8; 2];
+    fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .map(|_| &magic == b"MZ")
+        .unwrap_or(false)
+}
+
+pub fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|m| m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+/// Returns the interpreter line of a `#!` script, without the `#!`.
+pub fn read_shebang(path: &Path) -> Option<String> {
+    let mut head = [0u8; 256];
+    let len = fs::File::open(path).and_then(|mut f| f.read(&mut head)).ok()?;
+    let head = &head[..len];
+    if !head.starts_with(b"#!") {
+        return None;
+    }
+    let line = head[2..].split(|&b| b == b'\n').next()?;
+    Some(String::from_utf8_lossy(line).trim().to_string())
+}
+
+/// Maps a shebang line (`/bin/bash`, `/usr/bin/env python3`) to the
+/// nixpkgs package providing that interpreter.
+fn interpreter_pkg(shebang: &str) -> Option<&'static str> {
+    let mut words = shebang.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|w| !w.starts_with('-'))?;
+    }
+
+    let pkg = match program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+        "sh" | "bash" => "bash",
+        "dash" => "dash",
+        "zsh" => "zsh",
+        "ksh" => "ksh",
+        "python" => "python3",
+        "perl" => "perl",
+        "ruby" => "ruby",
+        "node" | "nodejs" => "nodejs",
+        "lua" => "lua",
+        "php" => "php",
+        "tclsh" => "tcl",
+        "wish" => "tk",
+        _ => return None,
+    };
+    Some(pkg)
+}
+
+/// Collects the interpreter packages needed by executable scripts in the
+/// payload so `patchShebangs` can rewrite them to store paths.
+pub fn script_interpreters(root: &Path) -> Vec<String> {
+    let mut interpreters = BTreeSet::new();
+    for entry in WalkDir::new(root).sort_by_file_name().into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() || !is_executable(entry.path()) {
+            continue;
+        }
+        if let Some(pkg) = read_shebang(entry.path()).as_deref().and_then(interpreter_pkg) {
+            interpreters.insert(pkg.to_string());
+        }
+    }
+    interpreters.into_iter().collect()
+}
+
+/// Maps the top-level imports of the given sources to python3Packages attrs,
+/// ignoring modules the payload ships itself.
+fn python_modules(sources: &[std::path::PathBuf], bundled: &BTreeSet<String>) -> Vec<String> {
+    let mut modules = BTreeSet::new();
+    for source in sources {
+        let Ok(content) = fs::read_to_string(source) else {
+            continue;
+        };
+        for caps in PYTHON_IMPORT.captures_iter(&content) {
+            let Some(name) = caps.get(1).or_else(|| caps.get(2)) else {
+                continue;
+            };
+            if bundled.contains(name.as_str()) {
+                continue;
+            }
+            if let Some(pkg) = get_python_module_pkg(name.as_str()) {
+                modules.insert(pkg.clone());
+            }
+        }
+    }
+    modules.into_iter().collect()
+}
+
+/// Maps a `runtimeconfig.json` framework reference to a nixpkgs attribute,
+/// e.g. `Microsoft.NETCore.App` 8.0.1 -> `dotnetCorePackages.runtime_8_0`.
+fn dotnet_runtime_attr(runtimeconfig: &Path) -> Option<String> {
+    let content = fs::read_to_string(runtimeconfig).ok()?;
+    let json: serde_json::Value = serde_json::from_str(&content).ok()?;
+    let options = json.get("runtimeOptions")?;
+
+    // Self-contained apps list `includedFrameworks` instead of `framework(s)`.
+    if options.get("includedFrameworks").is_some() {
+        return None;
+    }
+
+    let framework = options
+        .get("framework")
+        .or_else(|| options.get("frameworks").and_then(|f| f.get(0)))?;
+    let name = framework.get("name")?.as_str()?;
+    let version = framework.get("version")?.as_str()?;
+
+    let mut parts = version.split('.');
+    let major = parts.next()?;
+    let minor = parts.next().unwrap_or("0");
+
+    let flavour = match name {
+        "Microsoft.AspNetCore.App" => "aspnetcore",
+        _ => "runtime",
+    };
+
+    Some(format!("dotnetCorePackages.{}_{}_{}", flavour, major, minor))
+}
+
+/// Payload directories of each data kind.
+const DATA_DIRS: &[(&str, DataKind)] = &[
+    ("usr/share/fonts/", DataKind::Fonts),
+    ("etc/fonts/", DataKind::Fonts),
+    ("usr/share/icons/", DataKind::Icons),
+    ("usr/share/pixmaps/", DataKind::Icons),
+    ("usr/share/mime/", DataKind::Mime),
+    ("lib/firmware/", DataKind::Firmware),
+    ("usr/lib/firmware/", DataKind::Firmware),
+];
+
+/// Documentation and metadata that ship alongside any payload.
+const INCIDENTAL_DIRS: &[&str] = &[
+    "usr/share/doc/",
+    "usr/share/man/",
+    "usr/share/lintian/",
+    "usr/share/metainfo/",
+    "usr/share/appdata/",
+];
+
+/// The data kinds of a payload (file paths relative to its root) that is
+/// at least 90% fonts, icons, MIME definitions or firmware, docs aside; `None` for
+/// anything else.
+pub fn detect_data_kinds<S: AsRef<str>>(paths: &[S]) -> Option<Vec<DataKind>> {
+    let mut kinds = Vec::new();
+    let mut data = 0;
+    let mut other = 0;
+    for path in paths {
+        let path = path.as_ref().trim_start_matches("./");
+        // `.PKGINFO` and friends sit at the root of pacman and apk packages
+        if path.starts_with('.') || INCIDENTAL_DIRS.iter().any(|dir| path.starts_with(dir)) {
+            continue;
+        }
+        match DATA_DIRS.iter().find(|(dir, _)| path.starts_with(dir)) {
+            Some((_, kind)) => {
+                data += 1;
+                if !kinds.contains(kind) {
+                    kinds.push(*kind);
+                }
+            }
+            None => other += 1,
+        }
+    }
+    (data > 0 && data >= (data + other) * 9 / 10).then_some(kinds)
+}
+
+/// `<release>` of the first prebuilt module under `lib/modules/<release>/`.
+pub fn prebuilt_kernel_release<S: AsRef<str>>(paths: &[S]) -> Option<String> {
+    paths.iter().find_map(|path| {
+        let path = path.as_ref().trim_start_matches("./");
+        let rest = path.strip_prefix("lib/modules/").or_else(|| path.strip_prefix("usr/lib/modules/"))?;
+        let is_module = [".ko", ".ko.xz", ".ko.zst", ".ko.gz"].iter().any(|ext| rest.ends_with(ext));
+        is_module.then(|| rest.split('/').next().unwrap_or(rest).to_string())
+    })
+}
+
+/// The directory holding `dkms.conf` (e.g. `usr/src/foo-1.0`) of a DKMS
+/// source package.
+pub fn dkms_source_dir<S: AsRef<str>>(paths: &[S]) -> Option<String> {
+    paths.iter().find_map(|path| {
+        let path = path.as_ref().trim_start_matches("./");
+        let dir = path.strip_suffix("/dkms.conf")?;
+        dir.starts_with("usr/src/").then(|| dir.to_string())
+    })
+}
+
+/// Files only an Electron (or other Chromium-based) app bundle ships.
+const ELECTRON_MARKERS: &[&str] = &["resources/app.asar", "v8_context_snapshot.bin", "snapshot_blob.bin"];
+
+/// Whether the payload bundles Electron, which picks Wayland through
+/// Chromium's ozone switches instead of toolkit environment variables.
+pub fn is_electron<S: AsRef<str>>(paths: &[S]) -> bool {
+    paths.iter().any(|path| {
+        let path = path.as_ref().trim_start_matches("./");
+        ELECTRON_MARKERS
+            .iter()
+            .any(|marker| path.strip_suffix(marker).is_some_and(|dir| dir.is_empty() || dir.ends_with('/')))
+    })
+}
+
+/// The systemd units, udev rules and D-Bus files among `paths`, which the
+/// NixOS module registers through `systemd.packages`, `services.udev.packages`
+/// and `services.dbus.packages`.
+pub fn detect_integration<S: AsRef<str>>(paths: &[S]) -> Integration {
+    let mut integration = Integration::default();
+    for path in paths {
+        let path = path.as_ref().trim_start_matches("./");
+        let path = path.strip_prefix("usr/").unwrap_or(path);
+        let fname = path.rsplit('/').next().unwrap_or(path).to_string();
+        if let Some(unit) = path.strip_prefix("lib/systemd/system/") {
+            if !unit.contains('/') {
+                integration.system_units.push(fname);
+            }
+        } else if let Some(unit) = path.strip_prefix("lib/systemd/user/") {
+            if !unit.contains('/') {
+                integration.user_units.push(fname);
+            }
+        } else if (path.starts_with("lib/udev/rules.d/") || path.starts_with("etc/udev/rules.d/"))
+            && fname.ends_with(".rules")
+        {
+            integration.udev_rules.push(fname);
+        } else if path.starts_with("share/dbus-1/") || path.starts_with("etc/dbus-1/") {
+            integration.dbus_files.push(fname);
+        }
+    }
+    integration.system_units.sort();
+    integration.user_units.sort();
+    integration.udev_rules.sort();
+    integration.dbus_files.sort();
+    integration
+}
+
+/// Whether `fname` names a license, e.g. `LICENSE.txt` or `COPYING`.
+fn is_license(fname: &str) -> bool {
+    let upper = fname.to_ascii_uppercase();
+    ["LICENSE", "LICENCE", "COPYING", "EULA"].iter().any(|p| upper.starts_with(p))
+}
+
+/// The man pages, completions and license files among `paths` that copying
+/// the payload into `$out` leaves where nothing finds them: pages outside
+/// `usr/share/man`, `etc/bash_completion.d`, Debian's `vendor-completions`
+/// for zsh and fish's non-vendor directory. Licenses are Debian `copyright`
+/// files and `LICENSE`-like files near the top of the tree, not those of
+/// bundled node or Python modules.
+pub fn detect_extra_files<S: AsRef<str>>(paths: &[S]) -> ExtraFiles {
+    let mut extra = ExtraFiles::default();
+    for path in paths {
+        let path = path.as_ref().trim_start_matches("./");
+        let segments: Vec<&str> = path.split('/').collect();
+        let fname = segments.last().copied().unwrap_or(path);
+        let dir = &segments[..segments.len() - 1];
+        let in_man_section = dir.len() >= 2
+            && dir[dir.len() - 2] == "man"
+            && dir[dir.len() - 1].strip_prefix("man").is_some_and(|s| s.starts_with(|c: char| c.is_ascii_digit()));
+        if in_man_section {
+            if !path.starts_with("usr/share/man/") {
+                extra.man_pages.push(path.to_string());
+            }
+        } else if path.starts_with("etc/bash_completion.d/")
+            || path.contains("/bash_completion.d/")
+            || (path.contains("/bash-completion/completions/")
+                && !path.starts_with("usr/share/bash-completion/completions/"))
+        {
+            extra.bash_completions.push(path.to_string());
+        } else if fname.starts_with('_')
+            && (path.contains("zsh/vendor-completions/")
+                || (path.contains("zsh/site-functions/") && !path.starts_with("usr/share/zsh/site-functions/")))
+        {
+            extra.zsh_completions.push(path.to_string());
+        } else if fname.ends_with(".fish")
+            && (path.contains("fish/completions/")
+                || (path.contains("fish/vendor_completions.d/")
+                    && !path.starts_with("usr/share/fish/vendor_completions.d/")))
+        {
+            extra.fish_completions.push(path.to_string());
+        } else if (path.starts_with("usr/share/doc/") && fname == "copyright" && segments.len() == 5)
+            || (is_license(fname)
+                && segments.len() <= 4
+                && !path.starts_with("usr/share/licenses/")
+                && !segments.iter().any(|s| matches!(*s, "node_modules" | "site-packages" | "dist-packages")))
+        {
+            extra.licenses.push(path.to_string());
+        }
+    }
+    extra.man_pages.sort();
+    extra.bash_completions.sort();
+    extra.zsh_completions.sort();
+    extra.fish_completions.sort();
+    extra.licenses.sort();
+    extra
+}
+
+/// Parses the `[Desktop Entry]` group of the `.desktop` file at `rel`;
+/// `None` if it has none or no `Name`.
+pub fn read_desktop_entry(root: &Path, rel: &str) -> Option<DesktopEntry> {
+    let content = fs::read_to_string(root.join(rel)).ok()?;
+    let id = rel.rsplit('/').next()?.strip_suffix(".desktop")?.to_string();
+    let list = |value: &str| {
+        value
+            .split(';')
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+
+    let mut entry = DesktopEntry {
+        id,
+        ..DesktopEntry::default()
+    };
+    let mut in_group = false;
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_group = line == "[Desktop Entry]";
+            continue;
+        }
+        // Localized keys (`Name[de]`) contain a `[` and are skipped
+        let Some((key, value)) = line.split_once('=').filter(|_| in_group) else {
+            continue;
+        };
+        let value = value.trim().to_string();
+        match key.trim() {
+            "Name" => entry.name = value,
+            "GenericName" => entry.generic_name = Some(value),
+            "Comment" => entry.comment = Some(value),
+            "Exec" => entry.exec = Some(value),
+            "Icon" => entry.icon = Some(value),
+            "Terminal" => entry.terminal = value == "true",
+            "Categories" => entry.categories = list(&value),
+            "MimeType" => entry.mime_types = list(&value),
+            _ => {}
+        }
+    }
+    (!entry.name.is_empty()).then_some(entry)
+}
+
+/// Looks for a .NET (`*.runtimeconfig.json` + `*.dll`, optionally with an
+/// apphost executable next to it), Mono (`*.exe` assemblies) or pure Python
+/// (python shebang scripts and no ELF executables) layout.
+pub fn detect_app_kind(root: &Path, control_depends: &[String]) -> AppKind {
+    let mut runtimeconfigs = Vec::new();
+    let mut assemblies = Vec::new();
+    let mut self_contained = false;
+    let mut python_entries = Vec::new();
+    let mut python_sources = Vec::new();
+    let mut site_packages = BTreeSet::new();
+    let mut has_elf_executable = false;
+
+    for entry in WalkDir::new(root).sort_by_file_name().into_iter().filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy();
+        if entry.file_type().is_dir() && (name == "site-packages" || name == "dist-packages") {
+            site_packages.insert(entry.path().to_path_buf());
+        }
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let fname = entry.file_name().to_string_lossy();
+
+        if fname.ends_with(".py") {
+            python_sources.push(entry.path().to_path_buf());
+        } else if is_executable(entry.path()) {
+            if has_elf_magic(entry.path()) {
+                has_elf_executable = true;
+            } else if read_shebang(entry.path()).is_some_and(|s| s.contains("python")) {
+                python_entries.push(entry.path().to_path_buf());
+                python_sources.push(entry.path().to_path_buf());
+            }
+        }
+
+        if fname.ends_with(".runtimeconfig.json") {
+            runtimeconfigs.push(entry.path().to_path_buf());
+        } else if fname == "libcoreclr.so" || fname == "libhostfxr.so" {
+            self_contained = true;
+        } else if fname.ends_with(".exe") && has_pe_header(entry.path()) {
+            assemblies.push(entry.path().to_path_buf());
+        }
+    }
+
+    runtimeconfigs.sort();
+    if let Some(config) = runtimeconfigs.first() {
+        let fname = config.file_name().unwrap_or_default().to_string_lossy();
+        let stem = fname.trim_end_matches(".runtimeconfig.json");
+        let dir = config.parent().unwrap_or(root);
+
+        let dll = dir.join(format!("{}.dll", stem));
+        let apphost = dir.join(stem);
+
+        return AppKind::DotNet {
+            entry: relative(root, &dll),
+            apphost: apphost.is_file().then(|| relative(root, &apphost)),
+            runtime: if self_contained { None } else { dotnet_runtime_attr(config) },
+        };
+    }
+
+    let wants_mono = control_depends
+        .iter()
+        .any(|d| d.starts_with("mono-") || d.starts_with("libmono"));
+
+    assemblies.sort();
+    if wants_mono && let Some(exe) = assemblies.first() {
+        return AppKind::Mono {
+            entry: relative(root, exe),
+        };
+    }
+
+    if !python_entries.is_empty() && !has_elf_executable {
+        let bundled: BTreeSet<String> = site_packages
+            .iter()
+            .filter_map(|dir| fs::read_dir(dir).ok())
+            .flatten()
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().trim_end_matches(".py").to_string())
+            .collect();
+
+        python_entries.sort();
+        return AppKind::Python {
+            modules: python_modules(&python_sources, &bundled),
+            entries: python_entries.iter().map(|p| relative(root, p)).collect(),
+            site_packages: site_packages.iter().map(|p| relative(root, p)).collect(),
+        };
+    }
+
+    AppKind::Native
+}
@@ -0,0 +1,116 @@
+use std::collections::BTreeSet;
+use std::fs;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::errors::AppError;
+use crate::readfile_nix;
+use crate::structs::ScanOptions;
+
+static NIX_PKG_REF: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\s*pkgs\.([A-Za-z0-9_.+-]+)\s*(?:#.*)?$").unwrap());
+static NIX_ATTR: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"(?m)^\s*(pname|version|description)\s*=\s*"([^"]*)";"#).unwrap());
+
+/// What two inputs are compared on; sonames and binaries are only known
+/// when diffing debs, not generated expressions.
+#[derive(Default)]
+struct Snapshot {
+    metadata: Vec<(String, String)>,
+    sonames: BTreeSet<String>,
+    packages: BTreeSet<String>,
+    binaries: BTreeSet<String>,
+}
+
+fn snapshot_deb(path: &str, target_system: &str) -> Result<Snapshot, AppError> {
+    let info = readfile_nix::get_nix_shell(&[path.to_string()], target_system, &ScanOptions::default())?;
+    Ok(Snapshot {
+        metadata: vec![
+            ("name".to_string(), info.name),
+            ("version".to_string(), info.version),
+            ("arch".to_string(), info.arch),
+            ("description".to_string(), info.description),
+        ],
+        sonames: info.needed_libs.into_iter().collect(),
+        packages: info.deps.into_iter().collect(),
+        binaries: info.binaries.into_iter().map(|b| b.path).collect(),
+    })
+}
+
+/// Reads pname/version/description and every `pkgs.<attr>` list entry.
+fn snapshot_nix(path: &str) -> Result<Snapshot, AppError> {
+    let content = fs::read_to_string(path).map_err(|e| AppError::Input(format!("Failed to read {}: {}", path, e)))?;
+
+    let metadata = NIX_ATTR
+        .captures_iter(&content)
+        .map(|c| (c[1].to_string(), c[2].to_string()))
+        .collect();
+
+    let packages = content
+        .lines()
+        .filter_map(|line| NIX_PKG_REF.captures(line))
+        .map(|c| c[1].to_string())
+        .collect();
+
+    Ok(Snapshot {
+        metadata,
+        packages,
+        ..Default::default()
+    })
+}
+
+fn print_set_diff(title: &str, old: &BTreeSet<String>, new: &BTreeSet<String>, prefix: &str) -> bool {
+    let added: Vec<_> = new.difference(old).collect();
+    let removed: Vec<_> = old.difference(new).collect();
+    if added.is_empty() && removed.is_empty() {
+        return false;
+    }
+
+    println!("{}:", title);
+    for item in added {
+        println!("  + {}{}", prefix, item);
+    }
+    for item in removed {
+        println!("  - {}{}", prefix, item);
+    }
+    true
+}
+
+/// `app2nix diff <old> <new>`: both inputs must be packages or both
+/// generated .nix expressions.
+pub fn run_diff_command(old: &str, new: &str, target_system: &str) -> Result<(), AppError> {
+    let is_package = |path: &str| readfile_nix::detect_package_type(path).is_some();
+    let (old_snapshot, new_snapshot) = match (is_package(old), is_package(new)) {
+        (true, true) => (snapshot_deb(old, target_system)?, snapshot_deb(new, target_system)?),
+        (false, false) => (snapshot_nix(old)?, snapshot_nix(new)?),
+        _ => return Err(AppError::Usage("diff needs two packages or two generated .nix files".to_string())),
+    };
+
+    let mut changed = false;
+
+    let metadata_changes: Vec<_> = old_snapshot
+        .metadata
+        .iter()
+        .filter_map(|(key, old_value)| {
+            let new_value = new_snapshot.metadata.iter().find(|(k, _)| k == key).map(|(_, v)| v)?;
+            (old_value != new_value).then_some((key, old_value, new_value))
+        })
+        .collect();
+    if !metadata_changes.is_empty() {
+        changed = true;
+        println!("Metadata:");
+        for (key, old_value, new_value) in metadata_changes {
+            println!("  {}: {} -> {}", key, old_value, new_value);
+        }
+    }
+
+    changed |= print_set_diff("Sonames", &old_snapshot.sonames, &new_snapshot.sonames, "");
+    changed |= print_set_diff("Packages", &old_snapshot.packages, &new_snapshot.packages, "pkgs.");
+    changed |= print_set_diff("Binaries", &old_snapshot.binaries, &new_snapshot.binaries, "");
+
+    if !changed {
+        println!("No differences in metadata, dependencies or binaries.");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use log::info;
+
+use crate::cache;
+use crate::configuration;
+use crate::errors::AppError;
+
+/// Older nix-index databases miss packages added since.
+const NIX_INDEX_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Hosts app2nix talks to, and what for.
+const ENDPOINTS: [(&str, &str); 3] = [
+    ("https://cache.nixos.org/nix-cache-info", "closure sizes (--closure-size)"),
+    ("https://api.osv.dev", "vulnerability checks (--vuln-check)"),
+    ("https://repology.org", "app2nix outdated"),
+];
+
+#[derive(PartialEq)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    status: Status,
+    name: String,
+    detail: String,
+    fix: Option<String>,
+}
+
+impl Check {
+    fn new(status: Status, name: &str, detail: impl Into<String>, fix: Option<String>) -> Check {
+        Check {
+            status,
+            name: name.to_string(),
+            detail: detail.into(),
+            fix,
+        }
+    }
+}
+
+fn tools() -> Vec<Check> {
+    let mut checks = Vec::new();
+    for (tool, pkg, needed_for) in [
+        ("nix", "nix", "hashing, searching nixpkgs and checking written files"),
+        ("nix-instantiate", "nix", "checking that written files parse"),
+        ("wget", "wget", "downloading packages"),
+    ] {
+        checks.push(if crate::has_tool(tool) {
+            Check::new(Status::Ok, tool, "found", None)
+        } else {
+            Check::new(
+                Status::Fail,
+                tool,
+                format!("missing, needed for {}", needed_for),
+                Some(format!("install {} (nix-env -iA nixpkgs.{}, or your distribution's package)", tool, pkg)),
+            )
+        });
+    }
+    for (tool, pkg, effect) in crate::OPTIONAL_TOOLS.iter().chain([&crate::SNAP_TOOL]) {
+        checks.push(if crate::has_tool(tool) {
+            Check::new(Status::Ok, tool, "found", None)
+        } else {
+            Check::new(
+                Status::Warn,
+                tool,
+                format!("missing; {}", effect),
+                Some(format!("install {} or pass --auto-shell when generating", pkg)),
+            )
+        });
+    }
+    for (tool, used_by) in [("curl", "--vuln-check, --debian/--ppa and outdated"), ("bwrap", "app2nix try")] {
+        if !crate::has_tool(tool) {
+            checks.push(Check::new(
+                Status::Warn,
+                tool,
+                format!("missing, needed by {}", used_by),
+                Some(format!("install pkgs.{}", if tool == "bwrap" { "bubblewrap" } else { tool })),
+            ));
+        }
+    }
+    checks
+}
+
+/// nix-index keeps its database in `$NIX_INDEX_DATABASE` or
+/// `~/.cache/nix-index`, as the `files` file.
+fn nix_index_database() -> Check {
+    let dir = env::var("NIX_INDEX_DATABASE")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var("HOME").ok().map(|home| Path::new(&home).join(".cache/nix-index")));
+    let Some(files) = dir.map(|d| d.join("files")) else {
+        return Check::new(Status::Warn, "nix-index database", "HOME is not set", None);
+    };
+    let fetch = Some(
+        "run nix-index (takes a while), or download a prebuilt database from github.com/nix-community/nix-index-database"
+            .to_string(),
+    );
+    let Ok(metadata) = fs::metadata(&files) else {
+        return Check::new(
+            Status::Warn,
+            "nix-index database",
+            format!("{} not found; libraries are only resolved from the libraries config", files.display()),
+            fetch,
+        );
+    };
+    let age = metadata
+        .modified()
+        .ok()
+        .and_then(|m| SystemTime::now().duration_since(m).ok())
+        .unwrap_or_default();
+    let days = age.as_secs() / (24 * 60 * 60);
+    if age > NIX_INDEX_MAX_AGE {
+        return Check::new(
+            Status::Warn,
+            "nix-index database",
+            format!("{} is {} days old; newer packages are not found", files.display(), days),
+            fetch,
+        );
+    }
+    Check::new(Status::Ok, "nix-index database", format!("{} ({} days old)", files.display(), days), None)
+}
+
+/// app2nix turns nix-command and flakes on for its own calls; the flake.nix
+/// and the commands it suggests need them in nix.conf.
+fn experimental_features() -> Check {
+    let name = "experimental features";
+    let output = Command::new("nix")
+        .args(["config", "show", "experimental-features"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .or_else(|| {
+            Command::new("nix")
+                .args(["show-config", "--extra-experimental-features", "nix-command"])
+                .output()
+                .ok()
+                .filter(|o| o.status.success())
+        });
+    let Some(output) = output else {
+        return Check::new(Status::Warn, name, "could not ask nix for its configuration", None);
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // `config show <key>` prints the value, `show-config` every `key = value`
+    let enabled = match stdout.lines().find_map(|l| l.strip_prefix("experimental-features = ")) {
+        Some(value) => value.to_string(),
+        None => stdout.trim().to_string(),
+    };
+    let missing: Vec<&str> = ["nix-command", "flakes"]
+        .into_iter()
+        .filter(|f| !enabled.split_whitespace().any(|e| e == *f))
+        .collect();
+    if missing.is_empty() {
+        return Check::new(Status::Ok, name, "nix-command and flakes are enabled", None);
+    }
+    Check::new(
+        Status::Warn,
+        name,
+        format!("{} not enabled; app2nix enables them for its own calls only", missing.join(" and ")),
+        Some("add `experimental-features = nix-command flakes` to ~/.config/nix/nix.conf".to_string()),
+    )
+}
+
+/// Whether a file can be created in `dir`, creating `dir` if needed.
+fn writable(dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    tempfile::NamedTempFile::new_in(dir).map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn directories() -> Vec<Check> {
+    let mut checks = Vec::new();
+    match cache::cache_dir() {
+        Some(dir) => checks.push(match writable(&dir) {
+            Ok(()) => Check::new(Status::Ok, "cache directory", dir.display().to_string(), None),
+            Err(e) => Check::new(
+                Status::Warn,
+                "cache directory",
+                format!("{} is not writable ({}); scans are not cached", dir.display(), e),
+                Some("fix its permissions or point XDG_CACHE_HOME elsewhere".to_string()),
+            ),
+        }),
+        None => checks.push(Check::new(
+            Status::Warn,
+            "cache directory",
+            "neither XDG_CACHE_HOME nor HOME is set; scans are not cached",
+            None,
+        )),
+    }
+    let tmp = env::var("APP2NIX_TMPDIR").ok().filter(|v| !v.is_empty()).map(PathBuf::from).unwrap_or_else(env::temp_dir);
+    checks.push(match writable(&tmp) {
+        Ok(()) => Check::new(Status::Ok, "extraction directory", tmp.display().to_string(), None),
+        Err(e) => Check::new(
+            Status::Fail,
+            "extraction directory",
+            format!("{} is not writable ({})", tmp.display(), e),
+            Some("set APP2NIX_TMPDIR or pass --tmpdir to a writable directory".to_string()),
+        ),
+    });
+    checks
+}
+
+fn network() -> Vec<Check> {
+    ENDPOINTS
+        .iter()
+        .map(|(url, used_for)| {
+            let reachable = if crate::has_tool("curl") {
+                Command::new("curl").args(["-sS", "--head", "--max-time", "5", "-o", "/dev/null", url]).output()
+            } else {
+                Command::new("wget").args(["-q", "--spider", "--timeout=5", "--tries=1", url]).output()
+            }
+            .is_ok_and(|o| o.status.success());
+            let host = url.split('/').nth(2).unwrap_or(url);
+            if reachable {
+                Check::new(Status::Ok, host, "reachable", None)
+            } else {
+                Check::new(
+                    Status::Warn,
+                    host,
+                    format!("unreachable; {} will not work", used_for),
+                    Some("check the network, proxy variables (https_proxy) and firewall".to_string()),
+                )
+            }
+        })
+        .collect()
+}
+
+/// The `APP2NIX_*` variables in effect, which a CI job may set without it
+/// being obvious from the command line. Passwords are not shown.
+fn environment() -> Option<Check> {
+    let set: Vec<String> = configuration::ENV_VARS
+        .iter()
+        .filter_map(|(name, _)| {
+            let value = configuration::env_setting(name)?;
+            Some(if *name == "APP2NIX_PASSWORD" { format!("{}=***", name) } else { format!("{}={}", name, value) })
+        })
+        .collect();
+    (!set.is_empty()).then(|| Check::new(Status::Ok, "environment", set.join(", "), None))
+}
+
+fn libraries_config() -> Check {
+    let path = configuration::get_config_path();
+    match configuration::load_libraries_config() {
+        Ok(config) => Check::new(
+            Status::Ok,
+            "libraries config",
+            format!(
+                "{}: {} system libraries, {} library mappings",
+                path,
+                config.system_libs.len(),
+                config.lib_to_pkg_map.len()
+            ),
+            None,
+        ),
+        Err(e) => Check::new(
+            Status::Fail,
+            "libraries config",
+            e.to_string(),
+            Some(format!(
+                "fix the file or remove {} to use the built-in defaults; `app2nix config lint` shows details",
+                path
+            )),
+        ),
+    }
+}
+
+/// `app2nix doctor`: checks the tools, the nix-index database, nix's
+/// experimental features, the cache and extraction directories, network
+/// access and the libraries config, with a fix for each problem.
+pub fn run_doctor_command() -> Result<(), AppError> {
+    let mut checks = tools();
+    checks.push(nix_index_database());
+    checks.push(experimental_features());
+    checks.extend(directories());
+    checks.extend(network());
+    checks.extend(environment());
+    checks.push(libraries_config());
+
+    for check in &checks {
+        let mark = match check.status {
+            Status::Ok => "ok  ",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        println!("[{}] {}: {}", mark, check.name, check.detail);
+        if let Some(fix) = &check.fix {
+            println!("       fix: {}", fix);
+        }
+    }
+
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    let warned = checks.iter().filter(|c| c.status == Status::Warn).count();
+    if failed > 0 {
+        return Err(AppError::Input(format!(
+            "{} check(s) failed, {} warning(s); app2nix cannot run until the failures are fixed",
+            failed, warned
+        )));
+    }
+    info!("{} checks passed, {} warning(s).", checks.len() - warned, warned);
+    Ok(())
+}
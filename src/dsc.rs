@@ -0,0 +1,191 @@
+use std::fs;
+use std::path::Path;
+
+use log::{info, warn};
+
+use crate::configuration::get_debian_package_pkg;
+use crate::deb822::{self, Paragraph};
+use crate::errors::AppError;
+use crate::generation_nix::{escape_string, fill_template};
+use crate::hashing;
+use crate::naming;
+use crate::structs::PackageType;
+
+/// Debian packaging helpers `stdenv` already covers or Nix has no use for.
+const DEBIAN_TOOLING: &[&str] = &[
+    "build-essential",
+    "debhelper",
+    "debhelper-compat",
+    "dpkg-dev",
+    "g++",
+    "gcc",
+    "libc6-dev",
+    "lsb-release",
+    "make",
+    "quilt",
+];
+
+/// One file listed in `Checksums-Sha256`.
+struct SourceFile {
+    name: String,
+    sha256: String,
+}
+
+/// The fields of a `.dsc` used for the skeleton.
+struct SourceControl {
+    source: String,
+    version: String,
+    format: String,
+    homepage: Option<String>,
+    build_depends: Vec<String>,
+    files: Vec<SourceFile>,
+}
+
+/// The single paragraph of a (possibly clearsigned) `.dsc`.
+fn parse_paragraph(content: &str) -> Result<Paragraph, String> {
+    let body = match content.strip_prefix("-----BEGIN PGP SIGNED MESSAGE-----") {
+        // Armor headers (`Hash: ...`) end at the first blank line
+        Some(rest) => rest.split_once("\n\n").map_or(rest, |(_, b)| b),
+        None => content,
+    };
+    let body = body.split("\n-----BEGIN PGP SIGNATURE-----").next().unwrap_or(body);
+    deb822::parse(body)?.into_iter().next().ok_or_else(|| "no fields".to_string())
+}
+
+fn read_dsc(path: &Path) -> Result<SourceControl, AppError> {
+    let content = fs::read_to_string(path).map_err(|e| AppError::Extract(format!("{}: {}", path.display(), e)))?;
+    let fields = parse_paragraph(&content).map_err(|e| AppError::Extract(format!("Malformed {}: {}", path.display(), e)))?;
+    let field = |key: &str| fields.get(key).map(str::to_string).filter(|v| !v.is_empty());
+
+    let source = field("Source").ok_or_else(|| AppError::Extract(format!("No Source field in {}", path.display())))?;
+    let version = field("Version").ok_or_else(|| AppError::Extract(format!("No Version field in {}", path.display())))?;
+    let build_depends = ["Build-Depends", "Build-Depends-Arch", "Build-Depends-Indep"]
+        .iter()
+        .filter_map(|key| field(key))
+        .flat_map(|value| deb822::relationship_names(&value))
+        .collect();
+    let files = field("Checksums-Sha256")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let sha256 = parts.next()?.to_string();
+            let name = parts.nth(1)?.to_string();
+            Some(SourceFile { name, sha256 })
+        })
+        .collect();
+
+    Ok(SourceControl {
+        source,
+        version,
+        format: field("Format").unwrap_or_else(|| "1.0".to_string()),
+        homepage: field("Homepage"),
+        build_depends,
+        files,
+    })
+}
+
+fn fetchurl(base_url: &str, file: &SourceFile) -> Result<String, AppError> {
+    let digest = hashing::from_hex(&file.sha256)
+        .ok_or_else(|| AppError::Extract(format!("Malformed SHA256 for {}: {}", file.name, file.sha256)))?;
+    Ok(format!(
+        "pkgs.fetchurl {{\n    url = \"{}/{}\";\n    sha256 = \"{}\";\n  }}",
+        escape_string(base_url),
+        escape_string(&file.name),
+        hashing::to_sri(&digest)
+    ))
+}
+
+/// Generates a `stdenv.mkDerivation` skeleton from the `.dsc` at `path`:
+/// the orig tarball as `src`, the Debian patches applied in `prePatch` and
+/// `Build-Depends` mapped through the `debian_packages` table. Source files
+/// are fetched from `base_url`, the directory the `.dsc` came from.
+pub fn generate_from_dsc(path: &Path, base_url: &str) -> Result<String, AppError> {
+    let dsc = read_dsc(path)?;
+    info!("Debian source package {} {} (format {})", dsc.source, dsc.version, dsc.format);
+
+    let is_orig = |f: &&SourceFile| f.name.contains(".orig.tar.") || f.name.ends_with(".orig.tar");
+    let is_debian = |f: &&SourceFile| f.name.contains(".debian.tar.") || f.name.ends_with(".diff.gz");
+    // `3.0 (native)` ships one tarball that already contains debian/
+    let orig = dsc
+        .files
+        .iter()
+        .find(is_orig)
+        .or_else(|| dsc.files.iter().find(|f| f.name.contains(".tar.")))
+        .ok_or_else(|| AppError::Extract(format!("No source tarball listed in {}", path.display())))?;
+    let debian = dsc.files.iter().find(is_debian);
+
+    let mut native_build_inputs = Vec::new();
+    let mut build_inputs = Vec::new();
+    let mut unmapped = Vec::new();
+    for name in &dsc.build_depends {
+        if DEBIAN_TOOLING.contains(&name.as_str()) || name.starts_with("dh-") {
+            continue;
+        }
+        // Libraries to link go to buildInputs, build tools to nativeBuildInputs
+        let inputs = if name.ends_with("-dev") { &mut build_inputs } else { &mut native_build_inputs };
+        match get_debian_package_pkg(name) {
+            Some(attr) if !inputs.contains(attr) => inputs.push(attr.clone()),
+            Some(_) => {}
+            None => unmapped.push(name.clone()),
+        }
+    }
+    if !unmapped.is_empty() {
+        warn!("No nixpkgs attr known for Build-Depends: {}", unmapped.join(", "));
+    }
+
+    let list = |attrs: &[String]| {
+        attrs
+            .iter()
+            .map(|a| format!("    pkgs.{}", a))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let debian_let = match debian {
+        Some(file) => format!("let\n  debian = {};\nin\n", fetchurl(base_url, file)?),
+        None => String::new(),
+    };
+    let pre_patch = match debian {
+        Some(file) if file.name.ends_with(".diff.gz") => "  prePatch = ''\n    zcat ${debian} | patch -p1\n  '';\n\n".to_string(),
+        Some(_) => concat!(
+            "  # Debian's quilt series, in order\n",
+            "  prePatch = ''\n",
+            "    tar -xf ${debian}\n",
+            "    if [ -f debian/patches/series ]; then\n",
+            "      sed -e '/^#/d' -e '/^[[:space:]]*$/d' debian/patches/series | while read -r patch opts; do\n",
+            "        patch ''${opts:--p1} < \"debian/patches/$patch\"\n",
+            "      done\n",
+            "    fi\n",
+            "  '';\n\n",
+        )
+        .to_string(),
+        None => String::new(),
+    };
+    let unmapped_comment = if unmapped.is_empty() {
+        String::new()
+    } else {
+        format!("  # Build-Depends without a known nixpkgs attr: {}\n\n", unmapped.join(", "))
+    };
+    let homepage = dsc
+        .homepage
+        .as_deref()
+        .map(|h| format!("    homepage = \"{}\";\n", escape_string(h)))
+        .unwrap_or_default();
+
+    Ok(fill_template(
+        include_str!("../templates/dsc.in"),
+        &[
+            ("{header}", "{ pkgs ? import <nixpkgs> {} }:"),
+            ("{debian_let}", &debian_let),
+            ("{src}", &fetchurl(base_url, orig)?),
+            ("{native_build_inputs}", &list(&native_build_inputs)),
+            ("{build_inputs}", &list(&build_inputs)),
+            ("{unmapped}\n", &unmapped_comment),
+            ("{pre_patch}\n", &pre_patch),
+            ("{homepage}\n", &homepage),
+            ("{name}", &dsc.source),
+            ("{version}", &naming::normalize_version(&dsc.version, PackageType::Deb)),
+        ],
+    ))
+}
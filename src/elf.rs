@@ -0,0 +1,164 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use goblin::elf::header::{EM_386, EM_AARCH64, EM_ARM, EM_RISCV, EM_X86_64, ET_DYN, ET_EXEC};
+use goblin::elf::Elf;
+use once_cell::sync::Lazy;
+use regex::bytes::Regex;
+
+use crate::structs::{BinaryInfo, ElfKind};
+
+static SONAME_STRING: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"lib[A-Za-z0-9_+.-]+?\.so(?:\.[0-9]+)*").unwrap());
+
+const GO_SECTIONS: [&str; 3] = [".go.buildinfo", ".note.go.buildid", ".gopclntab"];
+
+/// Each Nix system's glibc loader and musl's `ld-musl-<arch>.so.1` arch.
+const LOADERS: [(&str, &str, &str); 5] = [
+    ("x86_64-linux", "ld-linux-x86-64.so.2", "x86_64"),
+    ("i686-linux", "ld-linux.so.2", "i386"),
+    ("aarch64-linux", "ld-linux-aarch64.so.1", "aarch64"),
+    ("armv7l-linux", "ld-linux-armhf.so.3", "armhf"),
+    ("riscv64-linux", "ld-linux-riscv64-lp64d.so.1", "riscv64"),
+];
+
+/// Whether code for `machine` runs on `system`: its own instruction set, or
+/// i386 on x86_64. Unknown systems accept anything.
+pub fn runs_on(machine: u16, system: &str) -> bool {
+    match system {
+        "x86_64-linux" => machine == EM_X86_64 || machine == EM_386,
+        "i686-linux" => machine == EM_386,
+        "aarch64-linux" => machine == EM_AARCH64,
+        "armv7l-linux" => machine == EM_ARM,
+        "riscv64-linux" => machine == EM_RISCV,
+        _ => true,
+    }
+}
+
+/// The Nix system whose glibc or musl loader the `PT_INTERP` path
+/// `interpreter` names, e.g. `i686-linux` for `/lib/ld-linux.so.2`.
+pub fn loader_system(interpreter: &str) -> Option<&'static str> {
+    let name = interpreter.rsplit('/').next().unwrap_or(interpreter);
+    LOADERS
+        .iter()
+        .find(|(_, glibc, musl_arch)| name == *glibc || name == format!("ld-musl-{}.so.1", musl_arch))
+        .map(|(system, _, _)| *system)
+}
+
+pub fn has_elf_magic(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .map(|_| &magic == b"\x7fELF")
+        .unwrap_or(false)
+}
+
+/// Parses the ELF headers of `path`; returns `None` for anything that is not
+/// an ELF executable or shared object (relocatable objects, core dumps, ...).
+pub fn inspect(path: &Path, rel: &str) -> Option<BinaryInfo> {
+    if !has_elf_magic(path) {
+        return None;
+    }
+    let bytes = fs::read(path).ok()?;
+    let elf = Elf::parse(&bytes).ok()?;
+
+    let is_go = elf
+        .section_headers
+        .iter()
+        .any(|sh| elf.shdr_strtab.get_at(sh.sh_name).is_some_and(|n| GO_SECTIONS.contains(&n)));
+
+    let kind = match elf.header.e_type {
+        _ if elf.interpreter.is_some() => ElfKind::Dynamic,
+        ET_DYN if elf.soname.is_some() || !elf.libraries.is_empty() || rel.contains(".so") => {
+            ElfKind::SharedLibrary
+        }
+        ET_DYN | ET_EXEC => ElfKind::Static,
+        _ => return None,
+    };
+
+    let mut dlopen_names: Vec<String> = match kind {
+        ElfKind::Static => Vec::new(),
+        _ => SONAME_STRING
+            .find_iter(&bytes)
+            .map(|m| String::from_utf8_lossy(m.as_bytes()).to_string())
+            .filter(|name| !elf.libraries.contains(&name.as_str()))
+            .collect(),
+    };
+    dlopen_names.sort();
+    dlopen_names.dedup();
+
+    Some(BinaryInfo {
+        path: rel.to_string(),
+        kind,
+        is_go,
+        is_32bit: !elf.is_64,
+        machine: elf.header.e_machine,
+        soname: elf.soname.map(|s| s.to_string()),
+        interpreter: elf.interpreter.map(|s| s.to_string()),
+        needed: elf.libraries.iter().map(|l| l.to_string()).collect(),
+        // The loader ignores DT_RPATH when DT_RUNPATH is present
+        search_paths: if elf.runpaths.is_empty() { &elf.rpaths } else { &elf.runpaths }
+            .iter()
+            .flat_map(|p| p.split(':'))
+            .filter(|p| !p.is_empty())
+            .map(str::to_string)
+            .collect(),
+        dlopen_names,
+    })
+}
+
+/// Maps a search path entry of the binary at payload-relative `rel` to a
+/// payload-relative directory: `$ORIGIN` is the binary's directory and an
+/// absolute path is taken inside the payload. `None` for entries with other
+/// tokens (`$LIB`, `$PLATFORM`) or climbing out of the payload.
+pub fn search_dir(entry: &str, rel: &str) -> Option<String> {
+    let origin = rel.rsplit_once('/').map_or("", |(dir, _)| dir);
+    let path = if let Some(rest) = entry.strip_prefix("$ORIGIN").or_else(|| entry.strip_prefix("${ORIGIN}")) {
+        format!("{}/{}", origin, rest)
+    } else if entry.starts_with('/') {
+        entry.to_string()
+    } else {
+        return None;
+    };
+    if path.contains('$') {
+        return None;
+    }
+    let mut dir: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                dir.pop()?;
+            }
+            c => dir.push(c),
+        }
+    }
+    Some(dir.join("/"))
+}
+
+/// Best-effort library version: the numeric suffix of the file name
+/// (`libfoo.so.1.2.3`), else an embedded `<name> 1.2.3`-style string, else the
+/// soname's ABI number.
+pub fn library_version(path: &Path, soname: Option<&str>) -> Option<String> {
+    let fname = path.file_name()?.to_string_lossy();
+    let (stem, suffix) = fname.split_once(".so")?;
+    let suffix = suffix.trim_start_matches('.');
+    if suffix.matches('.').count() >= 1 && suffix.split('.').all(|p| p.parse::<u32>().is_ok()) {
+        return Some(suffix.to_string());
+    }
+
+    let name = stem.trim_start_matches("lib");
+    if name.len() >= 3
+        && let Ok(pattern) = Regex::new(&format!(r"(?i){}[ _/-]?v?(\d+\.\d+(?:\.\d+)?)", regex::escape(name)))
+        && let Ok(bytes) = fs::read(path)
+        && let Some(caps) = pattern.captures(&bytes)
+    {
+        return Some(String::from_utf8_lossy(&caps[1]).to_string());
+    }
+
+    soname
+        .and_then(|s| s.split_once(".so."))
+        .map(|(_, abi)| abi.to_string())
+        .or_else(|| (!suffix.is_empty()).then(|| suffix.to_string()))
+}
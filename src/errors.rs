@@ -0,0 +1,39 @@
+use thiserror::Error;
+
+/// Failures that end a run, grouped by stage so scripts can tell them apart
+/// through the exit code.
+#[derive(Debug, Error)]
+pub enum AppError {
+    /// Bad command line.
+    #[error("{0}")]
+    Usage(String),
+    /// The input is not something app2nix can package (wrong file type,
+    /// missing file, architecture mismatch, already in nixpkgs).
+    #[error("{0}")]
+    Input(String),
+    #[error("Download failed: {0}")]
+    Download(String),
+    #[error("Failed to extract package: {0}")]
+    Extract(String),
+    /// Shared libraries that no nixpkgs attr could be found for.
+    #[error("Could not resolve libraries: {}", .0.join(", "))]
+    Resolve(Vec<String>),
+    #[error("Failed to generate output: {0}")]
+    Generate(String),
+}
+
+impl AppError {
+    /// Exit codes, also listed in `--help`:
+    /// 1 usage, 2 bad input, 3 download, 4 extract, 5 unresolved libraries,
+    /// 6 generation.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AppError::Usage(_) => 1,
+            AppError::Input(_) => 2,
+            AppError::Download(_) => 3,
+            AppError::Extract(_) => 4,
+            AppError::Resolve(_) => 5,
+            AppError::Generate(_) => 6,
+        }
+    }
+}
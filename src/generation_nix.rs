@@ -1,15 +1,588 @@
-use crate::structs::{PackageType, PackageInfo};
+use std::collections::HashSet;
 
-pub fn generate_nix_content(
-    pkg_type: &PackageType,
+use crate::elf;
+use crate::license;
+use crate::naming;
+use crate::readfile_nix::{AUDIO_LIBS, DRIVER_LIBS, PKGS_I686, PKGS_MUSL};
+use crate::structs::{
+    AppKind, BundledLib, AudioBackend, DataKind, DesktopEntry, DisplayServer, DownloadAuth, ExtraFiles, Fetcher, GenerateOptions, Hook, OptLayout, PackageInfo, PackageType,
+    Provenance, Source, SourcePin, UnpackStrategy,
+};
+
+/// Runtime used when a framework-dependent .NET app ships no runtimeconfig
+/// framework reference we could parse.
+const DEFAULT_DOTNET_RUNTIME: &str = "dotnetCorePackages.runtime_8_0";
+
+/// Escapes `value` for a double-quoted Nix string: backslashes, quotes,
+/// `${` interpolations and control characters.
+pub fn escape_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '$' if chars.peek() == Some(&'{') => escaped.push_str("\\$"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes `value` for an indented `''` string such as a build phase:
+/// `''` and `${`.
+fn escape_indented(value: &str) -> String {
+    value.replace("''", "'''").replace("${", "''${")
+}
+
+/// Substitutes the `placeholders` of `template` in one pass over it, so a
+/// value is never scanned again: a description mentioning `{arch}` stays
+/// as it is. Where several placeholders start at the same position the
+/// first listed wins.
+pub fn fill_template(template: &str, placeholders: &[(&str, &str)]) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    'scan: while !rest.is_empty() {
+        for (placeholder, value) in placeholders {
+            if let Some(after) = rest.strip_prefix(placeholder) {
+                filled.push_str(value);
+                rest = after;
+                continue 'scan;
+            }
+        }
+        let mut chars = rest.chars();
+        if let Some(c) = chars.next() {
+            filled.push(c);
+        }
+        rest = chars.as_str();
+    }
+    filled
+}
+
+/// Strips trailing whitespace, collapses runs of blank lines (left by
+/// template placeholders that came out empty) and ends the text with a
+/// single newline, so the same package always renders byte for byte alike.
+pub fn normalize_whitespace(content: &str) -> String {
+    let mut normalized = String::with_capacity(content.len());
+    let mut blank = true;
+    for line in content.lines().map(str::trim_end) {
+        if line.is_empty() {
+            if blank {
+                continue;
+            }
+            blank = true;
+        } else {
+            blank = false;
+        }
+        normalized.push_str(line);
+        normalized.push('\n');
+    }
+    while normalized.ends_with("\n\n") {
+        normalized.pop();
+    }
+    normalized
+}
+
+/// Folds a multi-line description into the single line `meta.description`
+/// is meant to be.
+fn fold_description(description: &str) -> String {
+    description.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// `meta.longDescription` as an indented string, empty without one.
+fn long_description_attr(text: &str) -> String {
+    if text.trim().is_empty() {
+        return String::new();
+    }
+    let lines: String = text
+        .lines()
+        .map(|l| if l.trim().is_empty() { "\n".to_string() } else { format!("      {}\n", escape_indented(l)) })
+        .collect();
+    format!("    longDescription = ''\n{}    '';\n", lines)
+}
+
+/// `meta.homepage`, after a comment naming the upstream maintainer.
+fn homepage_lines(pkg_info: &PackageInfo) -> String {
+    let mut lines = String::new();
+    if let Some(maintainer) = &pkg_info.maintainer {
+        lines.push_str(&format!("    # Upstream maintainer: {}\n", maintainer.replace('\n', " ")));
+    }
+    if let Some(homepage) = &pkg_info.homepage {
+        lines.push_str(&format!("    homepage = \"{}\";\n", escape_string(homepage)));
+    }
+    lines
+}
+
+/// Maps a path inside the deb payload to where `install_tree` copies it:
+/// `usr/*` into `$out`, `opt/*` according to `--opt-layout`.
+fn installed_path(rel: &str, name: &str, layout: OptLayout) -> String {
+    if let Some(rest) = rel.strip_prefix("opt/") {
+        return match layout {
+            OptLayout::Flatten => format!("$out/{}", rest),
+            OptLayout::Opt => format!("$out/opt/{}", rest),
+            OptLayout::Lib => {
+                let inner = rest.split_once('/').map_or(rest, |(_, inner)| inner);
+                format!("$out/lib/{}/{}", name, inner)
+            }
+        };
+    }
+    format!("$out/{}", rel.strip_prefix("usr/").unwrap_or(rel))
+}
+
+/// Sets the loader of i686 binaries in an x86_64 package, which autoPatchelf
+/// leaves alone as it only knows the stdenv's own loader.
+fn interpreter_lines(pkg_info: &PackageInfo, options: &GenerateOptions, system: &str) -> Vec<String> {
+    if system != "x86_64-linux" {
+        return Vec::new();
+    }
+    let mut by_loader: Vec<(String, Vec<String>)> = Vec::new();
+    for binary in &pkg_info.binaries {
+        let Some(interpreter) = binary.interpreter.as_deref() else {
+            continue;
+        };
+        if elf::loader_system(interpreter) != Some("i686-linux") {
+            continue;
+        }
+        let loader = if interpreter.contains("ld-musl") {
+            format!("${{pkgs.{}.musl}}/lib/ld-musl-i386.so.1", PKGS_I686)
+        } else {
+            format!("${{pkgs.{}.glibc}}/lib/ld-linux.so.2", PKGS_I686)
+        };
+        let path = format!("\"{}\"", installed_path(&binary.path, &pkg_info.name, options.opt_layout));
+        match by_loader.iter_mut().find(|(l, _)| *l == loader) {
+            Some((_, paths)) => paths.push(path),
+            None => by_loader.push((loader, vec![path])),
+        }
+    }
+    let mut lines = Vec::new();
+    if !by_loader.is_empty() {
+        lines.push("    # 32-bit binaries: autoPatchelf only sets the x86_64 loader".to_string());
+    }
+    for (loader, paths) in by_loader {
+        for path in paths {
+            lines.push(format!("    patchelf --set-interpreter {} {}", loader, path));
+        }
+    }
+    lines
+}
+
+/// The start of the installPhase: copies the payload into `$out`, keeping
+/// symlinks and modes (`cp -a`), re-points absolute and alternatives links
+/// into `$out`, drops links to files the package does not ship, then links executables into `$out/bin` with `--bin-links`.
+fn install_tree(pkg_info: &PackageInfo, options: &GenerateOptions, system: &str) -> String {
+    let mut lines = vec![
+        "    mkdir -p $out".to_string(),
+        "    cp -a usr/. $out/ 2>/dev/null || true".to_string(),
+        "    # Packages from before the /usr merge ship /bin, /sbin and /lib".to_string(),
+        "    for dir in bin sbin lib lib64; do".to_string(),
+        "      if [ -d $dir ]; then mkdir -p $out/$dir && cp -a $dir/. $out/$dir/; fi".to_string(),
+        "    done".to_string(),
+    ];
+    lines.push("    if [ -d etc ]; then mkdir -p $out/etc && cp -a etc/. $out/etc/; fi".to_string());
+    lines.push(match options.opt_layout {
+        OptLayout::Flatten => "    cp -a opt/. $out/ 2>/dev/null || true".to_string(),
+        OptLayout::Opt => "    if [ -d opt ]; then mkdir -p $out/opt && cp -a opt/. $out/opt/; fi".to_string(),
+        OptLayout::Lib => format!(
+            "    if [ -d opt ]; then mkdir -p $out/lib/{name} && cp -a opt/*/. $out/lib/{name}/; fi",
+            name = pkg_info.name
+        ),
+    });
+    if !pkg_info.links.is_empty() {
+        let installed_path = |rel: &str| installed_path(rel, &pkg_info.name, options.opt_layout);
+        let mut dirs: Vec<String> = pkg_info
+            .links
+            .iter()
+            .map(|l| {
+                let link = installed_path(&l.link);
+                link.rsplit_once('/').map_or(link.clone(), |(dir, _)| dir.to_string())
+            })
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+        lines.push("    # Absolute symlinks, links between usr and opt and update-alternatives links, relative within $out".to_string());
+        lines.push(format!("    mkdir -p {}", dirs.join(" ")));
+        for link in &pkg_info.links {
+            let (target, link) = (installed_path(&link.target), installed_path(&link.link));
+            // `bin/foo -> ../usr/bin/foo` installs both names as one file
+            if target != link {
+                lines.push(format!("    ln -sfr \"{}\" \"{}\"", target, link));
+            }
+        }
+    }
+    if !pkg_info.dangling_links.is_empty() {
+        lines.push("    # Links to files the package does not ship".to_string());
+        for link in &pkg_info.dangling_links {
+            lines.push(format!("    rm -f \"{}\"", installed_path(link, &pkg_info.name, options.opt_layout)));
+        }
+    }
+    let replaced = replaced_libs(pkg_info, options);
+    if !replaced.is_empty() {
+        let installed_path = |rel: &str| installed_path(rel, &pkg_info.name, options.opt_layout);
+        let mut dirs: Vec<String> = Vec::new();
+        lines.push("    # Bundled copies of libraries nixpkgs provides; autoPatchelf links the nixpkgs builds".to_string());
+        for lib in replaced {
+            let path = installed_path(&lib.path);
+            let dir = path.rsplit_once('/').map_or(path.clone(), |(dir, _)| dir.to_string());
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+            lines.push(format!("    rm -f \"{}\"", path));
+        }
+        // Links such as libfoo.so -> libfoo.so.1 would dangle
+        lines.push(format!("    find {} -maxdepth 1 -xtype l -delete", dirs.iter().map(|d| format!("\"{}\"", d)).collect::<Vec<_>>().join(" ")));
+    }
+    if !pkg_info.non_executable.is_empty() {
+        lines.push("    # Executables the package ships without their exec bit".to_string());
+        for path in &pkg_info.non_executable {
+            lines.push(format!("    chmod +x \"{}\"", installed_path(path, &pkg_info.name, options.opt_layout)));
+        }
+    }
+    lines.extend(interpreter_lines(pkg_info, options, system));
+    lines.extend(extra_files_lines(&pkg_info.extra_files, &pkg_info.name));
+    if options.bin_links {
+        lines.extend([
+            "    mkdir -p $out/bin".to_string(),
+            "    find $out -path $out/bin -prune -o -type f -executable ! -name '*.so*' -print | while read -r f; do"
+                .to_string(),
+            "      target=\"$out/bin/$(basename \"$f\")\"".to_string(),
+            "      [ -e \"$target\" ] || ln -s \"$f\" \"$target\"".to_string(),
+            "    done".to_string(),
+        ]);
+    }
+    lines.join("\n")
+}
+
+/// Installs the man pages, completions and licenses copying the payload
+/// left where nothing looks for them.
+fn extra_files_lines(extra: &ExtraFiles, name: &str) -> Vec<String> {
+    let quoted = |paths: &[String]| paths.iter().map(|p| format!("\"{}\"", escape_indented(p))).collect::<Vec<_>>().join(" ");
+    let mut lines = Vec::new();
+    if !extra.man_pages.is_empty() {
+        lines.push(format!("    installManPage {}", quoted(&extra.man_pages)));
+    }
+    for (shell, files) in [
+        ("bash", &extra.bash_completions),
+        ("zsh", &extra.zsh_completions),
+        ("fish", &extra.fish_completions),
+    ] {
+        if !files.is_empty() {
+            lines.push(format!("    installShellCompletion --{} {}", shell, quoted(files)));
+        }
+    }
+    if !extra.licenses.is_empty() {
+        lines.push(format!("    install -Dm644 -t $out/share/licenses/{} {}", name, quoted(&extra.licenses)));
+    }
+    if !lines.is_empty() {
+        lines.insert(0, "    # Man pages, completions and licenses outside the standard locations".to_string());
+    }
+    lines
+}
+
+fn runtime_wrapper(pkg_info: &PackageInfo, options: &GenerateOptions) -> String {
+    let installed_path = |rel: &str| installed_path(rel, &pkg_info.name, options.opt_layout);
+    let mut lines = Vec::new();
+    match &pkg_info.kind {
+        AppKind::DotNet { entry, apphost, runtime } => {
+            // Without an apphost the app is started through the nixpkgs `dotnet` host
+            let runtime = match apphost {
+                Some(_) => runtime.as_deref(),
+                None => Some(runtime.as_deref().unwrap_or(DEFAULT_DOTNET_RUNTIME)),
+            };
+            match (apphost, runtime) {
+                (Some(apphost), _) => lines.push(format!(
+                    "    makeWrapper \"{}\" \"$out/bin/{}\"",
+                    installed_path(apphost),
+                    pkg_info.name
+                )),
+                (None, runtime) => {
+                    lines.push(format!(
+                        "    makeWrapper \"${{pkgs.{}}}/bin/dotnet\" \"$out/bin/{}\"",
+                        runtime.unwrap_or(DEFAULT_DOTNET_RUNTIME),
+                        pkg_info.name
+                    ));
+                    lines.push(format!("      --add-flags \"{}\"", installed_path(entry)));
+                }
+            }
+            if let Some(runtime) = runtime {
+                lines.push(format!("      --set DOTNET_ROOT \"${{pkgs.{}}}/share/dotnet\"", runtime));
+            }
+            lines.extend(env_flags(options).into_iter().map(|flag| format!("      {}", flag)));
+        }
+        AppKind::Mono { entry } => {
+            lines.push(format!("    makeWrapper \"${{pkgs.mono}}/bin/mono\" \"$out/bin/{}\"", pkg_info.name));
+            lines.push(format!("      --add-flags \"{}\"", installed_path(entry)));
+            lines.extend(env_flags(options).into_iter().map(|flag| format!("      {}", flag)));
+        }
+        AppKind::Python { entries, site_packages, .. } => {
+            let python_path = site_packages
+                .iter()
+                .map(|p| installed_path(p))
+                .collect::<Vec<_>>()
+                .join(":");
+            for entry in entries.iter().map(|e| installed_path(e)) {
+                if !entry.starts_with("$out/bin/") || python_path.is_empty() {
+                    continue;
+                }
+                lines.push(format!(
+                    "    wrapProgram \"{}\" --prefix PYTHONPATH : \"{}\"",
+                    entry, python_path
+                ));
+            }
+            return lines.join("\n");
+        }
+        AppKind::Static { binaries } => {
+            for binary in binaries.iter().map(|b| installed_path(b)) {
+                if binary.starts_with("$out/bin/") {
+                    continue;
+                }
+                let fname = binary.rsplit('/').next().unwrap_or(&binary);
+                lines.push(format!("    ln -sf \"{}\" \"$out/bin/{}\"", binary, fname));
+            }
+            return lines.join("\n");
+        }
+        AppKind::Native | AppKind::ArchIndependent | AppKind::Dkms { .. } | AppKind::Data { .. } => {}
+    }
+    lines.join(" \\\n")
+}
+
+/// Chromium's switch to Wayland, the way nixpkgs wraps Electron apps: only
+/// with `NIXOS_OZONE_WL` set and inside a Wayland session. `''$` escapes the
+/// shell expansion from Nix.
+const OZONE_WAYLAND_FLAGS: &str = "--add-flags \"''${NIXOS_OZONE_WL:+''${WAYLAND_DISPLAY:+--ozone-platform-hint=auto --enable-features=WaylandWindowDecorations --enable-wayland-ime=true}}\"";
+
+/// Wrapper arguments selecting the display server for the app's toolkit:
+/// Chromium switches for Electron, `GDK_BACKEND` for GTK and
+/// `QT_QPA_PLATFORM` for Qt. Without `--wayland`/`--x11-only` only Electron
+/// gets the opt-in `NIXOS_OZONE_WL` switch.
+pub fn display_flags(pkg_info: &PackageInfo, options: &GenerateOptions) -> Vec<String> {
+    let links = |stems: &[&str]| {
+        pkg_info
+            .referenced_libs
+            .iter()
+            .any(|l| stems.contains(&l.split(".so").next().unwrap_or(l)))
+    };
+    let gtk = links(&["libgtk-3", "libgdk-3", "libgtk-4"]);
+    let qt = links(&["libQt5Gui", "libQt6Gui"]);
+
+    let mut flags = Vec::new();
+    match options.display {
+        None => {
+            if pkg_info.electron {
+                flags.push(OZONE_WAYLAND_FLAGS.to_string());
+            }
+        }
+        Some(DisplayServer::Wayland) => {
+            if pkg_info.electron {
+                flags.push("--set-default NIXOS_OZONE_WL 1".to_string());
+                flags.push(OZONE_WAYLAND_FLAGS.to_string());
+            }
+            if gtk {
+                flags.push("--set-default GDK_BACKEND \"wayland,x11\"".to_string());
+            }
+            if qt {
+                flags.push("--set-default QT_QPA_PLATFORM \"wayland;xcb\"".to_string());
+            }
+        }
+        Some(DisplayServer::X11) => {
+            if pkg_info.electron {
+                flags.push("--add-flags \"--ozone-platform=x11\"".to_string());
+            }
+            if gtk {
+                flags.push("--set GDK_BACKEND x11".to_string());
+            }
+            if qt {
+                flags.push("--set QT_QPA_PLATFORM xcb".to_string());
+            }
+        }
+    }
+    flags
+}
+
+/// `--set` arguments for the `--wrapper-env` variables. Shell variables
+/// such as `$out` are expanded when the wrapper is built.
+pub fn env_flags(options: &GenerateOptions) -> Vec<String> {
+    options
+        .wrapper_env
+        .iter()
+        .map(|(name, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('`', "\\`");
+            format!("--set {} \"{}\"", name, escape_indented(&value))
+        })
+        .collect()
+}
+
+/// Libraries the chosen display server is reached through, for the
+/// wrapper's library path; toolkits often dlopen them.
+fn display_packages(pkg_info: &PackageInfo, options: &GenerateOptions) -> Vec<&'static str> {
+    if pkg_info.kind != AppKind::Native {
+        return Vec::new();
+    }
+    match options.display {
+        None => Vec::new(),
+        Some(DisplayServer::Wayland) => vec!["wayland", "libxkbcommon"],
+        Some(DisplayServer::X11) => vec![
+            "xorg.libX11",
+            "xorg.libXcursor",
+            "xorg.libXi",
+            "xorg.libXrandr",
+            "xorg.libxcb",
+        ],
+    }
+}
+
+/// A `makeWrapper` per declared snap app, run with `$SNAP` set to the
+/// installed tree the way snapd would.
+fn snap_wrappers(pkg_info: &PackageInfo, with_lib_path: bool, display: &[String]) -> String {
+    let snap_root = format!("$out/share/{}", pkg_info.name);
+    pkg_info
+        .entry_points
+        .iter()
+        .map(|entry| {
+            let command = escape_indented(&entry.command);
+            let (command, args) = command.split_once(' ').unwrap_or((&command, ""));
+            let mut lines = vec![
+                format!("    makeWrapper \"{}/{}\" \"$out/bin/{}\"", snap_root, command, entry.name),
+                format!("      --set SNAP \"{}\"", snap_root),
+            ];
+            if with_lib_path {
+                lines.push(
+                    "      --prefix LD_LIBRARY_PATH : \"${pkgs.lib.makeLibraryPath [\n{lib_packages}\n        ]}\""
+                        .to_string(),
+                );
+            }
+            lines.extend(display.iter().map(|flag| format!("      {}", flag)));
+            if !args.trim().is_empty() {
+                lines.push(format!("      --add-flags \"{}\"", args.trim()));
+            }
+            lines.join(" \\\n")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Soname stems (`libfoo` of `libfoo.so.1`) provided by each baseline
+/// package; baseline entries without a listed stem are never pruned.
+const BASELINE_PROVIDES: &[(&str, &[&str])] = &[
+    ("alsa-lib", &["libasound"]),
+    ("at-spi2-core", &["libatk-1.0", "libatk-bridge-2.0", "libatspi"]),
+    ("cairo", &["libcairo", "libcairo-gobject"]),
+    ("cups", &["libcups"]),
+    ("dbus", &["libdbus-1"]),
+    ("expat", &["libexpat"]),
+    ("glib", &["libglib-2.0", "libgobject-2.0", "libgio-2.0", "libgmodule-2.0", "libgthread-2.0"]),
+    ("gtk3", &["libgtk-3", "libgdk-3"]),
+    ("libdrm", &["libdrm"]),
+    ("libglvnd", &["libGL", "libEGL", "libGLESv2", "libGLX", "libOpenGL"]),
+    ("libnotify", &["libnotify"]),
+    ("libsecret", &["libsecret-1"]),
+    ("libxkbcommon", &["libxkbcommon", "libxkbcommon-x11"]),
+    ("mesa", &["libgbm", "libGL", "libEGL"]),
+    ("nspr", &["libnspr4", "libplc4", "libplds4"]),
+    ("nss", &["libnss3", "libnssutil3", "libsmime3", "libssl3"]),
+    ("pango", &["libpango-1.0", "libpangocairo-1.0", "libpangoft2-1.0"]),
+    ("systemd", &["libudev", "libsystemd"]),
+    ("vulkan-loader", &["libvulkan"]),
+    ("xorg.libX11", &["libX11", "libX11-xcb"]),
+    ("xorg.libXcomposite", &["libXcomposite"]),
+    ("xorg.libXdamage", &["libXdamage"]),
+    ("xorg.libXext", &["libXext"]),
+    ("xorg.libXfixes", &["libXfixes"]),
+    ("xorg.libXrandr", &["libXrandr"]),
+    ("xorg.libxcb", &["libxcb"]),
+];
+
+/// Whether a baseline package is linked or dlopen'ed by any scanned binary.
+fn baseline_referenced(pkg: &str, referenced_stems: &[&str]) -> bool {
+    match BASELINE_PROVIDES.iter().find(|(p, _)| *p == pkg) {
+        Some((_, stems)) => stems.iter().any(|s| referenced_stems.contains(s)),
+        None => true,
+    }
+}
+
+/// Baseline packages that no scanned binary links or dlopens. Nothing is
+/// pruned without scan results or with `--keep-baseline`.
+pub fn pruned_baseline(pkg_info: &PackageInfo, options: &GenerateOptions) -> Vec<&'static str> {
+    if options.keep_baseline || pkg_info.referenced_libs.is_empty() {
+        return Vec::new();
+    }
+    let stems: Vec<&str> = pkg_info
+        .referenced_libs
+        .iter()
+        .map(|l| l.split(".so").next().unwrap_or(l))
+        .collect();
+    BASELINE_PROVIDES
+        .iter()
+        .map(|(pkg, _)| *pkg)
+        .filter(|pkg| !baseline_referenced(pkg, &stems))
+        .collect()
+}
+
+/// Packages providing the sound server client libraries the payload links
+/// or dlopens. libjack comes from PipeWire's JACK replacement when the app
+/// talks to PipeWire or `--audio pipewire` is given, from `jack2` otherwise;
+/// `--audio` also adds its backend's client library if the scan found none.
+fn audio_packages(pkg_info: &PackageInfo, options: &GenerateOptions) -> Vec<&'static str> {
+    let uses = |soname: &str| {
+        pkg_info
+            .referenced_libs
+            .iter()
+            .any(|l| AUDIO_LIBS.contains(&l.as_str()) && l.starts_with(soname))
+    };
+    let forced = |backend: AudioBackend| pkg_info.kind == AppKind::Native && options.audio == Some(backend);
+    let jack = match options.audio {
+        Some(AudioBackend::Pipewire) => "pipewire.jack",
+        None if uses("libpipewire-") => "pipewire.jack",
+        _ => "jack2",
+    };
+
+    let mut packages = Vec::new();
+    if uses("libpulse") || forced(AudioBackend::Pulse) {
+        packages.push("libpulseaudio");
+    }
+    if uses("libpipewire-") || forced(AudioBackend::Pipewire) {
+        packages.push("pipewire");
+    }
+    if uses("libjack") || forced(AudioBackend::Jack) {
+        packages.push(jack);
+    }
+    packages
+}
+
+/// The bundled libraries deleted in favour of their nixpkgs builds: all
+/// nixpkgs has with `--prefer-system-libs`, otherwise the conflicts settled
+/// for nixpkgs.
+fn replaced_libs<'a>(pkg_info: &'a PackageInfo, options: &GenerateOptions) -> Vec<&'a BundledLib> {
+    let settled_for_nix = |lib: &BundledLib| {
+        pkg_info.lib_conflicts.iter().any(|c| c.use_nixpkgs && c.bundled.contains(&lib.path))
+    };
+    pkg_info
+        .bundled_libs
+        .iter()
+        .filter(|l| (options.prefer_system_libs && l.nixpkgs.is_some()) || settled_for_nix(l))
+        .collect()
+}
+
+/// The nixpkgs attrs replacing `replaced_libs`.
+fn system_libs<'a>(pkg_info: &'a PackageInfo, options: &GenerateOptions) -> Vec<&'a String> {
+    let mut attrs: Vec<&String> = replaced_libs(pkg_info, options).iter().filter_map(|l| l.nixpkgs.as_ref()).collect();
+    attrs.sort();
+    attrs.dedup();
+    attrs
+}
+
+/// The buildInputs and wrapper library path for `pkg_info`: the baseline for
+/// its runtime kind plus everything the scan resolved.
+pub fn input_lists(
     pkg_info: &PackageInfo,
-    url: &str,
-    sha256: &str,
-    _mode_upstream: bool
-) -> String {
+    system: &str,
+    options: &GenerateOptions,
+) -> (Vec<String>, Vec<String>) {
+    let prefix = format!("legacyPackages.{}.", system);
     let clean_pkg_path = |p: &str| {
-        let prefix = "legacyPackages.x86_64-linux.";
-        if let Some(stripped) = p.strip_prefix(prefix) {
+        if let Some(stripped) = p.strip_prefix(&prefix) {
             stripped.to_string()
         } else {
             p.to_string()
@@ -19,7 +592,7 @@ pub fn generate_nix_content(
     let deps_list: Vec<String> = pkg_info.deps.iter().map(|p| clean_pkg_path(p)).collect();
 
     // Standard build dependencies
-    let build_deps = vec![
+    let native_build_deps = vec![
         "alsa-lib",
         "at-spi2-core",
         "cairo",
@@ -48,7 +621,7 @@ pub fn generate_nix_content(
     ];
 
     // Library path packages for wrapProgram
-    let lib_path_packages = vec![
+    let native_lib_path_packages = vec![
         "libglvnd",
         "mesa",
         "libdrm",
@@ -68,17 +641,1068 @@ pub fn generate_nix_content(
         "systemd",
     ];
 
+    // Managed runtimes dlopen their native dependencies instead of linking them
+    let (build_deps, lib_path_packages) = match &pkg_info.kind {
+        AppKind::Native => (native_build_deps, native_lib_path_packages),
+        AppKind::DotNet { .. } => (
+            vec!["icu", "krb5", "openssl", "zlib"],
+            vec!["icu", "krb5", "openssl", "zlib"],
+        ),
+        AppKind::Mono { .. } => (vec!["libgdiplus"], vec!["libgdiplus"]),
+        AppKind::Python { .. }
+        | AppKind::Static { .. }
+        | AppKind::ArchIndependent
+        | AppKind::Dkms { .. }
+        | AppKind::Data { .. } => (vec![], vec![]),
+    };
+
+    // Audio libraries are both linked and dlopen'ed, so they go in both lists
+    let audio = audio_packages(pkg_info, options);
+    let build_deps: Vec<&str> = build_deps.into_iter().chain(audio.iter().copied()).collect();
+    let lib_path_packages: Vec<&str> = lib_path_packages.into_iter().chain(audio.iter().copied()).collect();
+
+    // Drop baseline entries no scanned binary links or dlopens
+    let pruned = pruned_baseline(pkg_info, options);
+    // A musl payload gets the musl builds, and no glibc
+    let for_libc = |packages: Vec<&str>| -> Vec<String> {
+        packages
+            .into_iter()
+            .filter(|p| !pkg_info.musl || *p != "glibc")
+            .map(|p| if pkg_info.musl { format!("{}.{}", PKGS_MUSL, p) } else { p.to_string() })
+            .collect()
+    };
+    let baseline = |packages: Vec<&str>| for_libc(packages.into_iter().filter(|p| !pruned.contains(p)).collect());
+    let build_deps = baseline(build_deps);
+    let mut lib_path_packages = baseline(lib_path_packages);
+    // The display server libraries stay even where the scan saw no reference
+    for package in for_libc(display_packages(pkg_info, options)) {
+        if !lib_path_packages.contains(&package) {
+            lib_path_packages.push(package);
+        }
+    }
+
     // Combine resolved deps with standard build deps
-    let mut all_build_deps: Vec<String> = build_deps.iter().map(|s| s.to_string()).collect();
+    let mut all_build_deps: Vec<String> = build_deps;
     for dep in &deps_list {
-        let clean_dep = dep.split('.').last().unwrap_or(dep);
+        let clean_dep = if dep.starts_with(PKGS_I686) || dep.starts_with(PKGS_MUSL) {
+            dep.as_str()
+        } else {
+            dep.split('.').next_back().unwrap_or(dep)
+        };
         if !all_build_deps.contains(&clean_dep.to_string()) {
             all_build_deps.push(clean_dep.to_string());
         }
     }
+    for interpreter in &pkg_info.script_interpreters {
+        if !all_build_deps.contains(interpreter) {
+            all_build_deps.push(interpreter.clone());
+        }
+    }
+    let preset = options.preset.map_or(&[][..], |p| p.build_inputs()).iter().map(|p| p.to_string());
+    for extra in system_libs(pkg_info, options).into_iter().cloned().chain(preset).chain(options.extra_build_inputs.clone()) {
+        if !all_build_deps.contains(&extra) {
+            all_build_deps.push(extra.clone());
+        }
+        if !lib_path_packages.contains(&extra) {
+            lib_path_packages.push(extra);
+        }
+    }
+    all_build_deps.retain(|d| !options.exclude_deps.contains(d));
+    lib_path_packages.retain(|d| !options.exclude_deps.contains(d));
     all_build_deps.sort();
     all_build_deps.dedup();
 
+    (all_build_deps, lib_path_packages)
+
+}
+
+/// `unpackPhase` body for each package format, and the nixpkgs attr of the
+/// tool it needs.
+fn unpack_phase(pkg_type: &PackageType, strategy: UnpackStrategy) -> (&'static str, &'static str) {
+    match pkg_type {
+        PackageType::Deb if strategy == UnpackStrategy::Dpkg => ("    dpkg-deb -x $src .", "pkgs.dpkg"),
+        // Whatever its compression; one package's payload is gone before the next
+        PackageType::Deb => ("    ar -x $src\n    tar -xf data.tar*\n    rm data.tar*", "pkgs.dpkg"),
+        // Pacman metadata files sit next to usr/ at the archive root
+        PackageType::Pacman => (
+            "    tar -xf $src --exclude=.PKGINFO --exclude=.MTREE --exclude=.BUILDINFO --exclude=.INSTALL",
+            "pkgs.zstd",
+        ),
+        PackageType::Apk => ("    tar -xzf $src --ignore-zeros --anchored --exclude='.*'", "pkgs.gnutar"),
+        PackageType::Snap => ("    unsquashfs -no-xattrs -d root $src\n    cd root", "pkgs.squashfsTools"),
+        // Store paths are read-only; later phases edit the copy in place
+        PackageType::Directory => ("    cp -r $src/. .\n    chmod -R u+w .", "pkgs.coreutils"),
+    }
+}
+
+/// Whether `sources` are the same package for several systems.
+fn per_system(sources: &[Source]) -> bool {
+    sources.len() > 1 && sources.iter().all(|s| s.system.is_some())
+}
+
+/// The systems `sources` cover, or just `system`.
+pub fn source_systems(sources: &[Source], system: &str) -> Vec<String> {
+    if per_system(sources) {
+        sources.iter().filter_map(|s| s.system.clone()).collect()
+    } else {
+        vec![system.to_string()]
+    }
+}
+
+/// The environment variable fetchurl reads the value of header `name` from.
+fn header_var(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    format!("APP2NIX_HEADER_{}", name)
+}
+
+/// The host name of `url`, without userinfo and port.
+pub fn url_host(url: &str) -> Option<&str> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split('/').next().unwrap_or_default();
+    let host = authority.rsplit('@').next().unwrap_or(authority);
+    host.split(':').next()
+}
+
+/// fetchurl's `netrcPhase` for a download behind authentication. The login
+/// and header values come from impure environment variables, so no secret
+/// ends up in the expression or the store.
+fn fetch_auth(source: &Source, auth: &DownloadAuth, indent: &str) -> String {
+    let Some(host) = url_host(&source.url) else {
+        return String::new();
+    };
+    if auth.is_empty() {
+        return String::new();
+    }
+
+    let mut vars = Vec::new();
+    let mut phase = Vec::new();
+    if auth.login() {
+        vars.extend(["APP2NIX_LOGIN".to_string(), "APP2NIX_PASSWORD".to_string()]);
+        phase.push(format!(
+            "echo \"machine {} login $APP2NIX_LOGIN password $APP2NIX_PASSWORD\" > netrc",
+            host
+        ));
+    } else {
+        // fetchurl passes --netrc-file whenever there is a netrcPhase
+        phase.push("touch netrc".to_string());
+    }
+    if !auth.headers.is_empty() {
+        for header in &auth.headers {
+            let name = header.split_once(':').map_or(header.as_str(), |(name, _)| name).trim();
+            let var = header_var(name);
+            phase.push(format!("echo \"header = \\\"{}: ${}\\\"\" >> auth.curlrc", name, var));
+            if !vars.contains(&var) {
+                vars.push(var);
+            }
+        }
+        phase.push("curlOpts=\"$curlOpts --config $PWD/auth.curlrc\"".to_string());
+    }
+    let vars: Vec<String> = vars.iter().map(|v| format!("\"{}\"", v)).collect();
+    let phase: String = phase.iter().map(|line| format!("{}  {}\n", indent, line)).collect();
+    format!(
+        "{i}# Behind authentication: set these variables in the nix-daemon's\n\
+         {i}# environment, or add the file to the store beforehand with\n\
+         {i}# nix-store --add-fixed sha256 <file>.\n\
+         {i}netrcImpureEnvVars = [ {} ];\n\
+         {i}netrcPhase = ''\n{}{i}'';\n",
+        vars.join(" "),
+        phase,
+        i = indent
+    )
+}
+
+/// The `url` attribute of a fetchurl, or `urls` when the source has
+/// mirrors, indented by `indent`, followed by any login it needs.
+fn fetch_url(source: &Source, auth: &DownloadAuth, indent: &str) -> String {
+    let url = if source.mirrors.is_empty() {
+        format!("{}url = \"{}\";\n", indent, escape_string(&source.url))
+    } else {
+        let urls: String = std::iter::once(&source.url)
+            .chain(&source.mirrors)
+            .map(|url| format!("{}  \"{}\"\n", indent, escape_string(url)))
+            .collect();
+        format!("{}urls = [\n{}{}];\n", indent, urls, indent)
+    };
+    url + &fetch_auth(source, auth, indent)
+}
+
+/// Whether `url` is an S3 or GCS object rather than an HTTP(S) or FTP URL.
+pub fn object_store(url: &str) -> bool {
+    url.starts_with("s3://") || url.starts_with("gs://")
+}
+
+/// Whether `url` is a presigned S3 or GCS URL, which stops working after a
+/// while.
+fn presigned(url: &str) -> bool {
+    url.contains("X-Amz-Signature=") || url.contains("X-Goog-Signature=")
+}
+
+/// The file a URL names, without its query (`jdk.tar.gz?AuthParam=...`).
+fn url_file_name(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    match path.rsplit('/').next() {
+        Some(file) if !file.is_empty() => file,
+        _ => "source",
+    }
+}
+
+/// A requireFile call, its attributes indented by `indent`, with `steps`
+/// telling the user how to get the file into the store.
+fn require_file(file: &str, hash: &str, steps: &str, indent: &str) -> String {
+    let message: String = steps.lines().map(|l| format!("{}  {}\n", indent, escape_indented(l))).collect();
+    format!(
+        "pkgs.requireFile {{\n\
+         {i}name = \"{file}\";\n\
+         {i}hash = \"{hash}\";\n\
+         {i}message = ''\n\
+         {message}\
+         {i}'';\n\
+         {closing}}}",
+        i = indent,
+        file = escape_string(file),
+        hash = hash,
+        message = message,
+        closing = &indent[2..]
+    )
+}
+
+/// The fetcher call for `source`, its attributes indented by `indent`:
+/// fetchurl, or requireFile with `--fetch requireFile` and for an object
+/// store URL only the user's credentials can read.
+fn fetch_call(source: &Source, auth: &DownloadAuth, fetcher: Fetcher, indent: &str) -> String {
+    let closing = &indent[2..];
+    if source.tree.is_some() {
+        let literal = source.url.chars().all(|c| c.is_ascii_alphanumeric() || "/._+-".contains(c));
+        let path = if literal { source.url.clone() } else { format!("/. + \"{}\"", escape_string(&source.url)) };
+        return format!(
+            "builtins.path {{\n\
+             {i}# The directory app2nix scanned; it has to exist where this is evaluated\n\
+             {i}path = {path};\n\
+             {i}name = \"source\";\n\
+             {i}sha256 = \"{hash}\";\n\
+             {closing}}}",
+            i = indent,
+            path = path,
+            hash = source.sha256,
+            closing = closing
+        );
+    }
+    if object_store(&source.url) {
+        let file = url_file_name(&source.url);
+        let copy = if source.url.starts_with("s3://") { "aws s3 cp" } else { "gcloud storage cp" };
+        let steps = format!(
+            "Download {file} with your credentials and add it to the store:\n  {copy} {url} {file}\n  nix-store --add-fixed sha256 {file}",
+            file = file,
+            copy = copy,
+            url = source.url
+        );
+        return require_file(file, &source.sha256, &steps, indent);
+    }
+    if fetcher == Fetcher::RequireFile {
+        let file = url_file_name(&source.url);
+        // A local input was downloaded by hand already; its URL is a path
+        let download = match source.url.split_once("://") {
+            Some((scheme, rest)) if scheme != "file" => format!(
+                "{file} cannot be downloaded automatically. Accept its license and\ndownload it (signing in at {scheme}://{host} if asked) from\n  {url}\nThen add it to the store:",
+                file = file,
+                scheme = scheme,
+                host = rest.split('/').next().unwrap_or(rest),
+                url = source.url
+            ),
+            _ => format!(
+                "{} cannot be downloaded automatically. Get it from its vendor,\naccepting its license, and add it to the store:",
+                file
+            ),
+        };
+        let steps = format!("{}\n  nix-store --add-fixed sha256 {}", download, file);
+        return require_file(file, &source.sha256, &steps, indent);
+    }
+    let expires = if presigned(&source.url) {
+        format!(
+            "{}# A presigned URL: it expires, but the file stays in the store by its hash.\n",
+            indent
+        )
+    } else {
+        String::new()
+    };
+    format!(
+        "pkgs.fetchurl {{\n{}{}{}sha256 = \"{}\";\n{}}}",
+        expires,
+        fetch_url(source, auth, indent),
+        indent,
+        source.sha256,
+        closing
+    )
+}
+
+/// The `src` attribute, or `srcs` when several packages are merged. One
+/// package per architecture gives a `src` picked by the host system.
+fn src_attr(sources: &[Source], auth: &DownloadAuth, fetcher: Fetcher) -> String {
+    if per_system(sources) {
+        return "  src = srcs.${pkgs.stdenv.hostPlatform.system} or (throw \"Unsupported system: ${pkgs.stdenv.hostPlatform.system}\");\n"
+            .to_string();
+    }
+    match sources {
+        [source] => format!("  src = {};\n", fetch_call(source, auth, fetcher, "    ")),
+        _ => {
+            let fetches: String = sources
+                .iter()
+                .map(|s| format!("    ({})\n", fetch_call(s, auth, fetcher, "      ")))
+                .collect();
+            format!("  srcs = [\n{}  ];\n", fetches)
+        }
+    }
+}
+
+/// The `let` binding the per-system sources to `srcs`, the table `src`
+/// picks the host's package from, as nixpkgs' binary packages do.
+fn srcs_table(sources: &[Source], auth: &DownloadAuth, fetcher: Fetcher) -> String {
+    let fetches: String = sources
+        .iter()
+        .map(|s| {
+            format!(
+                "    {} = {};\n",
+                s.system.as_deref().unwrap_or_default(),
+                fetch_call(s, auth, fetcher, "      ")
+            )
+        })
+        .collect();
+    format!("\n\nlet\n  srcs = {{\n{}  }};\nin", fetches)
+}
+
+/// Runs the single-package `unpack` body once per entry of `$srcs`.
+fn unpack_each(unpack: &str, sources: &[Source]) -> String {
+    if sources.len() < 2 || per_system(sources) {
+        return unpack.to_string();
+    }
+    let body = unpack
+        .lines()
+        .map(|l| format!("  {}", l.replace("$src", "$s")))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("    for s in $srcs; do\n{}\n    done", body)
+}
+
+/// Escapes the ERE metacharacters in `text`.
+fn regex_escape(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| {
+            let escape = ".[]()*+?{}|^$\\".contains(c);
+            escape.then_some('\\').into_iter().chain([c])
+        })
+        .collect()
+}
+
+/// Where newer versions of a fetched package file can be looked up.
+struct VersionSource<'a> {
+    /// The version as it appears in the URL.
+    current: &'a str,
+    /// `owner/repo` of a GitHub release asset.
+    github: Option<String>,
+    /// The download directory and an ERE capturing the version from the
+    /// file names listed in it.
+    dir: &'a str,
+    regex: String,
+}
+
+/// `None` if the URL is local or does not contain the version.
+fn version_source<'a>(pkg_info: &'a PackageInfo, source: &'a Source) -> Option<VersionSource<'a>> {
+    if source.url.starts_with("file://") {
+        return None;
+    }
+    let (dir, file) = source.url.rsplit_once('/').unwrap_or(("", &source.url));
+    // File names carry the distro version, without the epoch
+    let original = pkg_info.original_version.as_deref().unwrap_or(&pkg_info.version);
+    let unepoched = original.split_once(':').map_or(original, |(_, v)| v);
+    let current = [original, unepoched, pkg_info.version.as_str()]
+        .into_iter()
+        .find(|v| !v.is_empty() && file.contains(v))?;
+
+    let github = source
+        .url
+        .strip_prefix("https://github.com/")
+        .filter(|rest| rest.contains("/releases/download/"))
+        .and_then(|rest| {
+            let mut parts = rest.splitn(3, '/');
+            Some(format!("{}/{}", parts.next()?, parts.next()?))
+        });
+    let (prefix, suffix) = file.split_once(current).unwrap_or((file, ""));
+    let regex = format!(
+        "{}([0-9][A-Za-z0-9.+~-]*){}",
+        regex_escape(prefix),
+        regex_escape(suffix)
+    );
+    Some(VersionSource {
+        current,
+        github,
+        dir,
+        regex,
+    })
+}
+
+/// `passthru.updateScript` for a single fetched package: looks up the
+/// newest version (latest GitHub release, or the newest matching file in
+/// the download directory, which covers apt pools), then rewrites version,
+/// URL and hash in default.nix. Needs the version in the URL.
+fn update_script(pkg_info: &PackageInfo, sources: &[Source], options: &GenerateOptions) -> String {
+    if options.pin.is_some() {
+        return "  # Updated by nvfetcher, see nvfetcher.toml\n\n".to_string();
+    }
+    if per_system(sources) {
+        return "  # No updateScript: the per-system sources have to be bumped together by hand\n\n".to_string();
+    }
+    let [source] = sources else {
+        return "  # No updateScript: merged packages have to be bumped together by hand\n\n".to_string();
+    };
+    if source.url.starts_with("file://") {
+        return "  # No updateScript: generated from a local file, there is nothing to poll\n\n".to_string();
+    }
+    if source.tree.is_some() {
+        return "  # No updateScript: generated from a directory, there is nothing to poll\n\n".to_string();
+    }
+    if object_store(&source.url) || presigned(&source.url) {
+        return "  # No updateScript: the package comes from an object store, bump it by hand\n\n".to_string();
+    }
+    let Some(versions) = version_source(pkg_info, source) else {
+        return "  # No updateScript: the download URL does not contain the version\n\n".to_string();
+    };
+
+    let (update_source, regex, latest_command) = match &versions.github {
+        Some(repo) => (
+            format!("latest release of github.com/{}", repo),
+            "tag_name v?(.+)".to_string(),
+            format!(
+                "curl -fsSL https://api.github.com/repos/{}/releases/latest | sed -nE 's/.*\"tag_name\": *\"v?([^\"]+)\".*/\\1/p'",
+                repo
+            ),
+        ),
+        None => (
+            format!("directory listing of {}/", versions.dir),
+            versions.regex.clone(),
+            format!(
+                "curl -fsSL \"{}/\" | grep -oE '{}' | sed -E 's/{}/\\1/' | sort -Vu | tail -n1",
+                versions.dir, versions.regex, versions.regex
+            ),
+        ),
+    };
+
+    // The new version, normalized the way default.nix's was
+    let mut version = "\"$latest\"".to_string();
+    if naming::normalize_version(versions.current, pkg_info.package_type) != naming::sanitize_version(versions.current) {
+        let revision = if pkg_info.package_type == PackageType::Apk { "-r" } else { "-" };
+        version = format!("\"''${{latest%{}*}}\"", revision);
+    }
+    if versions.current.contains('~') {
+        version.push_str("\n    version=''${version//\\~/-pre-}");
+    }
+
+    let url = escape_indented(&source.url);
+    fill_template(
+        include_str!("../templates/update_script.in"),
+        &[
+            ("{source}", &update_source),
+            ("{version}", &version),
+            ("{regex}", &regex),
+            ("{url_template}", &url.replace(versions.current, "${version}")),
+            ("{latest_command}", &escape_indented(&latest_command)),
+            ("{current}", versions.current),
+            // The mirrors in `urls` carry the version too
+            ("{url_lines}", if source.mirrors.is_empty() { "/url = /" } else { "/urls = \\[/,/];/" }),
+            ("{url}", &url.replace(versions.current, "''${latest}")),
+            ("{hash}", &source.sha256),
+            ("{name}", &pkg_info.name),
+        ],
+    )
+}
+
+/// `meta.license` for the licenses the package states. Anything not known
+/// to be free is `unfree`, which `--allow-unfree-note` explains.
+fn license_line(licenses: &[String], unfree_note: bool) -> String {
+    let attrs = license::nix_licenses(licenses);
+    let note = if unfree_note && attrs.contains(&"unfree") {
+        "    # Unfree: build with NIXPKGS_ALLOW_UNFREE=1 nix-build (add --impure to nix build),\n    # or set allowUnfree = true in the nixpkgs config.\n"
+    } else {
+        ""
+    };
+    match attrs.as_slice() {
+        [attr] => format!("{}    license = pkgs.lib.licenses.{};\n", note, attr),
+        _ => format!("{}    license = with pkgs.lib.licenses; [ {} ];\n", note, attrs.join(" ")),
+    }
+}
+
+/// `passthru.app2nix`, what `--provenance` recorded, so it can be read
+/// back with `nix eval` from the package itself.
+fn provenance_passthru(provenance: &Provenance) -> String {
+    let inputs: String = provenance
+        .inputs
+        .iter()
+        .map(|(name, sha256)| format!("      {{ name = \"{}\"; sha256 = \"{}\"; }}\n", escape_string(name), sha256))
+        .collect();
+    let rev = provenance
+        .nixpkgs_rev
+        .as_deref()
+        .map_or("null".to_string(), |r| format!("\"{}\"", escape_string(r)));
+    format!(
+        "  passthru.app2nix = {{\n    version = \"{}\";\n    command = \"{}\";\n    inputs = [\n{}    ];\n    nixpkgs = \"{}\";\n    nixpkgsRev = {};\n    date = \"{}\";\n  }};\n\n",
+        provenance.tool_version,
+        escape_string(&provenance.command),
+        inputs,
+        escape_string(&provenance.nixpkgs),
+        rev,
+        provenance.date
+    )
+}
+
+/// The `nvfetcher.toml` entry for `source`: the latest GitHub release or the
+/// newest file in the download directory, fetched by the URL with the
+/// version replaced by `$ver`. Without a version in the URL it is a
+/// `src.manual` pin.
+pub fn generate_nvfetcher_entry(pkg_info: &PackageInfo, source: &Source) -> String {
+    let key = if pkg_info.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        pkg_info.name.clone()
+    } else {
+        format!("\"{}\"", pkg_info.name)
+    };
+    let mut lines = vec![format!("[{}]", key)];
+    match version_source(pkg_info, source) {
+        Some(versions) => {
+            match &versions.github {
+                Some(repo) => {
+                    lines.push(format!("src.github = \"{}\"", repo));
+                    if source.url.contains(&format!("/v{}/", versions.current)) {
+                        lines.push("src.from_pattern = 'v(.+)'".to_string());
+                        lines.push("src.to_pattern = '\\1'".to_string());
+                    }
+                }
+                None => {
+                    lines.push(format!("src.webpage = '{}/'", versions.dir));
+                    lines.push(format!("src.regex = '{}'", versions.regex));
+                }
+            }
+            lines.push(format!("fetch.url = '{}'", source.url.replace(versions.current, "$ver")));
+        }
+        None => {
+            lines.push(format!("src.manual = \"{}\"", pkg_info.version));
+            lines.push(format!("fetch.url = '{}'", source.url));
+        }
+    }
+    format!("{}\n", lines.join("\n"))
+}
+
+/// Every library package the vendor binaries load, for
+/// `programs.nix-ld.libraries`. nix-ld only serves the 64-bit loader, so
+/// `pkgsi686Linux` entries are left out.
+pub fn nix_ld_libraries(pkg_info: &PackageInfo, system: &str, options: &GenerateOptions) -> Vec<String> {
+    let (build_inputs, lib_path_packages) = input_lists(pkg_info, system, options);
+    let mut libraries: Vec<String> = build_inputs
+        .into_iter()
+        .filter(|p| !p.starts_with(PKGS_I686) && !pkg_info.script_interpreters.contains(p))
+        .chain(lib_path_packages)
+        .collect();
+    libraries.sort();
+    libraries.dedup();
+    libraries
+}
+
+pub fn generate_nix_ld_snippet(pkg_info: &PackageInfo, system: &str, options: &GenerateOptions) -> String {
+    let libraries = nix_ld_libraries(pkg_info, system, options)
+        .iter()
+        .map(|p| format!("    pkgs.{}", p))
+        .collect::<Vec<_>>()
+        .join("\n");
+    fill_template(
+        include_str!("../templates/nix_ld.in"),
+        &[
+            ("{name}", &pkg_info.name),
+            ("{version}", &pkg_info.version),
+            ("{libraries}", &libraries),
+        ],
+    )
+}
+
+/// How to wire a data-only or kernel module package into the system.
+pub fn usage_hints(kind: &AppKind) -> Vec<&'static str> {
+    let kinds = match kind {
+        AppKind::Data { kinds } => kinds,
+        AppKind::Dkms { .. } => {
+            return vec![
+                "Kernel module: add it to boot.extraModulePackages, built with kernel = config.boot.kernelPackages.kernel.",
+            ];
+        }
+        _ => return Vec::new(),
+    };
+    kinds
+        .iter()
+        .map(|k| match k {
+            DataKind::Fonts => "Fonts: add this package to fonts.packages (NixOS) or to home.packages with fonts.fontconfig.enable (home-manager).",
+            DataKind::Icons => "Icons: add this package to environment.systemPackages; icon themes are found through XDG_DATA_DIRS.",
+            DataKind::Mime => "MIME data: add this package to environment.systemPackages with xdg.mime.enable = true.",
+            DataKind::Firmware => "Firmware: add this package to hardware.firmware (NixOS).",
+        })
+        .collect()
+}
+
+/// A NixOS module mapping the package's `/etc` conffiles to
+/// `environment.etc` entries pointing into `$out/etc`.
+pub fn generate_etc_module(pkg_info: &PackageInfo) -> String {
+    let entries = pkg_info
+        .conffiles
+        .iter()
+        .filter_map(|path| path.strip_prefix("/etc/"))
+        .map(escape_string)
+        .map(|rel| format!("    \"{}\".source = \"${{package}}/etc/{}\";", rel, rel))
+        .collect::<Vec<_>>()
+        .join("\n");
+    fill_template(
+        include_str!("../templates/etc_module.in"),
+        &[
+            ("{name}", &pkg_info.name),
+            ("{version}", &pkg_info.version),
+            ("{entries}", &entries),
+        ],
+    )
+}
+
+/// Where a payload path (absolute, as desktop files name them) ends up in
+/// the package bound to `package`.
+fn package_path(path: &str, pkg_info: &PackageInfo, options: &GenerateOptions) -> String {
+    let rel = path.trim_start_matches('/');
+    // deb.in wraps the main executable as bin/<name>
+    let in_bin = ["usr/bin/", "bin/", "usr/sbin/", "sbin/"].iter().any(|d| rel.starts_with(d));
+    if pkg_info.kind == AppKind::Native && !in_bin {
+        return format!("${{package}}/bin/{}", pkg_info.name);
+    }
+    installed_path(rel, &pkg_info.name, options.opt_layout).replacen("$out", "${package}", 1)
+}
+
+/// An `xdg.desktopEntries` attrset for a shipped launcher, with `Exec` and
+/// icon paths pointing into the package instead of `/usr` or `/opt`.
+fn hm_desktop_entry(entry: &DesktopEntry, pkg_info: &PackageInfo, options: &GenerateOptions) -> String {
+    let quote = |v: &str| format!("\"{}\"", escape_string(v));
+    let list = |values: &[String]| values.iter().map(|v| quote(v)).collect::<Vec<_>>().join(" ");
+
+    let mut lines = vec![format!("  xdg.desktopEntries.{} = {{", quote(&entry.id))];
+    lines.push(format!("    name = {};", quote(&entry.name)));
+    if let Some(generic_name) = &entry.generic_name {
+        lines.push(format!("    genericName = {};", quote(generic_name)));
+    }
+    if let Some(comment) = &entry.comment {
+        lines.push(format!("    comment = {};", quote(comment)));
+    }
+    if let Some(exec) = &entry.exec {
+        let (program, args) = exec.split_once(' ').unwrap_or((exec, ""));
+        let program = escape_string(program.trim_matches('"'));
+        // Escaped before `${package}` is put in, which must stay an interpolation
+        let program = if program.starts_with('/') { package_path(&program, pkg_info, options) } else { program };
+        let exec = if args.is_empty() { program } else { format!("{} {}", program, escape_string(args)) };
+        lines.push(format!("    exec = \"{}\";", exec));
+    }
+    if let Some(icon) = &entry.icon {
+        let icon = escape_string(icon);
+        let icon = if icon.starts_with('/') {
+            installed_path(icon.trim_start_matches('/'), &pkg_info.name, options.opt_layout)
+                .replacen("$out", "${package}", 1)
+        } else {
+            icon
+        };
+        lines.push(format!("    icon = \"{}\";", icon));
+    }
+    lines.push(format!("    terminal = {};", entry.terminal));
+    if !entry.categories.is_empty() {
+        lines.push(format!("    categories = [ {} ];", list(&entry.categories)));
+    }
+    if !entry.mime_types.is_empty() {
+        lines.push(format!("    mimeType = [ {} ];", list(&entry.mime_types)));
+    }
+    lines.push("  };".to_string());
+    lines.join("\n")
+}
+
+/// A Home Manager module adding the package to `home.packages`, its
+/// launchers to `xdg.desktopEntries` and its `/etc/xdg/autostart` entries
+/// to the user's `~/.config/autostart`.
+pub fn generate_hm_module(pkg_info: &PackageInfo, options: &GenerateOptions) -> String {
+    let desktop_entries: String = pkg_info
+        .desktop_entries
+        .iter()
+        .map(|e| format!("\n{}\n", hm_desktop_entry(e, pkg_info, options)))
+        .collect();
+    let autostart: String = pkg_info
+        .autostart
+        .iter()
+        .filter_map(|f| f.strip_prefix("etc/xdg/"))
+        .map(|rel| format!("  xdg.configFile.\"{}\".source = \"${{package}}/etc/xdg/{}\";\n", rel, rel))
+        .collect();
+    let autostart = if autostart.is_empty() { autostart } else { format!("\n{}", autostart) };
+    fill_template(
+        include_str!("../templates/hm_module.in"),
+        &[
+            ("{desktop_entries}", &desktop_entries),
+            ("{autostart}", &autostart),
+            ("{name}", &pkg_info.name),
+            ("{version}", &pkg_info.version),
+        ],
+    )
+}
+
+/// The `config` lines registering the package's units, udev rules and D-Bus
+/// files. Units are enabled the way a distro's preset would: system
+/// services for `multi-user.target`, user services for `default.target`.
+fn nixos_integration(pkg_info: &PackageInfo, options: &GenerateOptions) -> String {
+    let integration = &pkg_info.integration;
+    let services = |units: &[String]| -> Vec<String> {
+        units
+            .iter()
+            .filter_map(|u| u.strip_suffix(".service"))
+            .filter(|u| !u.ends_with('@'))
+            .map(str::to_string)
+            .collect()
+    };
+
+    let mut lines = Vec::new();
+    if !integration.system_units.is_empty() || !integration.user_units.is_empty() {
+        lines.push(format!(
+            "\n    # Units: {}",
+            [integration.system_units.as_slice(), integration.user_units.as_slice()].concat().join(", ")
+        ));
+        lines.push("    systemd.packages = [ cfg.package ];".to_string());
+        for service in services(&integration.system_units) {
+            lines.push(format!("    systemd.services.\"{}\".wantedBy = [ \"multi-user.target\" ];", escape_string(&service)));
+        }
+        for service in services(&integration.user_units) {
+            lines.push(format!("    systemd.user.services.\"{}\".wantedBy = [ \"default.target\" ];", escape_string(&service)));
+        }
+    }
+    if !integration.udev_rules.is_empty() {
+        lines.push(format!("\n    # udev rules: {}", integration.udev_rules.join(", ")));
+        lines.push("    services.udev.packages = [ cfg.package ];".to_string());
+    }
+    if !integration.dbus_files.is_empty() {
+        lines.push(format!("\n    # D-Bus: {}", integration.dbus_files.join(", ")));
+        lines.push("    services.dbus.packages = [ cfg.package ];".to_string());
+    }
+    lines.extend(security_wrappers(pkg_info, options));
+    lines.iter().map(|l| format!("{}\n", l)).collect()
+}
+
+/// `security.wrappers` entries for the files that need capabilities or
+/// setuid/setgid: the store keeps neither, so NixOS installs privileged
+/// copies under `/run/wrappers/bin`. A wrapper is named after its program,
+/// or after its whole path when two programs share a name.
+fn security_wrappers(pkg_info: &PackageInfo, options: &GenerateOptions) -> Vec<String> {
+    let program = |path: &str| path.rsplit('/').next().unwrap_or(path).to_string();
+    let mut lines = Vec::new();
+    let mut named = HashSet::new();
+    for file in &pkg_info.privileged {
+        let installed = installed_path(&file.path, &pkg_info.name, options.opt_layout);
+        let source = installed.replacen("$out", "${cfg.package}", 1);
+        let shared = pkg_info.privileged.iter().filter(|f| program(&f.path) == program(&file.path)).count() > 1;
+        let wrapper = if shared { file.path.trim_start_matches("usr/").replace('/', "-") } else { program(&file.path) };
+        if !named.insert(wrapper.clone()) {
+            continue;
+        }
+        lines.push(format!("\n    security.wrappers.\"{}\" = {{", escape_string(&wrapper)));
+        lines.push(format!("      source = \"{}\";", source));
+        lines.push("      owner = \"root\";".to_string());
+        lines.push("      group = \"root\";".to_string());
+        if let Some(capabilities) = &file.capabilities {
+            lines.push(format!("      capabilities = \"{}\";", escape_string(capabilities)));
+        }
+        if file.setuid {
+            lines.push("      setuid = true;".to_string());
+        }
+        if file.setgid {
+            lines.push("      setgid = true;".to_string());
+        }
+        lines.push("    };".to_string());
+    }
+    lines
+}
+
+/// A NixOS module exposing `programs.<name>.enable` and `.package`, wiring
+/// any shipped systemd units, udev rules and D-Bus files and privileged
+/// wrappers when enabled.
+pub fn generate_nixos_module(pkg_info: &PackageInfo, options: &GenerateOptions) -> String {
+    fill_template(
+        include_str!("../templates/nixos_module.in"),
+        &[
+            ("{integration}", &nixos_integration(pkg_info, options)),
+            ("{name}", &pkg_info.name),
+            ("{version}", &pkg_info.version),
+        ],
+    )
+}
+
+/// The command the image runs: the wrapper default.nix installs, or the
+/// first declared or static executable.
+fn docker_entrypoint(pkg_info: &PackageInfo, options: &GenerateOptions) -> Option<String> {
+    match &pkg_info.kind {
+        AppKind::Native if pkg_info.package_type == PackageType::Snap => pkg_info
+            .entry_points
+            .first()
+            .map(|e| format!("${{package}}/bin/{}", e.name)),
+        AppKind::Native | AppKind::DotNet { .. } | AppKind::Mono { .. } => {
+            Some(format!("${{package}}/bin/{}", pkg_info.name))
+        }
+        AppKind::Python { entries, .. } => entries.first().map(|e| {
+            installed_path(e, &pkg_info.name, options.opt_layout).replacen("$out", "${package}", 1)
+        }),
+        AppKind::Static { binaries } => binaries.first().map(|b| {
+            let fname = b.rsplit('/').next().unwrap_or(b);
+            format!("${{package}}/bin/{}", fname)
+        }),
+        AppKind::ArchIndependent | AppKind::Dkms { .. } | AppKind::Data { .. } => None,
+    }
+}
+
+/// A `dockerTools.buildLayeredImage` expression around default.nix, with CA
+/// certificates for TLS and the main executable as entrypoint.
+pub fn generate_docker_image(pkg_info: &PackageInfo, options: &GenerateOptions) -> String {
+    // Image references are lowercase; tags allow `[A-Za-z0-9_.-]` only
+    let image = pkg_info.name.to_lowercase();
+    let tag: String = pkg_info
+        .version
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "_.-".contains(c) { c } else { '_' })
+        .collect();
+    let entrypoint = match docker_entrypoint(pkg_info, options) {
+        Some(command) => format!("    Entrypoint = [ \"{}\" ];\n", command),
+        None => "    # No executable detected; set Entrypoint or Cmd by hand\n".to_string(),
+    };
+    fill_template(
+        include_str!("../templates/docker.in"),
+        &[
+            ("{entrypoint}", &entrypoint),
+            ("{image}", &image),
+            ("{tag}", if tag.is_empty() { "latest" } else { &tag }),
+            ("{name}", &pkg_info.name),
+            ("{version}", &pkg_info.version),
+        ],
+    )
+}
+
+/// `flake.nix` exposing default.nix as `packages.<system>.<name>` (and
+/// `default`) for every system in `systems`.
+pub fn generate_flake(pkg_info: &PackageInfo, systems: &[String]) -> String {
+    fill_template(
+        include_str!("../templates/flake.in"),
+        &[
+            ("{systems}", &systems.join("\" \"")),
+            ("{description}", &escape_string(&fold_description(&pkg_info.description))),
+            ("{name}", &pkg_info.name),
+        ],
+    )
+}
+
+/// Seconds the VM test's program has to stay up without `--vm-test-seconds`.
+const VM_TEST_SECONDS: u64 = 10;
+
+/// Lines that mean the dynamic loader gave up on a library.
+const LOADER_ERRORS: &str = "error while loading shared libraries|cannot open shared object file";
+
+/// `test.nix`, a `nixosTest` for default.nix: a daemon's first system
+/// service is started and must stay active; an app with launchers is
+/// started in an X session and must keep running; anything else is run
+/// with `--help` on a headless VM and must not fail to load. The log is
+/// checked for loader errors either way.
+pub fn generate_vm_test(pkg_info: &PackageInfo, options: &GenerateOptions) -> String {
+    let seconds = options.vm_test_seconds.unwrap_or(VM_TEST_SECONDS);
+    let log = format!("/tmp/{}.log", pkg_info.name);
+    let service = pkg_info
+        .integration
+        .system_units
+        .iter()
+        .find(|u| u.ends_with(".service") && !u.contains('@'));
+    let graphical = !pkg_info.desktop_entries.is_empty() || pkg_info.electron;
+    let exe = docker_entrypoint(pkg_info, options);
+
+    let (mode, check, machine, script) = match (service, &exe) {
+        (Some(unit), _) => (
+            "headless",
+            format!("checks {} is still active after {} seconds", unit, seconds),
+            format!(
+                "    systemd.packages = [ package ];\n    systemd.services.\"{}\".wantedBy = [ \"multi-user.target\" ];\n",
+                unit.trim_end_matches(".service")
+            ),
+            vec![
+                format!("machine.wait_for_unit(\"{}\")", unit),
+                format!("machine.sleep({})", seconds),
+                format!("machine.succeed(\"systemctl is-active {}\")", unit),
+                format!("machine.fail(\"journalctl -u {} | grep -E '{}'\")", unit, LOADER_ERRORS),
+            ],
+        ),
+        (None, Some(exe)) if graphical => (
+            "graphical",
+            format!("checks the program is still running after {} seconds", seconds),
+            [
+                "    imports = [",
+                "      \"${pkgs.path}/nixos/tests/common/x11.nix\"",
+                "      \"${pkgs.path}/nixos/tests/common/user-account.nix\"",
+                "    ];",
+                "    virtualisation.memorySize = 2048;",
+            ]
+            .iter()
+            .map(|l| format!("{}\n", l))
+            .collect(),
+            vec![
+                "machine.wait_for_x()".to_string(),
+                format!("machine.succeed(\"su - alice -c 'DISPLAY=:0 {} > {} 2>&1 &'\")", exe, log),
+                format!("machine.sleep({})", seconds),
+                format!("machine.fail(\"grep -E '{}' {}\")", LOADER_ERRORS, log),
+                format!("machine.succeed(\"pgrep -f {}\")", exe),
+                format!("machine.screenshot(\"{}\")", pkg_info.name),
+            ],
+        ),
+        (None, Some(exe)) => (
+            "headless",
+            "checks the program starts without missing libraries".to_string(),
+            String::new(),
+            vec![
+                "machine.wait_for_unit(\"multi-user.target\")".to_string(),
+                format!("machine.execute(\"timeout {} {} --help > {} 2>&1\")", seconds, exe, log),
+                format!("machine.fail(\"grep -E '{}' {}\")", LOADER_ERRORS, log),
+            ],
+        ),
+        (None, None) => (
+            "headless",
+            "checks it installs (no executable was detected; start one in testScript)".to_string(),
+            String::new(),
+            vec![
+                "machine.wait_for_unit(\"multi-user.target\")".to_string(),
+                "machine.succeed(\"test -e ${package}\")".to_string(),
+            ],
+        ),
+    };
+
+    // `${package}` in the script is interpolated on purpose
+    let script: String = script.iter().map(|l| format!("    {}\n", l)).collect();
+    fill_template(
+        include_str!("../templates/vm_test.in"),
+        &[
+            ("{machine}", &machine),
+            ("{script}", &script),
+            ("{mode}", mode),
+            ("{check}", &check),
+            ("{test_name}", &pkg_info.name.to_lowercase()),
+            ("{name}", &pkg_info.name),
+            ("{version}", &pkg_info.version),
+        ],
+    )
+}
+
+/// The `hook` snippets from `--*-file`, indented as phase body lines.
+/// They are kept as written: `${...}` is Nix interpolation, as in any
+/// hand-written phase.
+fn hook_lines(options: &GenerateOptions, hook: Hook) -> Vec<String> {
+    options
+        .hooks
+        .iter()
+        .filter(|(h, _)| *h == hook)
+        .flat_map(|(_, snippet)| snippet.lines())
+        .map(|line| if line.trim().is_empty() { String::new() } else { format!("    {}", line) })
+        .collect()
+}
+
+/// `lines` as a block replacing a whole-line placeholder.
+fn phase_block(lines: &[String]) -> String {
+    lines.iter().map(|l| format!("{}\n", l)).collect()
+}
+
+/// The file name and contents of the template default.nix is built from.
+pub fn template(pkg_type: &PackageType, kind: &AppKind) -> (&'static str, &'static str) {
+    if *pkg_type == PackageType::Snap {
+        return ("snap.in", include_str!("../templates/snap.in"));
+    }
+    match kind {
+        AppKind::Native => ("deb.in", include_str!("../templates/deb.in")),
+        AppKind::DotNet { .. } | AppKind::Mono { .. } => ("dotnet.in", include_str!("../templates/dotnet.in")),
+        AppKind::Python { .. } => ("python.in", include_str!("../templates/python.in")),
+        AppKind::Static { .. } => ("static.in", include_str!("../templates/static.in")),
+        // Firmware alone gets the compressed layout hardware.firmware expects
+        AppKind::Data { kinds } if kinds.as_slice() == [DataKind::Firmware] => {
+            ("firmware.in", include_str!("../templates/firmware.in"))
+        }
+        AppKind::ArchIndependent | AppKind::Data { .. } => ("all.in", include_str!("../templates/all.in")),
+        AppKind::Dkms { .. } => ("dkms.in", include_str!("../templates/dkms.in")),
+    }
+}
+
+pub fn generate_nix_content(
+    pkg_type: &PackageType,
+    pkg_info: &PackageInfo,
+    sources: &[Source],
+    system: &str,
+    options: &GenerateOptions,
+    _mode_upstream: bool
+) -> String {
+    let (all_build_deps, lib_path_packages) = input_lists(pkg_info, system, options);
+
+    // `--template` replaces the built-in template for the package
+    let body = options.template.as_deref().unwrap_or(template(pkg_type, &pkg_info.kind).1);
+    let python = matches!(pkg_info.kind, AppKind::Python { .. });
+
+    // Wrapper scripts still point at /bin/bash, /usr/bin/perl, ...; Python
+    // entry scripts at /usr/bin/python3, rewritten to the python of the
+    // template
+    let mut pre_fixup_lines = Vec::new();
+    if python || !pkg_info.script_interpreters.is_empty() {
+        pre_fixup_lines.push("    patchShebangs --host $out".to_string());
+    }
+    // Alpine names musl `libc.musl-<arch>.so.1`; nixpkgs' musl is `libc.so`
+    for soname in pkg_info.referenced_libs.iter().filter(|l| l.starts_with("libc.musl-")) {
+        pre_fixup_lines.push(format!(
+            "    find $out -type f -exec patchelf --replace-needed {} libc.so {{}} \\; 2>/dev/null || true",
+            soname
+        ));
+    }
+    // CUDA and NVML come from the host driver, found through the RUNPATH
+    // entry addDriverRunpath adds once autoPatchelf is done
+    let driver_libs: Vec<&String> = pkg_info
+        .referenced_libs
+        .iter()
+        .filter(|l| DRIVER_LIBS.contains(&l.as_str()))
+        .collect();
+    let mut post_fixup_lines = Vec::new();
+    // The PYTHONPATH wrappers go around the scripts patchShebangs rewrote,
+    // unless a custom template places them itself
+    if python && !body.contains("{wrapper}") {
+        post_fixup_lines.extend(runtime_wrapper(pkg_info, options).lines().map(str::to_string));
+    }
+    if !driver_libs.is_empty() {
+        post_fixup_lines.extend([
+            "    find $out -type f \\( -executable -o -name '*.so*' \\) | while read -r f; do".to_string(),
+            "      if isELF \"$f\"; then addDriverRunpath \"$f\"; fi".to_string(),
+            "    done".to_string(),
+        ]);
+    }
+    let user_pre_fixup = hook_lines(options, Hook::PreFixup);
+    let user_post_fixup = hook_lines(options, Hook::PostFixup);
+    pre_fixup_lines.extend(user_pre_fixup.iter().cloned());
+    post_fixup_lines.extend(user_post_fixup.iter().cloned());
+    let mut fixup = String::new();
+    // stdenv moves share/doc, share/man and share/info to the outputs named
+    // here; the libraries are moved by hand before autoPatchelf looks for them
+    if options.split_outputs {
+        fixup.push_str("  outputs = [ \"out\" \"lib\" \"doc\" ];\n  outputMan = \"doc\";\n  outputInfo = \"doc\";\n\n");
+        pre_fixup_lines.splice(
+            0..0,
+            [
+                "    mkdir -p $lib $doc".to_string(),
+                "    moveToOutput \"lib/*.so*\" \"$lib\"".to_string(),
+            ],
+        );
+    }
+    if !pre_fixup_lines.is_empty() {
+        fixup.push_str(&format!("  preFixup = ''\n{}\n  '';\n\n", pre_fixup_lines.join("\n")));
+    }
+    if !post_fixup_lines.is_empty() {
+        fixup.push_str(&format!("  postFixup = ''\n{}\n  '';\n\n", post_fixup_lines.join("\n")));
+    }
+    let ignore_missing: String = driver_libs.iter().map(|l| format!("      \"{}\"\n", l)).collect();
+
     // Format buildInputs with pkgs. prefix
     let packages_string = all_build_deps
         .iter()
@@ -102,22 +1726,221 @@ pub fn generate_nix_content(
         .collect::<Vec<_>>()
         .join("\n");
 
-    let header = "{ pkgs ? import <nixpkgs> {} }:";
+    let dkms = matches!(pkg_info.kind, AppKind::Dkms { .. });
+    // A DKMS module is built against the kernel it is called with
+    let mut header = if dkms {
+        "{ pkgs ? import <nixpkgs> {}, kernel ? pkgs.linuxPackages.kernel }:".to_string()
+    } else {
+        "{ pkgs ? import <nixpkgs> {} }:".to_string()
+    };
+    // The package's own spelling stays visible next to the normalized one
+    let original = |label: &str, value: &Option<String>| {
+        value
+            .as_deref()
+            .map(|v| format!(" # {}: {}", label, v.replace('\n', " ")))
+            .unwrap_or_default()
+    };
+    let pname_line = format!(
+        "  pname = \"{}\";{}\n",
+        options.pname.as_deref().unwrap_or(&pkg_info.name),
+        original("Package", &pkg_info.original_name)
+    );
+    let version_line = format!(
+        "  version = \"{}\";{}\n",
+        pkg_info.version,
+        original("Version", &pkg_info.original_version)
+    );
+    let version_line = if options.pin == Some(SourcePin::Nvfetcher) { String::new() } else { version_line };
+
+    let (unpack, unpack_tool) = unpack_phase(pkg_type, options.unpack_strategy);
+    // An application directory without usr/ or opt/ of its own goes below opt/
+    let unpack = match sources.first().and_then(|s| s.tree.as_deref()) {
+        Some(tree) if !tree.is_empty() => format!(
+            "    mkdir -p \"{tree}\"\n    cp -r $src/. \"{tree}\"\n    chmod -R u+w .",
+            tree = escape_indented(tree)
+        ),
+        _ => unpack.to_string(),
+    };
+    let mut native_tools = unpack_tool.to_string();
+    // Extension modules and helpers next to the Python scripts are linked
+    // like any native payload
+    if python && !pkg_info.binaries.is_empty() {
+        native_tools = format!("pkgs.autoPatchelfHook\n    {}", native_tools);
+    }
+    // dpkg-deb decompresses zstd itself
+    if pkg_info.zstd && options.unpack_strategy == UnpackStrategy::Tar {
+        native_tools.push_str("\n    pkgs.zstd");
+    }
+    if !driver_libs.is_empty() {
+        native_tools.push_str("\n    pkgs.addDriverRunpath");
+    }
+    // Only templates copying the payload with install_tree install them
+    let copies_payload = body.contains("{install_tree}");
+    if copies_payload && pkg_info.extra_files.needs_install_shell_files() {
+        native_tools.push_str("\n    pkgs.installShellFiles");
+    }
+    let preset = options.preset.map_or(&[][..], |p| p.native_build_inputs());
+    for extra in preset.iter().copied().chain(options.extra_native_build_inputs.iter().map(String::as_str)) {
+        native_tools.push_str(&format!("\n    pkgs.{}", extra));
+    }
+    let unpack = unpack_each(&unpack, sources);
+    let mut src = src_attr(sources, &options.auth, options.fetcher);
+    // The DKMS template suffixes the kernel's version to the package's
+    let mut dkms_version = format!("  version = \"{}-${{kernel.version}}\";\n", pkg_info.version);
+    // nvfetcher's _sources/generated.nix provides version and src
+    if options.pin == Some(SourcePin::Nvfetcher) {
+        header.push_str("\n\nlet\n  sources = pkgs.callPackage ./_sources/generated.nix { };\nin");
+        src = format!("  inherit (sources.\"{}\") version src;\n", pkg_info.name);
+        if dkms {
+            src = format!("  inherit (sources.\"{}\") src;\n", pkg_info.name);
+            dkms_version = format!("  version = \"${{sources.\"{}\".version}}-${{kernel.version}}\";\n", pkg_info.name);
+        }
+    } else if per_system(sources) {
+        header.push_str(&srcs_table(sources, &options.auth, options.fetcher));
+    }
+    let mut update_script = update_script(pkg_info, sources, options);
+    if per_system(sources) && options.pin.is_none() {
+        update_script.push_str("  passthru.sources = srcs;\n\n");
+    }
+    if let Some(url) = &options.changelog_url {
+        update_script.push_str(&format!("  passthru.changelog = \"{}\";\n\n", escape_string(url)));
+    }
+    if let Some(provenance) = &options.provenance {
+        update_script.push_str(&provenance_passthru(provenance));
+    }
+    let mut display = display_flags(pkg_info, options);
+    display.extend(env_flags(options));
+    let display_lines: String = display.iter().map(|f| format!("        {} \\\n", f)).collect();
+    let platforms = source_systems(sources, system).join("\" \"");
+    let license = license_line(&pkg_info.licenses, options.unfree_note);
+    let long_description = long_description_attr(&pkg_info.long_description);
+    let homepage = homepage_lines(pkg_info);
+    let stdenv = match (pkg_info.multilib, pkg_info.musl) {
+        (true, _) => "multiStdenv".to_string(),
+        (false, true) => format!("{}.stdenv", PKGS_MUSL),
+        (false, false) => "stdenv".to_string(),
+    };
 
     match pkg_type {
-        PackageType::Deb => {
-            let template = include_str!("../templates/deb.in");
-            let content = template
-                .replace("{header}", header)
-                .replace("{name}", &pkg_info.name)
-                .replace("{version}", &pkg_info.version)
-                .replace("{url}", url)
-                .replace("{sha256}", sha256)
-                .replace("{packages}", &packages_string)
-                .replace("{lib_packages}", &lib_packages_string)
-                .replace("{description}", &pkg_info.description)
-                .replace("{arch}", &pkg_info.arch);
-            content
+        // Snap apps declare their own commands; the payload layout is kept
+        PackageType::Snap => fill_template(
+            body,
+            &[
+                ("  pname = \"{name}\";\n", &pname_line),
+                ("  version = \"{version}\";\n", &version_line),
+                ("{header}", &header),
+                ("{stdenv}", &stdenv),
+                ("{wrapper}", &snap_wrappers(pkg_info, !lib_path_packages.is_empty(), &display)),
+                ("{ignore_missing}\n", &ignore_missing),
+                ("{native_tools}", &native_tools),
+                ("{unpack}", &unpack),
+                ("{name}", &pkg_info.name),
+                ("{version}", &pkg_info.version),
+                ("{src}\n", &src),
+                ("{packages}", &packages_string),
+                ("{lib_packages}", &lib_packages_string),
+                ("{description}", &escape_string(&fold_description(&pkg_info.description))),
+                ("{arch}", &platforms),
+                ("{license}\n", &license),
+                ("{long_description}\n", &long_description),
+                ("{homepage}\n", &homepage),
+                ("{update_script}\n", &update_script),
+                ("{fixup}\n", &fixup),
+                ("{post_install}\n", &phase_block(&hook_lines(options, Hook::PostInstall))),
+            ],
+        ),
+        PackageType::Deb | PackageType::Pacman | PackageType::Apk | PackageType::Directory => {
+            let template = body;
+            let hint_comment: String = usage_hints(&pkg_info.kind)
+                .iter()
+                .map(|h| format!("# {}\n", h))
+                .collect();
+            let source_dir = match &pkg_info.kind {
+                AppKind::Dkms { source_dir } => source_dir.as_str(),
+                _ => "",
+            };
+            let python_packages = match &pkg_info.kind {
+                AppKind::Python { modules, .. } => modules
+                    .iter()
+                    .map(|m| format!("    ps.{}", m))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                _ => String::new(),
+            };
+            fill_template(
+                template,
+                &[
+                    ("  pname = \"{name}\";\n", &pname_line),
+                    ("  version = \"{version}\";\n", &version_line),
+                    ("  version = \"{version}-${kernel.version}\";\n", &dkms_version),
+                    ("{header}", &header),
+                    ("{stdenv}", &stdenv),
+                    ("{install_tree}", &install_tree(pkg_info, options, system)),
+                    ("{wrapper}", &runtime_wrapper(pkg_info, options)),
+                    ("{display_flags}\n", &display_lines),
+                    ("{python_packages}", &python_packages),
+                    ("{hint}", &hint_comment),
+                    ("{source_dir}", source_dir),
+                    ("{ignore_missing}\n", &ignore_missing),
+                    ("{native_tools}", &native_tools),
+                    ("{unpack}", &unpack),
+                    ("{name}", &pkg_info.name),
+                    ("{version}", &pkg_info.version),
+                    ("{src}\n", &src),
+                    ("{packages}", &packages_string),
+                    ("{lib_packages}", &lib_packages_string),
+                    ("{description}", &escape_string(&fold_description(&pkg_info.description))),
+                    ("{arch}", &platforms),
+                    ("{license}\n", &license),
+                    ("{long_description}\n", &long_description),
+                    ("{homepage}\n", &homepage),
+                    ("{update_script}\n", &update_script),
+                    ("{fixup}\n", &fixup),
+                    ("{post_install}\n", &phase_block(&hook_lines(options, Hook::PostInstall))),
+                    ("{pre_fixup}\n", &phase_block(&user_pre_fixup)),
+                    ("{post_fixup}\n", &phase_block(&user_post_fixup)),
+                ],
+            )
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_quotes_backslashes_and_control_characters() {
+        assert_eq!(escape_string(r#"say "hi" \o/"#), r#"say \"hi\" \\o/"#);
+        assert_eq!(escape_string("a\nb\r\tc"), r"a\nb\r\tc");
+    }
+
+    #[test]
+    fn escapes_only_interpolating_dollars() {
+        assert_eq!(escape_string("${HOME} costs $5"), r"\${HOME} costs $5");
+        assert_eq!(escape_string("$${x}"), r"$\${x}");
+        assert_eq!(escape_string("ends with $"), "ends with $");
+    }
+
+    #[test]
+    fn fills_placeholders_without_rescanning_values() {
+        let filled = fill_template(
+            "{name}: {description} ({arch})",
+            &[("{description}", "runs on {arch}"), ("{arch}", "x86_64-linux"), ("{name}", "foo")],
+        );
+        assert_eq!(filled, "foo: runs on {arch} (x86_64-linux)");
+    }
+
+    #[test]
+    fn prefers_the_first_listed_placeholder_at_a_position() {
+        let placeholders = [("  version = \"{version}\";\n", ""), ("{version}", "1.0")];
+        let filled = fill_template("  version = \"{version}\";\n  src = \"{version}\";\n", &placeholders);
+        assert_eq!(filled, "  src = \"1.0\";\n");
+    }
+
+    #[test]
+    fn leaves_plain_text_alone() {
+        assert_eq!(escape_string("Ünïcode {arch} text"), "Ünïcode {arch} text");
+        assert_eq!(escape_string(""), "");
+    }
+}
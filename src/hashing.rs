@@ -0,0 +1,125 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use sha2::{Digest, Sha256};
+
+/// SHA-256 of a file, streamed so large debs are not loaded whole.
+pub fn sha256(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Adds a NAR string: its length, the bytes and zero padding to 8 bytes.
+fn nar_str(hasher: &mut Sha256, bytes: &[u8]) {
+    hasher.update((bytes.len() as u64).to_le_bytes());
+    hasher.update(bytes);
+    hasher.update(&[0u8; 8][..(8 - bytes.len() % 8) % 8]);
+}
+
+fn nar_node(hasher: &mut Sha256, path: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    nar_str(hasher, b"(");
+    nar_str(hasher, b"type");
+    if metadata.file_type().is_symlink() {
+        nar_str(hasher, b"symlink");
+        nar_str(hasher, b"target");
+        nar_str(hasher, fs::read_link(path)?.as_os_str().as_bytes());
+    } else if metadata.is_dir() {
+        nar_str(hasher, b"directory");
+        let mut entries: Vec<_> = fs::read_dir(path)?.map(|e| e.map(|e| e.file_name())).collect::<Result<_, _>>()?;
+        entries.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+        for name in entries {
+            nar_str(hasher, b"entry");
+            nar_str(hasher, b"(");
+            nar_str(hasher, b"name");
+            nar_str(hasher, name.as_bytes());
+            nar_str(hasher, b"node");
+            nar_node(hasher, &path.join(&name))?;
+            nar_str(hasher, b")");
+        }
+    } else {
+        nar_str(hasher, b"regular");
+        // Nix only looks at the owner's execute bit
+        if metadata.permissions().mode() & 0o100 != 0 {
+            nar_str(hasher, b"executable");
+            nar_str(hasher, b"");
+        }
+        nar_str(hasher, b"contents");
+        hasher.update(metadata.len().to_le_bytes());
+        let mut file = File::open(path)?;
+        let mut buf = [0u8; 64 * 1024];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        hasher.update(&[0u8; 8][..(8 - (metadata.len() % 8) as usize) % 8]);
+    }
+    nar_str(hasher, b")");
+    Ok(())
+}
+
+/// SHA-256 of the NAR serialization of a directory tree, the recursive
+/// hash `builtins.path` and `nix hash path` use.
+pub fn nar_sha256(path: &Path) -> io::Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    nar_str(&mut hasher, b"nix-archive-1");
+    nar_node(&mut hasher, path)?;
+    Ok(hasher.finalize().to_vec())
+}
+
+/// One digest standing for several files, e.g. the debs merged into one
+/// derivation.
+pub fn combine(digests: &[Vec<u8>]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    for digest in digests {
+        hasher.update(digest);
+    }
+    hasher.finalize().to_vec()
+}
+
+pub fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parses a hex digest as found in `.dsc` checksum fields.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Parses a SHA-256 given on the command line, in hex or SRI form.
+pub fn parse_sha256(value: &str) -> Option<Vec<u8>> {
+    let digest = match value.strip_prefix("sha256-") {
+        Some(base64) => STANDARD.decode(base64).ok()?,
+        None => from_hex(value)?,
+    };
+    (digest.len() == 32).then_some(digest)
+}
+
+/// The SRI form `fetchurl` accepts (`sha256-<base64>`), as printed by
+/// `nix hash file`.
+pub fn to_sri(digest: &[u8]) -> String {
+    format!("sha256-{}", STANDARD.encode(digest))
+}
@@ -0,0 +1,157 @@
+use log::{info, warn};
+
+pub const KEEP_START: &str = "# app2nix:keep-start";
+pub const KEEP_END: &str = "# app2nix:keep-end";
+
+/// A hand-kept block of a previously generated file, markers included.
+/// `anchors` are the generated lines above it, nearest first, each with
+/// how many identical lines came before it, so `];` or `''` can be told
+/// apart.
+struct Region {
+    lines: Vec<String>,
+    anchors: Vec<(String, usize)>,
+}
+
+/// Each of `lines`, trimmed, with how many identical lines precede it.
+fn numbered(lines: &[&str]) -> Vec<(String, usize)> {
+    let mut seen: Vec<(String, usize)> = Vec::new();
+    lines
+        .iter()
+        .map(|line| {
+            let key = line.trim().to_string();
+            let n = seen.iter().filter(|(k, _)| *k == key).count();
+            seen.push((key.clone(), n));
+            (key, n)
+        })
+        .collect()
+}
+
+fn regions(old: &str) -> Vec<Region> {
+    let mut regions = Vec::new();
+    let mut generated: Vec<&str> = Vec::new();
+    let mut current: Option<Vec<String>> = None;
+    for line in old.lines() {
+        match &mut current {
+            Some(lines) => {
+                lines.push(line.to_string());
+                if line.trim_start().starts_with(KEEP_END) {
+                    let anchors = numbered(&generated).into_iter().rev().filter(|(k, _)| !k.is_empty()).collect();
+                    regions.push(Region {
+                        lines: current.take().unwrap_or_default(),
+                        anchors,
+                    });
+                }
+            }
+            None if line.trim_start().starts_with(KEEP_START) => current = Some(vec![line.to_string()]),
+            None => generated.push(line),
+        }
+    }
+    if current.is_some() {
+        warn!("A {} region has no {}; it is not kept.", KEEP_START, KEEP_END);
+    }
+    regions
+}
+
+/// Carries the `# app2nix:keep-start` ... `# app2nix:keep-end` regions of
+/// `old` over into the regenerated `new`, each below the nearest generated
+/// line above it that `new` still has. Regions whose context is gone end up
+/// before the closing brace.
+pub fn preserve(old: &str, new: &str) -> String {
+    let regions = regions(old);
+    if regions.is_empty() {
+        return new.to_string();
+    }
+    let new_lines: Vec<&str> = new.lines().collect();
+    let keys = numbered(&new_lines);
+    let closing = new_lines.iter().rposition(|l| l.trim() == "}").unwrap_or(new_lines.len());
+
+    let mut placed: Vec<(usize, Region)> = Vec::new();
+    for region in regions {
+        let after = region
+            .anchors
+            .iter()
+            .find_map(|anchor| keys.iter().position(|k| k == anchor))
+            .map(|i| i + 1);
+        let at = after.unwrap_or_else(|| {
+            warn!(
+                "Nothing generated around a kept region starting {:?} remains; it was moved before the closing brace.",
+                region.lines.get(1).map(|l| l.trim()).unwrap_or_default()
+            );
+            closing
+        });
+        placed.push((at, region));
+    }
+    // Stable, so regions sharing a spot keep their order
+    placed.sort_by_key(|(at, _)| *at);
+    info!("Kept {} hand-edited region(s) from the previous file.", placed.len());
+
+    let mut merged = Vec::with_capacity(new_lines.len());
+    let mut regions = placed.into_iter().peekable();
+    for (i, line) in new_lines.iter().enumerate() {
+        while let Some((_, region)) = regions.next_if(|(at, _)| *at == i) {
+            merged.extend(region.lines);
+        }
+        merged.push(line.to_string());
+    }
+    for (_, region) in regions {
+        merged.extend(region.lines);
+    }
+    let mut merged = merged.join("\n");
+    if new.ends_with('\n') {
+        merged.push('\n');
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEPT: &str = "  # app2nix:keep-start\n  env.FOO = \"1\";\n  # app2nix:keep-end\n";
+
+    #[test]
+    fn tells_repeated_closing_lines_apart() {
+        let old = format!("{{\n  a = [\n    x\n  ];\n  b = [\n    y\n  ];\n{}}}\n", KEPT);
+        let new = "{\n  a = [\n    x2\n  ];\n  b = [\n    y2\n  ];\n  c = 1;\n}\n";
+        assert_eq!(
+            preserve(&old, new),
+            format!("{{\n  a = [\n    x2\n  ];\n  b = [\n    y2\n  ];\n{}  c = 1;\n}}\n", KEPT)
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_next_line_above_when_the_anchor_vanished() {
+        let old = format!("{{\n  pname = \"foo\";\n  version = \"1.0\";\n{}}}\n", KEPT);
+        let new = "{\n  pname = \"foo\";\n  version = \"2.0\";\n}\n";
+        assert_eq!(preserve(&old, new), format!("{{\n  pname = \"foo\";\n{}  version = \"2.0\";\n}}\n", KEPT));
+    }
+
+    #[test]
+    fn moves_a_region_without_context_before_the_closing_brace() {
+        let old = format!("let\n  x = 1;\n{}in\n", KEPT);
+        let new = "{\n  y = 2;\n}\n";
+        assert_eq!(preserve(&old, new), format!("{{\n  y = 2;\n{}}}\n", KEPT));
+    }
+
+    #[test]
+    fn keeps_the_order_of_regions_at_one_spot() {
+        let second = KEPT.replace("FOO", "BAR");
+        let old = format!("{{\n  pname = \"foo\";\n{}{}}}\n", KEPT, second);
+        let new = "{\n  pname = \"foo\";\n  version = \"1.0\";\n}\n";
+        let regions = regions(&old);
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[0].anchors, regions[1].anchors);
+        assert_eq!(
+            preserve(&old, new),
+            format!("{{\n  pname = \"foo\";\n{}{}  version = \"1.0\";\n}}\n", KEPT, second)
+        );
+    }
+
+    #[test]
+    fn drops_a_region_without_an_end_marker() {
+        let old = "{\n  # app2nix:keep-start\n  env.FOO = \"1\";\n}\n";
+        let new = "{\n  pname = \"foo\";\n}\n";
+        assert!(regions(old).is_empty());
+        assert_eq!(preserve(old, new), new);
+    }
+}
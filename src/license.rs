@@ -0,0 +1,185 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// `lib.licenses` attrs by normalized license name (see `normalize`):
+/// Debian's short names, SPDX identifiers and pacman's names.
+const NIX_LICENSES: &[(&str, &str)] = &[
+    ("gpl1", "gpl1Only"),
+    ("gpl1+", "gpl1Plus"),
+    ("gpl2", "gpl2Only"),
+    ("gpl2+", "gpl2Plus"),
+    ("gpl3", "gpl3Only"),
+    ("gpl3+", "gpl3Plus"),
+    ("gpl", "gpl2Plus"),
+    ("lgpl2", "lgpl2Only"),
+    ("lgpl2+", "lgpl2Plus"),
+    ("lgpl2.1", "lgpl21Only"),
+    ("lgpl2.1+", "lgpl21Plus"),
+    ("lgpl3", "lgpl3Only"),
+    ("lgpl3+", "lgpl3Plus"),
+    ("lgpl", "lgpl21Plus"),
+    ("agpl3", "agpl3Only"),
+    ("agpl3+", "agpl3Plus"),
+    ("mit", "mit"),
+    ("expat", "mit"),
+    ("x11", "x11"),
+    ("apache2", "asl20"),
+    ("apache", "asl20"),
+    ("bsd2clause", "bsd2"),
+    ("bsd3clause", "bsd3"),
+    ("bsd", "bsd3"),
+    ("isc", "isc"),
+    ("zlib", "zlib"),
+    ("mpl1.1", "mpl11"),
+    ("mpl2", "mpl20"),
+    ("epl1", "epl10"),
+    ("epl2", "epl20"),
+    ("cddl1", "cddl"),
+    ("artistic", "artistic1"),
+    ("artistic1", "artistic1"),
+    ("artistic2", "artistic2"),
+    ("perl", "artistic1"),
+    ("psf2", "psfl"),
+    ("python", "psfl"),
+    ("bsl1", "boost"),
+    ("boost", "boost"),
+    ("ofl1.1", "ofl"),
+    ("cc01", "cc0"),
+    ("cc0", "cc0"),
+    ("ccby4", "cc-by-40"),
+    ("ccbysa4", "cc-by-sa-40"),
+    ("unlicense", "unlicense"),
+    ("wtfpl", "wtfpl"),
+    ("publicdomain", "publicDomain"),
+];
+
+/// `/usr/share/common-licenses/GPL-2`, as free-form Debian copyright files
+/// refer to the license text.
+static COMMON_LICENSE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"/usr/share/common-licenses/([A-Za-z0-9.+-]*[A-Za-z0-9+])").unwrap());
+/// Separators of a license expression: SPDX `OR`/`AND`/`WITH`, Debian's
+/// lowercase ones, pacman's `|` and lists.
+static EXPRESSION_SEPARATOR: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)\s+(?:or|and)\s+|\s*[|,;/]\s*|[()]").unwrap());
+
+/// `GPL-2.0-or-later` and `GPL-2+` both become `gpl2+`, `BSD-3-Clause`
+/// `bsd3clause`.
+fn normalize(name: &str) -> String {
+    let name = name.trim().to_ascii_lowercase().replace("-or-later", "+").replace("-only", "");
+    let name = name.strip_prefix("custom:").unwrap_or(&name);
+    name.replace(".0", "").chars().filter(|c| !matches!(c, '-' | '_' | ' ')).collect()
+}
+
+/// The license names in an expression such as `GPL-2+ or Artistic`,
+/// `MIT AND Apache-2.0` or `GPL2 | LGPL`. Exceptions (`WITH
+/// Classpath-exception-2.0`) are dropped.
+pub fn split_expression(expression: &str) -> Vec<String> {
+    EXPRESSION_SEPARATOR
+        .split(expression)
+        .map(|name| name.split_once(" with ").or_else(|| name.split_once(" WITH ")).map_or(name, |(n, _)| n))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The licenses a Debian `copyright` file names: the `License:` fields of
+/// a machine-readable (DEP-5) one, else the common-licenses files a
+/// free-form one refers to.
+pub fn from_copyright(content: &str) -> Vec<String> {
+    let mut names: Vec<String> = if content.lines().any(|l| l.starts_with("Format:")) {
+        content
+            .lines()
+            .filter_map(|l| l.strip_prefix("License:"))
+            .flat_map(split_expression)
+            .collect()
+    } else {
+        COMMON_LICENSE.captures_iter(content).map(|c| c[1].to_string()).collect()
+    };
+    let mut seen = Vec::new();
+    names.retain(|n| {
+        let new = !seen.contains(n);
+        seen.push(n.clone());
+        new
+    });
+    names
+}
+
+/// The `lib.licenses` attr for a license name; `None` for anything not
+/// known to be free, such as `proprietary` or pacman's `custom`.
+pub fn nix_license(name: &str) -> Option<&'static str> {
+    let key = normalize(name);
+    NIX_LICENSES.iter().find(|(n, _)| *n == key).map(|(_, attr)| *attr)
+}
+
+/// The `lib.licenses` attrs for what a package states: `unfree` stands in
+/// for every unknown license, and for no license at all.
+pub fn nix_licenses(names: &[String]) -> Vec<&'static str> {
+    let mut attrs: Vec<&'static str> = Vec::new();
+    for attr in names.iter().map(|n| nix_license(n).unwrap_or("unfree")) {
+        if !attrs.contains(&attr) {
+            attrs.push(attr);
+        }
+    }
+    if attrs.is_empty() {
+        attrs.push("unfree");
+    }
+    attrs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_license_fields_of_a_dep5_copyright() {
+        let copyright = "\
+Format: https://www.debian.org/doc/packaging-manuals/copyright-format/1.0/
+Upstream-Name: foo
+
+Files: *
+License: GPL-2+ or Artistic
+
+Files: src/llvm/*
+License: Apache-2.0 WITH LLVM-exception
+
+License: GPL-2+
+ This program is free software; see /usr/share/common-licenses/GPL-2.
+";
+        assert_eq!(from_copyright(copyright), ["GPL-2+", "Artistic", "Apache-2.0"]);
+    }
+
+    #[test]
+    fn reads_the_common_licenses_a_free_form_copyright_refers_to() {
+        let copyright = "\
+Copyright (C) 2020 Someone.
+
+On Debian systems the full text is in /usr/share/common-licenses/LGPL-2.1,
+and the documentation is under /usr/share/common-licenses/GFDL-1.3.
+See /usr/share/common-licenses/LGPL-2.1 again.
+";
+        assert_eq!(from_copyright(copyright), ["LGPL-2.1", "GFDL-1.3"]);
+        assert!(from_copyright("All rights reserved.").is_empty());
+    }
+
+    #[test]
+    fn maps_debian_spdx_and_pacman_names_to_the_same_attr() {
+        assert_eq!(nix_license("GPL-2+"), Some("gpl2Plus"));
+        assert_eq!(nix_license("GPL-2.0-or-later"), Some("gpl2Plus"));
+        assert_eq!(nix_license("GPL2"), Some("gpl2Only"));
+        assert_eq!(nix_license("LGPL-2.1"), Some("lgpl21Only"));
+        assert_eq!(nix_license("BSD-3-Clause"), Some("bsd3"));
+        assert_eq!(nix_license("Expat"), Some("mit"));
+        assert_eq!(nix_license("Apache-2.0"), Some("asl20"));
+        assert_eq!(nix_license("MPL-2.0"), Some("mpl20"));
+        assert_eq!(nix_license("custom:MIT"), Some("mit"));
+    }
+
+    #[test]
+    fn knows_nothing_about_unfree_licenses() {
+        assert_eq!(nix_license("proprietary"), None);
+        assert_eq!(nix_license("custom"), None);
+        assert_eq!(nix_license("custom:Vendor EULA"), None);
+        assert_eq!(nix_license(""), None);
+    }
+}
@@ -0,0 +1,106 @@
+use std::io::{IsTerminal, Write};
+use std::sync::OnceLock;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::{Value, json};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Set by `init`, for `print_result`.
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+/// Writes every record to stderr, so stdout only carries command output
+/// (diff/resolve results, generated expressions).
+struct Logger {
+    format: LogFormat,
+    /// `>>>` markers and emoji, for a terminal only; CI logs get a plain
+    /// `Info:` prefix and the bare message.
+    decorated: bool,
+}
+
+/// The message without the emoji some messages start with.
+fn undecorated(message: &str) -> &str {
+    message.trim_start_matches(|c: char| !c.is_ascii()).trim_start()
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let message = record.args().to_string();
+        let line = match self.format {
+            LogFormat::Text if self.decorated => match record.level() {
+                Level::Error => format!("Error: {}", message),
+                Level::Warn => format!("Warning: {}", message),
+                Level::Info => format!(">>> {}", message),
+                Level::Debug => format!("    {}", message),
+                Level::Trace => format!("    [trace] {}", message),
+            },
+            LogFormat::Text => match record.level() {
+                Level::Error => format!("Error: {}", undecorated(&message)),
+                Level::Warn => format!("Warning: {}", undecorated(&message)),
+                Level::Info => format!("Info: {}", undecorated(&message)),
+                Level::Debug => format!("Debug: {}", undecorated(&message)),
+                Level::Trace => format!("Trace: {}", undecorated(&message)),
+            },
+            LogFormat::Json => json!({
+                "level": record.level().as_str().to_lowercase(),
+                "target": record.target(),
+                "message": undecorated(&message),
+            })
+            .to_string(),
+        };
+
+        crate::progress::suspend(|| {
+            let _ = writeln!(std::io::stderr().lock(), "{}", line);
+        });
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// `verbosity` is the net count of `-v` minus `-q` (`--quiet` counting
+/// two): -2 and below errors only, -1 warnings only, 0 progress, 1
+/// per-file detail, 2 and above everything.
+pub fn init(verbosity: i32, format: LogFormat) {
+    let level = match verbosity {
+        i32::MIN..=-2 => LevelFilter::Error,
+        -1 => LevelFilter::Warn,
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+
+    let _ = FORMAT.set(format);
+    let decorated = std::io::stderr().is_terminal();
+    if log::set_boxed_logger(Box::new(Logger { format, decorated })).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Whether only errors are logged (`--quiet`), leaving the outcome to
+/// `print_result`.
+pub fn quiet() -> bool {
+    log::max_level() < LevelFilter::Warn
+}
+
+/// Prints a command's outcome as one line on stdout: `text`, or `fields`
+/// as JSON with `--log-format json`.
+pub fn print_result(text: &str, fields: Value) {
+    match FORMAT.get() {
+        Some(LogFormat::Json) => println!("{}", fields),
+        _ => println!("{}", text),
+    }
+}
@@ -0,0 +1,89 @@
+use crate::structs::PackageType;
+
+/// Turns `name` into a nixpkgs-style pname: lowercase, with anything but
+/// letters, digits and `-` (spaces, `+`, `.`, `_`, ...) replaced by `-`.
+pub fn sanitize_pname(name: &str) -> String {
+    let mut pname = String::with_capacity(name.len());
+    for c in name.trim().chars().flat_map(char::to_lowercase) {
+        let c = if c.is_ascii_alphanumeric() { c } else { '-' };
+        if c == '-' && pname.ends_with('-') {
+            continue;
+        }
+        pname.push(c);
+    }
+    pname.trim_matches('-').to_string()
+}
+
+/// Makes `version` valid in a store path, which allows letters, digits and
+/// `+-._?=`; anything else (spaces, `~`, `:`) becomes `-`.
+pub fn sanitize_version(version: &str) -> String {
+    version
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "+-._?=".contains(c) { c } else { '-' })
+        .collect()
+}
+
+/// Turns a distro package version into a nixpkgs version: the epoch
+/// (`1:`) and the packaging revision (deb `-1ubuntu0`, pacman `-3`, apk
+/// `-r2`) are dropped, and Debian's pre-release `~` becomes `-pre-`, which
+/// `builtins.compareVersions` also sorts before the release.
+pub fn normalize_version(version: &str, pkg_type: PackageType) -> String {
+    let version = version.trim();
+    let version = version
+        .split_once(':')
+        .filter(|(epoch, _)| !epoch.is_empty() && epoch.chars().all(|c| c.is_ascii_digit()))
+        .map_or(version, |(_, rest)| rest);
+    let is_release = |rel: &str| !rel.is_empty() && rel.chars().all(|c| c.is_ascii_digit() || c == '.');
+    let upstream = match pkg_type {
+        PackageType::Deb => version.rsplit_once('-').map_or(version, |(upstream, _)| upstream),
+        PackageType::Pacman => version
+            .rsplit_once('-')
+            .filter(|(_, rel)| is_release(rel))
+            .map_or(version, |(upstream, _)| upstream),
+        PackageType::Apk => version
+            .rsplit_once("-r")
+            .filter(|(_, rel)| is_release(rel))
+            .map_or(version, |(upstream, _)| upstream),
+        PackageType::Snap | PackageType::Directory => version,
+    };
+    sanitize_version(&upstream.replace('~', "-pre-"))
+}
+
+/// Whether `attr` looks like a nixpkgs attribute path (`gtk3`,
+/// `xorg.libX11`, `python3Packages.pyqt5`).
+pub fn is_attr_path(attr: &str) -> bool {
+    !attr.is_empty()
+        && attr.split('.').all(|part| {
+            part.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || "_-'".contains(c))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_the_epoch_and_the_debian_revision() {
+        assert_eq!(normalize_version("1:2.3.4-1ubuntu0.1", PackageType::Deb), "2.3.4");
+        assert_eq!(normalize_version("2.3.4-1-2", PackageType::Deb), "2.3.4-1");
+        assert_eq!(normalize_version("2.3.4", PackageType::Deb), "2.3.4");
+        // Only digits make an epoch
+        assert_eq!(normalize_version("a:1.0", PackageType::Snap), "a-1.0");
+    }
+
+    #[test]
+    fn turns_a_tilde_into_a_pre_release() {
+        assert_eq!(normalize_version("1.0~rc1-2", PackageType::Deb), "1.0-pre-rc1");
+        assert_eq!(normalize_version("1.0~beta", PackageType::Snap), "1.0-pre-beta");
+    }
+
+    #[test]
+    fn drops_only_numeric_pacman_and_apk_releases() {
+        assert_eq!(normalize_version("1:1.2.3-3", PackageType::Pacman), "1.2.3");
+        assert_eq!(normalize_version("1.2.3-beta", PackageType::Pacman), "1.2.3-beta");
+        assert_eq!(normalize_version("0.9.1-r2", PackageType::Apk), "0.9.1");
+        assert_eq!(normalize_version("0.9.1-rc1", PackageType::Apk), "0.9.1-rc1");
+    }
+}
@@ -0,0 +1,204 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+use walkdir::WalkDir;
+
+use crate::errors::AppError;
+use crate::vulns::compare_versions;
+
+const REPOLOGY_API: &str = "https://repology.org/api/v1/project";
+/// Repology asks API clients for at most one request per second.
+const REPOLOGY_INTERVAL: Duration = Duration::from_secs(1);
+
+static NIX_ATTR: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?m)^\s*(pname|version)\s*=\s*"([^"]*)";"#).unwrap());
+/// The comments `update_script` writes above `passthru.updateScript`.
+static UPDATE_SOURCE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^\s*# Update source: (.+)$").unwrap());
+static VERSION_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^\s*#\s+version regex: (.+)$").unwrap());
+
+/// Where the newest upstream version is looked up.
+enum Upstream {
+    GitHub(String),
+    /// A download directory (e.g. an apt pool) and the file name regex
+    /// whose first group is the version.
+    Directory { dir: String, regex: String },
+    Repology(String),
+}
+
+impl Upstream {
+    fn label(&self) -> String {
+        match self {
+            Upstream::GitHub(repo) => format!("github.com/{}", repo),
+            Upstream::Directory { dir, .. } => format!("{}/", dir),
+            Upstream::Repology(project) => format!("Repology {}", project),
+        }
+    }
+}
+
+struct Expression {
+    pname: String,
+    version: String,
+    upstream: Upstream,
+}
+
+/// Reads pname, version and the update source recorded next to
+/// `passthru.updateScript`, falling back to Repology's project of the same
+/// name.
+fn read_expression(path: &Path) -> Result<Expression, String> {
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let attr = |key: &str| {
+        NIX_ATTR
+            .captures_iter(&content)
+            .find(|c| &c[1] == key)
+            .map(|c| c[2].to_string())
+            .ok_or_else(|| format!("no {} = \"...\"; line", key))
+    };
+    let pname = attr("pname")?;
+    let version = attr("version")?;
+
+    let source = UPDATE_SOURCE.captures(&content).map(|c| c[1].trim().to_string());
+    let upstream = match source.as_deref() {
+        Some(source) if source.starts_with("latest release of github.com/") => {
+            Upstream::GitHub(source.trim_start_matches("latest release of github.com/").to_string())
+        }
+        Some(source) if source.starts_with("directory listing of ") => match VERSION_REGEX.captures(&content) {
+            Some(regex) => Upstream::Directory {
+                dir: source.trim_start_matches("directory listing of ").trim_end_matches('/').to_string(),
+                regex: regex[1].trim().to_string(),
+            },
+            None => Upstream::Repology(pname.clone()),
+        },
+        _ => Upstream::Repology(pname.clone()),
+    };
+    Ok(Expression {
+        pname,
+        version,
+        upstream,
+    })
+}
+
+fn fetch(url: &str) -> Result<Vec<u8>, String> {
+    let output = Command::new("curl")
+        .args(["-sS", "--fail", "-L", "-A", concat!("app2nix/", env!("CARGO_PKG_VERSION")), url])
+        .output()
+        .map_err(|e| format!("Failed to run curl: {}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(output.stdout)
+}
+
+fn fetch_json(url: &str) -> Result<Value, String> {
+    serde_json::from_slice(&fetch(url)?).map_err(|e| format!("Invalid response from {}: {}", url, e))
+}
+
+fn newest<'a>(versions: impl Iterator<Item = &'a str>) -> Option<String> {
+    versions.max_by(|a, b| compare_versions(a, b)).map(str::to_string)
+}
+
+/// The newest upstream version, or `None` if the source knows none.
+fn latest_version(upstream: &Upstream) -> Result<Option<String>, String> {
+    match upstream {
+        Upstream::GitHub(repo) => {
+            let release = fetch_json(&format!("https://api.github.com/repos/{}/releases/latest", repo))?;
+            Ok(release
+                .get("tag_name")
+                .and_then(Value::as_str)
+                .map(|tag| tag.trim_start_matches('v').to_string()))
+        }
+        Upstream::Directory { dir, regex } => {
+            let regex = Regex::new(regex).map_err(|e| format!("Bad version regex: {}", e))?;
+            let listing = String::from_utf8_lossy(&fetch(&format!("{}/", dir))?).into_owned();
+            let versions: Vec<String> = regex
+                .captures_iter(&listing)
+                .filter_map(|c| c.get(1).map(|v| v.as_str().to_string()))
+                .collect();
+            Ok(newest(versions.iter().map(String::as_str)))
+        }
+        Upstream::Repology(project) => {
+            thread::sleep(REPOLOGY_INTERVAL);
+            let packages = fetch_json(&format!("{}/{}", REPOLOGY_API, project))?;
+            // Repology marks the repositories carrying the newest version
+            let versions = packages.as_array().into_iter().flatten().filter_map(|p| {
+                (p.get("status").and_then(Value::as_str) == Some("newest"))
+                    .then(|| p.get("version").and_then(Value::as_str))
+                    .flatten()
+            });
+            Ok(newest(versions))
+        }
+    }
+}
+
+/// Every `default.nix` named or found below a named directory.
+fn expressions(paths: &[String]) -> Vec<String> {
+    let mut files = Vec::new();
+    for path in paths {
+        if Path::new(path).is_dir() {
+            let mut found: Vec<String> = WalkDir::new(path)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file() && e.file_name() == "default.nix")
+                .map(|e| e.path().display().to_string())
+                .collect();
+            found.sort();
+            files.extend(found);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files
+}
+
+/// `app2nix outdated <default.nix|dir>...`: compares each generated
+/// expression's version with the newest upstream one, from the source its
+/// updateScript polls or else Repology. Prints one line per file.
+pub fn run_outdated_command(paths: &[String]) -> Result<(), AppError> {
+    let files = expressions(paths);
+    if files.is_empty() {
+        return Err(AppError::Input(format!("No default.nix found in {}", paths.join(", "))));
+    }
+
+    let mut outdated = 0;
+    let mut unknown = 0;
+    for file in &files {
+        let expression = match read_expression(Path::new(file)) {
+            Ok(expression) => expression,
+            Err(e) => {
+                warn!("{}: {}", file, e);
+                unknown += 1;
+                continue;
+            }
+        };
+        let source = expression.upstream.label();
+        match latest_version(&expression.upstream) {
+            Ok(Some(latest)) if compare_versions(&latest, &expression.version) == Ordering::Greater => {
+                outdated += 1;
+                println!("{}: {} {} -> {} ({})", file, expression.pname, expression.version, latest, source);
+            }
+            Ok(Some(_)) => println!("{}: {} {} is up to date ({})", file, expression.pname, expression.version, source),
+            Ok(None) => {
+                unknown += 1;
+                println!("{}: {} {}: no upstream version found ({})", file, expression.pname, expression.version, source);
+            }
+            Err(e) => {
+                unknown += 1;
+                warn!("{}: looking up {} failed: {}", file, source, e);
+            }
+        }
+    }
+
+    info!(
+        "{} of {} package(s) outdated, {} could not be checked.",
+        outdated,
+        files.len(),
+        unknown
+    );
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use std::path::Path;
+
+use crate::archive;
+use crate::errors::AppError;
+use crate::license;
+use crate::readfile_nix::ARCH_INDEPENDENT;
+use crate::structs::{PackageInfo, PackageType};
+
+/// Maps a pacman or apk `arch` value to the matching Nix system string.
+fn arch_to_system(arch: &str) -> String {
+    match arch {
+        "any" | "noarch" => ARCH_INDEPENDENT.to_string(),
+        "x86_64" => "x86_64-linux".to_string(),
+        "aarch64" => "aarch64-linux".to_string(),
+        "i686" | "x86" => "i686-linux".to_string(),
+        "armv7h" | "armv7" => "armv7l-linux".to_string(),
+        "armv6h" | "armhf" => "armv6l-linux".to_string(),
+        "riscv64" => "riscv64-linux".to_string(),
+        "ppc64le" => "powerpc64le-linux".to_string(),
+        arch => format!("{}-linux", arch),
+    }
+}
+
+/// Reads name, version, arch, description, homepage, licenses and
+/// dependencies from the `.PKGINFO` of a pacman `.pkg.tar.*` or Alpine
+/// `.apk` package (`key = value` lines, repeated keys for lists).
+pub fn read_pkginfo(path: &Path, package_type: PackageType) -> Result<PackageInfo, AppError> {
+    let pkginfo = archive::read_tarball_file(path, ".PKGINFO")?
+        .ok_or_else(|| AppError::Extract(format!("No .PKGINFO in {}", path.display())))?;
+
+    let mut package_info = PackageInfo {
+        package_type,
+        ..PackageInfo::default()
+    };
+    for line in pkginfo.lines() {
+        let Some((key, value)) = line.split_once(" = ") else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "pkgname" => package_info.name = value.to_string(),
+            "pkgver" => package_info.version = value.to_string(),
+            "pkgdesc" => package_info.description = value.to_string(),
+            "arch" => package_info.arch = arch_to_system(value),
+            "license" => package_info.licenses.extend(license::split_expression(value)),
+            "url" => package_info.homepage = Some(value.to_string()),
+            // apk only; pacman's `packager` built the package, not the app
+            "maintainer" => package_info.maintainer = Some(value.to_string()),
+            "size" => package_info.installed_size = value.parse().ok(),
+            // pacman's `backup` files are relative to /
+            "backup" => package_info.conffiles.push(format!("/{}", value)),
+            // `libfoo>=1.2` names the package before the operator; apk also
+            // has `so:`, `cmd:` and `pc:` provider prefixes
+            "depend" => {
+                let name = value.split(['<', '>', '=', '~']).next().unwrap_or(value);
+                let name = name.split_once(':').map_or(name, |(_, n)| n);
+                package_info.control_depends.push(name.to_string());
+            }
+            _ => {}
+        }
+    }
+    Ok(package_info)
+}
@@ -0,0 +1,119 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use log::{debug, info};
+
+use crate::configuration;
+use crate::errors::AppError;
+
+/// Points in the pipeline plugins can work on. Each stage runs the
+/// commands listed under its name in the libraries config's `[hooks]`,
+/// then an `app2nix-<stage>` executable on `PATH`, if there is one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PluginStage {
+    /// Gets the extracted tree's path as its argument and may change the
+    /// tree before it is scanned.
+    PostExtract,
+    /// Reads the resolved nixpkgs attrs on stdin, one per line, and prints
+    /// the list to use instead.
+    Deps,
+    /// Reads a rendered expression on stdin and prints the one to write;
+    /// the file name is its argument.
+    Render,
+}
+
+impl PluginStage {
+    pub fn name(self) -> &'static str {
+        match self {
+            PluginStage::PostExtract => "post-extract",
+            PluginStage::Deps => "deps",
+            PluginStage::Render => "render",
+        }
+    }
+}
+
+/// The command lines `stage` runs, in order.
+pub fn commands(stage: PluginStage) -> Vec<String> {
+    let hooks = configuration::get_hooks();
+    let mut commands = match stage {
+        PluginStage::PostExtract => hooks.post_extract.clone(),
+        PluginStage::Deps => hooks.deps.clone(),
+        PluginStage::Render => hooks.render.clone(),
+    };
+    let executable = format!("app2nix-{}", stage.name());
+    if crate::has_tool(&executable) {
+        commands.push(executable);
+    }
+    commands
+}
+
+/// Runs `command` with `arg` appended, feeding it `input`, and returns its
+/// stdout; its stderr goes to ours. A plugin failing stops the run.
+fn run(stage: PluginStage, command: &str, arg: Option<&str>, input: &str) -> Result<String, AppError> {
+    let failed = |detail: String| {
+        let message = format!("{} plugin {} failed: {}", stage.name(), command, detail);
+        match stage {
+            PluginStage::PostExtract => AppError::Extract(message),
+            PluginStage::Deps | PluginStage::Render => AppError::Generate(message),
+        }
+    };
+    let mut words = command.split_whitespace();
+    let program = words.next().ok_or_else(|| failed("empty command".to_string()))?;
+    debug!("Running {} plugin: {}", stage.name(), command);
+    let mut child = Command::new(program)
+        .args(words)
+        .args(arg)
+        .env("APP2NIX_STAGE", stage.name())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| failed(e.to_string()))?;
+    // A plugin that does not read its input closes the pipe early
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(input.as_bytes());
+    }
+    let output = child.wait_with_output().map_err(|e| failed(e.to_string()))?;
+    if !output.status.success() {
+        return Err(failed(output.status.to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Runs the post-extract plugins on the unpacked tree at `dir`.
+pub fn post_extract(dir: &str) -> Result<(), AppError> {
+    for command in commands(PluginStage::PostExtract) {
+        info!("Running post-extract plugin {}...", command);
+        run(PluginStage::PostExtract, &command, Some(dir), "")?;
+    }
+    Ok(())
+}
+
+/// Passes the resolved dependencies through the deps plugins.
+pub fn adjust_deps(deps: &mut Vec<String>) -> Result<(), AppError> {
+    for command in commands(PluginStage::Deps) {
+        let output = run(PluginStage::Deps, &command, None, &format!("{}\n", deps.join("\n")))?;
+        let adjusted: Vec<String> = output
+            .lines()
+            .map(|l| l.trim().trim_start_matches("pkgs.").to_string())
+            .filter(|l| !l.is_empty())
+            .collect();
+        if adjusted != *deps {
+            info!("The deps plugin {} changed the dependencies to: {}", command, adjusted.join(", "));
+        }
+        *deps = adjusted;
+    }
+    Ok(())
+}
+
+/// Passes the expression rendered for `path` through the render plugins.
+pub fn rewrite(path: &str, content: &str) -> Result<String, AppError> {
+    let mut content = content.to_string();
+    for command in commands(PluginStage::Render) {
+        let output = run(PluginStage::Render, &command, Some(path), &content)?;
+        if output.trim().is_empty() {
+            return Err(AppError::Generate(format!("render plugin {} printed nothing for {}", command, path)));
+        }
+        content = output;
+    }
+    Ok(content)
+}
@@ -0,0 +1,137 @@
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use log::info;
+
+/// Set by `init`; `None` when bars are off (stderr is not a terminal, `-q`
+/// or JSON logs).
+static BARS: OnceLock<Option<MultiProgress>> = OnceLock::new();
+
+/// Turns progress bars on if `enabled` and stderr is a terminal; otherwise
+/// long steps report plain lines.
+pub fn init(enabled: bool) {
+    let bars = (enabled && std::io::stderr().is_terminal()).then(MultiProgress::new);
+    let _ = BARS.set(bars);
+}
+
+fn bars() -> Option<&'static MultiProgress> {
+    BARS.get().and_then(Option::as_ref)
+}
+
+/// Whether bars are drawn.
+pub fn enabled() -> bool {
+    bars().is_some()
+}
+
+/// Runs `f` with the bars cleared, so a log line is not drawn over.
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    match bars() {
+        Some(bars) => bars.suspend(f),
+        None => f(),
+    }
+}
+
+/// Without a terminal, steps over fewer items than this only log their
+/// duration.
+const PLAIN_REPORT_MIN: u64 = 100;
+
+/// A long-running step: a bar (a spinner if its length is unknown) on a
+/// terminal, otherwise a line every tenth of the way. Its duration is
+/// logged when it finishes.
+pub struct Stage {
+    label: String,
+    bar: Option<ProgressBar>,
+    len: Option<u64>,
+    position: u64,
+    reported: u64,
+    started: Instant,
+}
+
+impl Stage {
+    /// A step over `len` items, e.g. files or libraries.
+    pub fn new(label: &str, len: Option<u64>) -> Stage {
+        let template = match len {
+            Some(_) => "{spinner} {prefix} [{elapsed}] [{bar:30}] {pos}/{len} {wide_msg}",
+            None => "{spinner} {prefix} [{elapsed}] {wide_msg}",
+        };
+        Stage::with_template(label, len, template)
+    }
+
+    /// A step counted in bytes, e.g. a download.
+    pub fn bytes(label: &str, len: Option<u64>) -> Stage {
+        let template = match len {
+            Some(_) => "{spinner} {prefix} [{elapsed}] [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec}) {wide_msg}",
+            None => "{spinner} {prefix} [{elapsed}] {bytes} ({bytes_per_sec}) {wide_msg}",
+        };
+        Stage::with_template(label, len, template)
+    }
+
+    fn with_template(label: &str, len: Option<u64>, template: &str) -> Stage {
+        let bar = bars().map(|bars| {
+            let bar = bars.add(len.map_or_else(ProgressBar::new_spinner, ProgressBar::new));
+            if let Ok(style) = ProgressStyle::with_template(template) {
+                bar.set_style(style.progress_chars("=> "));
+            }
+            bar.set_prefix(label.to_string());
+            bar.enable_steady_tick(Duration::from_millis(100));
+            bar
+        });
+        Stage {
+            label: label.to_string(),
+            bar,
+            len,
+            position: 0,
+            reported: 0,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn inc(&mut self, delta: u64) {
+        self.set_position(self.position + delta);
+    }
+
+    pub fn set_position(&mut self, position: u64) {
+        self.position = position;
+        match (&self.bar, self.len) {
+            (Some(bar), _) => bar.set_position(position),
+            (None, Some(len)) if len >= PLAIN_REPORT_MIN => {
+                let tenth = position * 10 / len;
+                if tenth > self.reported && position < len {
+                    self.reported = tenth;
+                    info!("{}: {}/{}", self.label, position, len);
+                }
+            }
+            (None, _) => {}
+        }
+    }
+
+    /// Shows what the step is working on, e.g. the current file.
+    pub fn set_message(&self, message: &str) {
+        if let Some(bar) = &self.bar {
+            bar.set_message(message.to_string());
+        }
+    }
+
+    /// Ends the step and logs how long it took.
+    pub fn finish(mut self) {
+        self.clear();
+        info!("{} took {:.1}s", self.label, self.started.elapsed().as_secs_f64());
+    }
+
+    fn clear(&mut self) {
+        if let Some(bar) = self.bar.take() {
+            bar.finish_and_clear();
+            if let Some(bars) = bars() {
+                bars.remove(&bar);
+            }
+        }
+    }
+}
+
+impl Drop for Stage {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
@@ -1,262 +1,1242 @@
-use std::collections::HashSet;
-use std::error::Error;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
+use std::env;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
 use std::process::Command;
 
-use tempfile::tempdir;
+use log::{debug, error, info, warn};
+use tempfile::TempDir;
 use walkdir::WalkDir;
 
-use crate::structs::PackageInfo;
+use crate::archive;
+use crate::changelog;
+use crate::deb822;
+use crate::closure::human_size;
+use crate::detection::{
+    detect_app_kind, detect_data_kinds, detect_extra_files, detect_integration, dkms_source_dir, is_electron, is_executable, prebuilt_kernel_release,
+    read_desktop_entry, script_interpreters,
+};
+use crate::elf;
+use crate::errors::AppError;
+use crate::license;
+use crate::pkginfo;
+use crate::plugins;
+use crate::progress::Stage;
+use crate::snap;
+use crate::structs::{
+    AppKind, BinaryInfo, BundledLib, DataKind, ElfKind, LibConflict, PackageInfo, PackageType, PayloadLink, PrivilegedFile,
+    ScanOptions, ScanResult,
+};
 use crate::configuration::{
     get_pkg_for_lib,
     is_system_lib,
 };
+use crate::resolver::resolve_lib_via_locate;
 
-fn ensure_tools_dependencies() -> Result<(), Box<dyn Error>> {
-    let tools = vec!["patchelf", "ar", "tar"];
-    let mut missing = Vec::new();
-
-    for tool in tools {
-        let output = Command::new("which").arg(tool).output();
-        match output {
-            Ok(out) if out.status.success() => {},
-            _ => missing.push(tool),
-        }
+/// Where the extraction tree will live: `--keep-workdir=<dir>`, else
+/// `--tmpdir`/`APP2NIX_TMPDIR`, else `TMPDIR`.
+fn extraction_parent(options: &ScanOptions) -> PathBuf {
+    match (&options.workdir, &options.tmpdir) {
+        (Some(dir), _) => PathBuf::from(dir),
+        (None, Some(dir)) => PathBuf::from(dir),
+        (None, None) => env::temp_dir(),
     }
+}
 
-    if !missing.is_empty() {
-        return Err(format!("Missing required tools: {}", missing.join(", ")).into());
+/// Free bytes on the filesystem holding `path`, or its closest existing
+/// ancestor.
+fn available_space(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|p| p.exists())?;
+    let c_path = CString::new(existing.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `c_path` is NUL-terminated and `stat` is a valid out pointer
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
     }
-
-    Ok(())
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
 }
 
-fn resolve_lib_via_locate(lib_name: &str) -> Option<String> {
-    if let Some(pkg) = get_pkg_for_lib(lib_name) {
-        return Some(pkg.clone());
-    }
+/// Fails before unpacking if the extraction target cannot hold the
+/// packages plus the unpacked tree, instead of tar dying halfway through.
+fn ensure_extraction_space(pkg_paths: &[String], installed_size: Option<u64>, options: &ScanOptions) -> Result<(), AppError> {
+    let archive_size: u64 = pkg_paths
+        .iter()
+        .map(|p| fs::metadata(p).map(|m| m.len()).unwrap_or(0))
+        .sum();
+    // Without Installed-Size, assume a typical xz ratio for the unpacked tree
+    let needed = archive_size + installed_size.unwrap_or(archive_size * 4);
 
-    let search_path = format!("/lib/{}", lib_name);
+    let parent = extraction_parent(options);
+    let Some(available) = available_space(&parent) else {
+        return Ok(());
+    };
+    if available < needed {
+        return Err(AppError::Extract(format!(
+            "Not enough space in {} to unpack the package: {} needed, {} available. \
+             Pass --tmpdir or set APP2NIX_TMPDIR to a larger location.",
+            parent.display(),
+            human_size(needed),
+            human_size(available)
+        )));
+    }
+    Ok(())
+}
 
+/// Directory to extract the deb into, with the guard that deletes it.
+fn prepare_workdir(options: &ScanOptions) -> Result<(Option<TempDir>, PathBuf), AppError> {
+    let temp = || {
+        let parent = extraction_parent(options);
+        tempfile::Builder::new()
+            .prefix("app2nix-")
+            .tempdir_in(&parent)
+            .map_err(|e| AppError::Extract(format!("Failed to create temp dir in {}: {}", parent.display(), e)))
+    };
 
-    let which_output = Command::new("which").arg("nix-locate").output();
-    if which_output.is_err() || !which_output.unwrap().status.success() {
-        return None;
+    if !options.keep_workdir {
+        let dir = temp()?;
+        let path = dir.path().to_path_buf();
+        return Ok((Some(dir), path));
     }
 
-    let output = Command::new("nix-locate")
-        .args(["--top-level", "--minimal", "--at-root", "--whole-name", &search_path])
-        .output()
-        .ok()?;
-
-    if output.status.success() {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        if let Some(line) = stdout.lines().next() {
-            let trimmed = line.trim();
-            if !trimmed.is_empty() {
-                let parts: Vec<&str> = trimmed.split('.').collect();
-                return Some(parts.last().unwrap_or(&trimmed).to_string());
+    let path = match &options.workdir {
+        Some(dir) => {
+            let path = PathBuf::from(dir);
+            let occupied = fs::read_dir(&path).is_ok_and(|mut entries| entries.next().is_some());
+            if occupied {
+                return Err(AppError::Input(format!("Work directory {} is not empty", dir)));
             }
+            fs::create_dir_all(&path).map_err(|e| AppError::Extract(format!("{}: {}", dir, e)))?;
+            path
         }
-    }
+        None => temp()?.keep(),
+    };
+    info!("Keeping extracted tree at {}", path.display());
+    Ok((None, path))
+}
 
-    let output_loose = Command::new("nix-locate")
-        .args(["--top-level", "--minimal", "--whole-name", lib_name])
-        .output()
-        .ok()?;
+/// Package set holding the 32-bit variants of nixpkgs attrs on x86_64.
+pub const PKGS_I686: &str = "pkgsi686Linux";
 
-    let stdout_loose = String::from_utf8_lossy(&output_loose.stdout);
-    if let Some(line) = stdout_loose.lines().next() {
-        let trimmed = line.trim();
-        if !trimmed.is_empty() {
-            let parts: Vec<&str> = trimmed.split('.').collect();
-            return Some(parts.last().unwrap_or(&trimmed).to_string());
-        }
-    }
+/// Package set built against musl instead of glibc.
+pub const PKGS_MUSL: &str = "pkgsMusl";
 
-    None
+/// Libraries only the host's GPU driver provides (under
+/// `/run/opengl-driver/lib` on NixOS); no nixpkgs attr should stand in.
+pub const DRIVER_LIBS: &[&str] = &[
+    "libcuda.so",
+    "libcuda.so.1",
+    "libnvcuvid.so.1",
+    "libnvidia-encode.so.1",
+    "libnvidia-fbc.so.1",
+    "libnvidia-ml.so.1",
+];
+
+/// Sound server client libraries; which package provides them depends on
+/// the server in use, so the generator wires them instead of the resolver.
+pub const AUDIO_LIBS: &[&str] = &[
+    "libjack.so.0",
+    "libpipewire-0.3.so.0",
+    "libpulse-simple.so.0",
+    "libpulse.so.0",
+];
+
+fn is_musl(binary: &BinaryInfo) -> bool {
+    binary.interpreter.as_deref().is_some_and(|i| i.contains("ld-musl"))
+        || binary.needed.iter().any(|l| l.starts_with("libc.musl-"))
 }
 
-fn scan_binary_and_resolve(deb_path: &str) -> Result<(Vec<String>, Vec<String>), Box<dyn Error>> {
-    println!(">>> Unpacking and scanning binary dependencies (this may take a moment)...");
+/// Why the scan did not look at a file's libraries.
+#[derive(Clone, Copy)]
+enum SkipReason {
+    NotElf,
+    Unreadable,
+    /// An ELF for an instruction set the target cannot run.
+    WrongArch,
+    /// Over `--max-scan-size`.
+    TooLarge,
+    /// Relocatable objects, core dumps and other ELF files that are neither
+    /// executables nor shared objects.
+    OtherElf,
+    /// A second name of a file already scanned.
+    HardLink,
+}
 
+impl SkipReason {
+    const ALL: [SkipReason; 6] = [
+        SkipReason::NotElf,
+        SkipReason::Unreadable,
+        SkipReason::WrongArch,
+        SkipReason::TooLarge,
+        SkipReason::OtherElf,
+        SkipReason::HardLink,
+    ];
 
-    ensure_tools_dependencies()?;
+    fn label(self) -> &'static str {
+        match self {
+            SkipReason::NotElf => "not ELF",
+            SkipReason::Unreadable => "unreadable",
+            SkipReason::WrongArch => "another architecture",
+            SkipReason::TooLarge => "larger than --max-scan-size",
+            SkipReason::OtherElf => "neither executable nor shared object",
+            SkipReason::HardLink => "hard link to a scanned file",
+        }
+    }
+}
 
-    let tmp_dir = tempdir()?;
-    let tmp_path = tmp_dir.path();
-    let abs_deb_path = fs::canonicalize(deb_path)?;
+/// Files skipped by the scan, counted by reason; each is listed with `-v`.
+#[derive(Default)]
+struct Skipped {
+    counts: [usize; SkipReason::ALL.len()],
+}
 
+impl Skipped {
+    fn add(&mut self, reason: SkipReason, rel: &str, detail: &str) {
+        self.counts[reason as usize] += 1;
+        if detail.is_empty() {
+            debug!("[skipped, {}] {}", reason.label(), rel);
+        } else {
+            debug!("[skipped, {}: {}] {}", reason.label(), detail, rel);
+        }
+    }
 
-    let ar_output = Command::new("ar")
-        .arg("x")
-        .arg(&abs_deb_path)
-        .current_dir(tmp_path)
-        .output()?;
+    fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
 
-    if !ar_output.status.success() {
-        return Err("Failed to unpack deb archive with 'ar'".into());
+    /// Summarizes the skipped files; those whose libraries might be missing
+    /// from the output as a result are a warning.
+    fn report(&self) {
+        let summary: Vec<String> = SkipReason::ALL
+            .iter()
+            .filter(|r| self.counts[**r as usize] > 0)
+            .map(|r| format!("{} {}", self.counts[*r as usize], r.label()))
+            .collect();
+        if !summary.is_empty() {
+            let hint = if log::log_enabled!(log::Level::Debug) { "" } else { " Pass -v to list them." };
+            info!("Skipped: {}.{}", summary.join(", "), hint);
+        }
+        let unscanned = [SkipReason::Unreadable, SkipReason::WrongArch, SkipReason::TooLarge];
+        for reason in unscanned {
+            let count = self.counts[reason as usize];
+            if count > 0 {
+                warn!(
+                    "{} files were not scanned ({}); libraries only they need are not resolved.",
+                    count,
+                    reason.label()
+                );
+            }
+        }
     }
+}
 
+fn scan_binary_and_resolve(
+    pkg_paths: &[String],
+    pkg_type: PackageType,
+    control_depends: &[String],
+    target_system: &str,
+    options: &ScanOptions,
+) -> Result<ScanResult, AppError> {
+    info!("Unpacking and scanning binary dependencies (this may take a moment)...");
 
-    let mut data_tar: Option<String> = None;
-    for entry in fs::read_dir(tmp_path)? {
-        let entry = entry?;
-        let name_str = entry.file_name().to_string_lossy().to_string();
-        if name_str.starts_with("data.tar") {
-            data_tar = Some(name_str);
-            break;
+
+    // The tree is removed when `_cleanup` drops, unless it is kept for inspection
+    let (_cleanup, tmp_path) = prepare_workdir(options)?;
+    let tmp_path = tmp_path.as_path();
+    // Several packages are unpacked over each other into one merged tree
+    let unpacking = Stage::new("Unpacking", None);
+    for pkg_path in pkg_paths {
+        let abs_pkg_path =
+            fs::canonicalize(pkg_path).map_err(|e| AppError::Extract(format!("{}: {}", pkg_path, e)))?;
+        unpacking.set_message(pkg_path);
+
+        match pkg_type {
+            PackageType::Deb => {
+                let data_member = archive::unpack_member(&abs_pkg_path, "data", tmp_path)?;
+                debug!("Unpacked {}", data_member);
+            }
+            PackageType::Pacman | PackageType::Apk => archive::unpack_tarball(&abs_pkg_path, tmp_path)?,
+            PackageType::Snap => snap::unsquash(&abs_pkg_path, tmp_path, &[])?,
+            PackageType::Directory => copy_tree(&abs_pkg_path, &tmp_path.join(tree_prefix(&abs_pkg_path)))?,
         }
     }
+    unpacking.finish();
+    plugins::post_extract(&tmp_path.display().to_string())?;
 
-    let tar_name = data_tar.ok_or("Could not find data.tar.* archive inside deb")?;
+    let files: Vec<String> = WalkDir::new(tmp_path)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.path().strip_prefix(tmp_path).unwrap_or(e.path()).to_string_lossy().to_string())
+        .collect();
+    if let Some(kind) = payload_kind(&files)? {
+        return Ok(ScanResult {
+            kind,
+            integration: detect_integration(&files),
+            extra_files: detect_extra_files(&files),
+            licenses: tree_licenses(tmp_path, &files),
+            changelog: changelog::from_tree(tmp_path, &files),
+            ..ScanResult::default()
+        });
+    }
 
-    let tar_output = Command::new("tar")
-        .arg("xf")
-        .arg(&tar_name)
-        .current_dir(tmp_path)
-        .output()?;
+    let mut kind = detect_app_kind(tmp_path, control_depends);
+    let electron = is_electron(&files);
+    if electron {
+        info!("Detected Electron application.");
+    }
+    let mut desktop_entries: Vec<_> = files
+        .iter()
+        .filter(|f| f.starts_with("usr/share/applications/") && f.ends_with(".desktop"))
+        .filter_map(|f| read_desktop_entry(tmp_path, f))
+        .collect();
+    desktop_entries.sort_by(|a, b| a.id.cmp(&b.id));
+    let mut autostart: Vec<String> = files
+        .iter()
+        .filter(|f| f.starts_with("etc/xdg/autostart/") && f.ends_with(".desktop"))
+        .cloned()
+        .collect();
+    autostart.sort();
+    match &kind {
+        AppKind::DotNet { entry, .. } => info!("Detected .NET application: {}", entry),
+        AppKind::Mono { entry } => info!("Detected Mono application: {}", entry),
+        AppKind::Python { entries, .. } => info!("Detected Python application: {}", entries.join(", ")),
+        AppKind::Native
+        | AppKind::Static { .. }
+        | AppKind::ArchIndependent
+        | AppKind::Dkms { .. }
+        | AppKind::Data { .. } => {}
+    }
 
-    if !tar_output.status.success() {
-        eprintln!("Warning: failed to extract {}", tar_name);
+    let interpreters = script_interpreters(tmp_path);
+    if !interpreters.is_empty() {
+        info!("Found scripts requiring interpreters: {}", interpreters.join(", "));
     }
 
-    let mut needed_libs = HashSet::new();
-    let mut resolved_packages = HashSet::new();
+    let mut needed_libs = BTreeSet::new();
+    let mut needed_libs_32 = BTreeSet::new();
+    let mut resolved_packages = BTreeSet::new();
     let mut missing_libs = Vec::new();
 
 
-    let mut bundled_files = HashSet::new();
-    for entry in WalkDir::new(tmp_path).into_iter().filter_map(|e| e.ok()) {
-        if entry.file_type().is_file() {
-            if let Some(fname) = entry.file_name().to_str() {
-                bundled_files.insert(fname.to_string());
-            }
+    let mut bundled_files = BTreeSet::new();
+    let mut links = Vec::new();
+    let mut dangling = Vec::new();
+    for entry in WalkDir::new(tmp_path).sort_by_file_name().into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file()
+            && let Some(fname) = entry.file_name().to_str()
+        {
+            bundled_files.insert(fname.to_string());
+        }
+        if !entry.file_type().is_symlink() {
+            continue;
+        }
+        let Ok(target) = fs::read_link(entry.path()) else {
+            continue;
+        };
+        let link = entry.path().strip_prefix(tmp_path).unwrap_or(entry.path()).to_string_lossy().to_string();
+        // Chains through absolute links end at the payload's copy too
+        if payload_target(tmp_path, &link).is_none() {
+            dangling.push(link);
+            continue;
+        }
+        if let Ok(absolute) = target.strip_prefix("/") {
+            // `/opt/App/app` only exists on the vendor's target system; inside
+            // $out the link has to point at the installed copy
+            debug!("[symlink] {} -> /{}", link, absolute.display());
+            links.push(PayloadLink {
+                link,
+                target: absolute.to_string_lossy().to_string(),
+            });
+            continue;
+        }
+        // `usr/bin/foo -> ../lib/foo/foo` survives `cp -a`; a link from usr
+        // into opt does not, as the two trees are installed apart
+        if let Some(resolved) = resolve_relative(&link, &target)
+            && link.split('/').next() != resolved.split('/').next()
+        {
+            debug!("[symlink] {} -> {} ({})", link, target.display(), resolved);
+            links.push(PayloadLink { link, target: resolved });
         }
     }
+    if !dangling.is_empty() {
+        warn!(
+            "{} symlinks point at files the package does not ship and are left out: {}",
+            dangling.len(),
+            dangling.join(", ")
+        );
+    }
 
-
-    for entry in WalkDir::new(tmp_path).into_iter().filter_map(|e| e.ok()) {
+    let mut binaries = Vec::new();
+    let mut referenced_libs = BTreeSet::new();
+    let mut skipped = Skipped::default();
+    let mut inodes = BTreeMap::new();
+    let mut privileged = Vec::new();
+    let mut non_executable = Vec::new();
+    let mut scanning = Stage::new("Scanning files", Some(files.len() as u64));
+    for entry in WalkDir::new(tmp_path).sort_by_file_name() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let rel = e.path().and_then(|p| p.strip_prefix(tmp_path).ok()).unwrap_or(Path::new(""));
+                skipped.add(SkipReason::Unreadable, &rel.to_string_lossy(), &e.to_string());
+                continue;
+            }
+        };
         if !entry.file_type().is_file() {
             continue;
         }
+        scanning.inc(1);
+        let rel = entry.path().strip_prefix(tmp_path).unwrap_or(entry.path()).to_string_lossy().to_string();
+        let meta = entry.metadata().ok();
+        let mode = meta.as_ref().map_or(0o755, |m| m.mode());
+        // The store drops setuid and setgid bits; each link of a privileged
+        // file is a program of its own
+        if mode & 0o6000 != 0 {
+            debug!("[mode {:o}] {}", mode & 0o7777, rel);
+            privileged.push(PrivilegedFile {
+                path: rel.clone(),
+                capabilities: None,
+                setuid: mode & 0o4000 != 0,
+                setgid: mode & 0o2000 != 0,
+            });
+        }
+        // Hard links share one inode; `cp -a` keeps them linked
+        if let Some(meta) = &meta
+            && meta.nlink() > 1
+        {
+            if let Some(first) = inodes.get(&(meta.dev(), meta.ino())) {
+                skipped.add(SkipReason::HardLink, &rel, &format!("same file as {}", first));
+                continue;
+            }
+            inodes.insert((meta.dev(), meta.ino()), rel.clone());
+        }
 
-        let output = Command::new("patchelf")
-            .arg("--print-needed")
-            .arg(entry.path())
-            .output();
+        // Resources, locales and archives make up most of a payload; only
+        // their first four bytes are read
+        if !elf::has_elf_magic(entry.path()) {
+            match fs::File::open(entry.path()) {
+                Err(e) => skipped.add(SkipReason::Unreadable, &rel, &e.to_string()),
+                Ok(_) => skipped.add(SkipReason::NotElf, &rel, ""),
+            }
+            continue;
+        }
 
-        if let Ok(out) = output {
-            if out.status.success() {
-                let stdout = String::from_utf8_lossy(&out.stdout);
-                for line in stdout.lines() {
-                    let lib = line.trim();
-                    if lib.is_empty() {
-                        continue;
-                    }
+        scanning.set_message(&rel);
+        let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        if options.max_scan_size.is_some_and(|limit| size > limit) {
+            skipped.add(SkipReason::TooLarge, &rel, &human_size(size));
+            continue;
+        }
 
+        let Some(binary) = elf::inspect(entry.path(), &rel) else {
+            skipped.add(SkipReason::OtherElf, &rel, "");
+            continue;
+        };
+        if !elf::runs_on(binary.machine, target_system) {
+            skipped.add(SkipReason::WrongArch, &rel, &format!("e_machine {}", binary.machine));
+            continue;
+        }
+        let kind_label = match binary.kind {
+            ElfKind::Dynamic => "dynamic",
+            ElfKind::SharedLibrary => "shared library",
+            ElfKind::Static => "static",
+        };
+        debug!(
+            "[{}{}] {}",
+            kind_label,
+            if binary.is_go { ", go" } else { "" },
+            binary.path
+        );
+        if binary.kind != ElfKind::SharedLibrary && binary.soname.is_none() && mode & 0o111 == 0 {
+            debug!("[mode {:o}] {} is an executable without an exec bit", mode & 0o7777, rel);
+            non_executable.push(rel.clone());
+        }
+        referenced_libs.extend(binary.dlopen_names.iter().cloned());
+        referenced_libs.extend(binary.needed.iter().cloned());
+        binaries.push(binary);
+    }
+    scanning.finish();
 
-                    if is_system_lib(lib) {
-                        continue;
-                    }
+    // Shipped ELF libraries by soname and file name, with their word size
+    let shipped: Vec<(&str, bool)> = binaries
+        .iter()
+        .filter(|b| b.kind == ElfKind::SharedLibrary)
+        .flat_map(|b| {
+            let fname = b.path.rsplit('/').next().unwrap_or(&b.path);
+            b.soname.as_deref().into_iter().chain([fname]).map(move |name| (name, b.is_32bit))
+        })
+        .collect();
+    let mut via_search_path = 0;
+    for binary in &binaries {
+        // 32-bit objects in an x86_64 package link against pkgsi686Linux
+        let is_multilib = binary.is_32bit && target_system == "x86_64-linux";
+        let search_dirs: Vec<String> =
+            binary.search_paths.iter().filter_map(|p| elf::search_dir(p, &binary.path)).collect();
+        for lib in &binary.needed {
+            if is_system_lib(lib) || DRIVER_LIBS.contains(&lib.as_str()) || AUDIO_LIBS.contains(&lib.as_str()) {
+                continue;
+            }
+            // Internal if the binary's RUNPATH/RPATH finds it in the payload,
+            // else if the payload ships a library of that name and word size;
+            // a same-named file that was not inspected (too large, not ELF)
+            // still counts
+            let on_search_path = search_dirs.iter().find(|d| tmp_path.join(d).join(lib).exists());
+            let internal = on_search_path.is_some()
+                || shipped.iter().any(|(name, is_32bit)| name == lib && *is_32bit == binary.is_32bit)
+                || (!shipped.iter().any(|(name, _)| name == lib) && bundled_files.contains(lib));
+            if let Some(dir) = on_search_path {
+                debug!("[search path] {} needs {}, found in {}", binary.path, lib, dir);
+                via_search_path += 1;
+            }
+            if get_pkg_for_lib(lib).is_some() || !internal {
+                if is_multilib {
+                    needed_libs_32.insert(lib.clone());
+                } else {
+                    needed_libs.insert(lib.clone());
+                }
+            }
+        }
+    }
+    if via_search_path > 0 {
+        info!("{} library references are satisfied through the binaries' RUNPATH/RPATH.", via_search_path);
+    }
 
+    info!(
+        "Classified {} ELF files ({} static), skipped {} files.",
+        binaries.len(),
+        binaries.iter().filter(|b| b.kind == ElfKind::Static).count(),
+        skipped.total()
+    );
+    skipped.report();
 
+    // A payload of only static binaries needs none of the patchelf machinery
+    if kind == AppKind::Native
+        && !binaries.is_empty()
+        && binaries.iter().all(|b| b.kind == ElfKind::Static)
+    {
+        kind = AppKind::Static {
+            binaries: binaries
+                .iter()
+                .filter(|b| is_executable(&tmp_path.join(&b.path)))
+                .map(|b| b.path.clone())
+                .collect(),
+        };
+    }
 
-                    if get_pkg_for_lib(lib).is_some() || !bundled_files.contains(lib) {
-                        needed_libs.insert(lib.to_string());
-                    }
-                }
+    info!(
+        "Identified {} unique shared libraries required by binaries.",
+        needed_libs.len() + needed_libs_32.len()
+    );
+
+    let multilib = binaries.iter().any(|b| b.is_32bit) && target_system == "x86_64-linux";
+    if multilib {
+        info!(
+            "Found 32-bit objects, resolving {} libraries against {}.",
+            needed_libs_32.len(),
+            PKGS_I686
+        );
+    }
+
+    // musl binaries cannot load glibc builds of their dependencies
+    let musl = binaries.iter().any(is_musl);
+    if musl
+        && let Some(glibc) = binaries.iter().find(|b| !is_musl(b) && b.interpreter.is_some())
+    {
+        return Err(AppError::Input(format!(
+            "{} uses the glibc loader {} while other binaries are linked against musl; one derivation cannot provide libraries for both",
+            glibc.path,
+            glibc.interpreter.as_deref().unwrap_or_default()
+        )));
+    }
+    // i686 loaders on x86_64 are set in installPhase; any other architecture
+    // cannot run on the target at all
+    let foreign: Vec<String> = binaries
+        .iter()
+        .filter_map(|b| {
+            let interpreter = b.interpreter.as_deref()?;
+            match elf::loader_system(interpreter) {
+                Some(system) if system == target_system => None,
+                Some("i686-linux") if target_system == "x86_64-linux" => None,
+                _ => Some(format!("{} ({})", b.path, interpreter)),
             }
-        }
+        })
+        .collect();
+    if !foreign.is_empty() {
+        warn!(
+            "{} binaries request a dynamic loader of another architecture and are left unpatched: {}",
+            foreign.len(),
+            foreign.join(", ")
+        );
+    }
+    if musl {
+        warn!(
+            "Binaries are linked against musl libc; resolving libraries against {} instead of glibc builds.",
+            PKGS_MUSL
+        );
+    }
+
+    let bundled_libs: Vec<BundledLib> = binaries
+        .iter()
+        .filter(|b| b.kind == ElfKind::SharedLibrary)
+        .map(|b| BundledLib {
+            path: b.path.clone(),
+            soname: b.soname.clone(),
+            version: elf::library_version(&tmp_path.join(&b.path), b.soname.as_deref()),
+            nixpkgs: b.soname.as_deref().and_then(get_pkg_for_lib).map(|pkg| {
+                match (b.is_32bit && target_system == "x86_64-linux", musl) {
+                    (true, _) => format!("{}.{}", PKGS_I686, pkg),
+                    (false, true) => format!("{}.{}", PKGS_MUSL, pkg),
+                    (false, false) => pkg.clone(),
+                }
+            }),
+        })
+        .collect();
+    let mut in_nixpkgs: Vec<String> = bundled_libs
+        .iter()
+        .filter_map(|l| Some(format!("{} ({})", l.soname.as_ref()?, l.nixpkgs.as_ref()?)))
+        .collect();
+    in_nixpkgs.sort();
+    in_nixpkgs.dedup();
+    if !in_nixpkgs.is_empty() {
+        info!(
+            "{} bundled libraries are also in nixpkgs: {}; --prefer-system-libs links against those instead.",
+            in_nixpkgs.len(),
+            in_nixpkgs.join(", ")
+        );
     }
 
-    println!(">>> Identified {} unique shared libraries required by binaries.", needed_libs.len());
+    // A bundled copy of a library nixpkgs also has is settled later by
+    // --bundled-conflicts instead of being resolved here
+    let mut lib_conflicts = Vec::new();
+    for (needed, is_32bit) in [(&mut needed_libs, false), (&mut needed_libs_32, true)] {
+        let mut sonames: Vec<String> = needed.iter().cloned().collect();
+        sonames.sort();
+        for soname in sonames {
+            let bundled: Vec<String> = bundled_libs
+                .iter()
+                .filter(|l| l.soname.as_deref() == Some(soname.as_str()) || l.path.rsplit('/').next() == Some(soname.as_str()))
+                .map(|l| l.path.clone())
+                .collect();
+            let Some(pkg) = get_pkg_for_lib(&soname).filter(|_| !bundled.is_empty()) else {
+                continue;
+            };
+            needed.remove(&soname);
+            lib_conflicts.push(LibConflict {
+                nixpkgs: match (is_32bit, musl) {
+                    (true, _) => format!("{}.{}", PKGS_I686, pkg),
+                    (false, true) => format!("{}.{}", PKGS_MUSL, pkg),
+                    (false, false) => pkg.clone(),
+                },
+                soname,
+                bundled,
+                use_nixpkgs: false,
+            });
+        }
+    }
 
+    let mut needed_sonames: Vec<String> = needed_libs
+        .iter()
+        .chain(needed_libs_32.iter())
+        .chain(lib_conflicts.iter().map(|c| &c.soname))
+        .cloned()
+        .collect();
+    needed_sonames.sort();
+    needed_sonames.dedup();
 
-    for lib in needed_libs {
+    let lookups = needed_libs.len() + needed_libs_32.len();
+    let mut resolving = Stage::new("Resolving libraries", Some(lookups as u64));
+    let needed = needed_libs
+        .into_iter()
+        .map(|lib| (lib, false))
+        .chain(needed_libs_32.into_iter().map(|lib| (lib, true)));
+    for (lib, is_32bit) in needed {
+        resolving.set_message(&lib);
+        resolving.inc(1);
         match resolve_lib_via_locate(&lib) {
             Some(pkg) => {
-                println!("    [+] Resolved: {} -> pkgs.{}", lib, pkg);
+                let pkg = match (is_32bit, musl) {
+                    (true, _) => format!("{}.{}", PKGS_I686, pkg),
+                    (false, true) => format!("{}.{}", PKGS_MUSL, pkg),
+                    (false, false) => pkg,
+                };
+                debug!("[+] Resolved: {} -> pkgs.{}", lib, pkg);
                 resolved_packages.insert(pkg);
             }
             None => {
-                println!("    [!] Warning: Could not find package for library '{}'", lib);
+                debug!("[!] Could not find package for library '{}'", lib);
                 missing_libs.push(lib);
             }
         }
     }
 
+    resolving.finish();
+
     let mut result_pkgs: Vec<String> = resolved_packages.into_iter().collect();
     result_pkgs.sort();
     missing_libs.sort();
 
-    Ok((result_pkgs, missing_libs))
+    Ok(ScanResult {
+        deps: result_pkgs,
+        missing: missing_libs,
+        kind,
+        script_interpreters: interpreters,
+        multilib,
+        musl,
+        needed_libs: needed_sonames,
+        binaries,
+        bundled_libs,
+        referenced_libs: referenced_libs.into_iter().collect(),
+        links,
+        dangling_links: dangling,
+        privileged,
+        non_executable,
+        electron,
+        desktop_entries,
+        autostart,
+        integration: detect_integration(&files),
+        extra_files: detect_extra_files(&files),
+        lib_conflicts,
+        licenses: tree_licenses(tmp_path, &files),
+        changelog: changelog::from_tree(tmp_path, &files),
+    })
 }
 
-pub fn get_nix_shell(filename: &str, skip_deps: bool) -> Result<PackageInfo, Box<dyn Error>> {
-    if filename.is_empty() {
-        return Err("Filename cannot be empty".into());
+/// The licenses the Debian copyright files among `files` name.
+fn tree_licenses(root: &Path, files: &[String]) -> Vec<String> {
+    let mut licenses: Vec<String> = Vec::new();
+    let copyrights = files.iter().filter(|f| f.starts_with("usr/share/doc/") && f.ends_with("/copyright"));
+    for content in copyrights.filter_map(|f| fs::read_to_string(root.join(f)).ok()) {
+        for name in license::from_copyright(&content) {
+            if !licenses.contains(&name) {
+                licenses.push(name);
+            }
+        }
     }
+    licenses
+}
+
+fn data_kinds_label(kinds: &[DataKind]) -> String {
+    kinds
+        .iter()
+        .map(|k| match k {
+            DataKind::Fonts => "fonts",
+            DataKind::Icons => "icons",
+            DataKind::Mime => "MIME data",
+            DataKind::Firmware => "firmware",
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
 
-    let mut package_info = PackageInfo::default();
+/// Payloads that skip the binary pipeline: DKMS sources and data-only
+/// packages. Prebuilt kernel modules cannot be loaded by a nixpkgs kernel,
+/// so they are refused outright.
+fn payload_kind(files: &[String]) -> Result<Option<AppKind>, AppError> {
+    if let Some(source_dir) = dkms_source_dir(files) {
+        info!("DKMS module sources in {}, generating a kernel module derivation.", source_dir);
+        return Ok(Some(AppKind::Dkms { source_dir }));
+    }
+    if let Some(release) = prebuilt_kernel_release(files) {
+        return Err(AppError::Input(format!(
+            "The package ships kernel modules prebuilt for kernel {}, which a NixOS kernel cannot load. \
+             Convert the vendor's DKMS package (usually named <name>-dkms) instead; app2nix builds it \
+             against linuxPackages.kernel.",
+            release
+        )));
+    }
+    if let Some(kinds) = detect_data_kinds(files) {
+        info!("Data-only package ({}), skipping binary scan.", data_kinds_label(&kinds));
+        return Ok(Some(AppKind::Data { kinds }));
+    }
+    Ok(None)
+}
 
+/// Files of the package payload, listed without unpacking.
+fn list_payload(filename: &str, pkg_type: PackageType) -> Result<Vec<String>, AppError> {
+    let path = Path::new(filename);
+    match pkg_type {
+        PackageType::Deb => archive::list_member(path, "data"),
+        PackageType::Pacman | PackageType::Apk => archive::list_tarball(path),
+        PackageType::Snap => Ok(Vec::new()),
+        PackageType::Directory => {
+            let prefix = Path::new(&tree_prefix(path)).to_path_buf();
+            Ok(WalkDir::new(path)
+                .sort_by_file_name()
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .map(|e| prefix.join(e.path().strip_prefix(path).unwrap_or(e.path())).to_string_lossy().to_string())
+                .collect())
+        }
+    }
+}
 
-    let output = Command::new("dpkg")
-        .arg("--info")
-        .arg(filename)
-        .output();
+/// Top-level directories that make a directory input a filesystem tree
+/// rather than one application's directory.
+const ROOT_DIRS: &[&str] = &["usr", "opt", "bin", "sbin", "lib", "lib64", "etc"];
 
-    let output = match output {
-        Ok(ref out) if out.status.success() => Ok(out.clone()),
+/// Where the contents of directory input `dir` go in the package root:
+/// nowhere else if it has its own usr/, opt/, ... (an installed tree or an
+/// extracted AppImage), else `opt/<dir name>`.
+pub fn tree_prefix(dir: &Path) -> String {
+    if ROOT_DIRS.iter().any(|d| dir.join(d).is_dir()) {
+        return String::new();
+    }
+    format!("opt/{}", dir.file_name().unwrap_or_default().to_string_lossy())
+}
+
+/// Copies directory input `dir` into the scan tree at `dest`, symlinks and
+/// modes as they are.
+fn copy_tree(dir: &Path, dest: &Path) -> Result<(), AppError> {
+    fs::create_dir_all(dest).map_err(|e| AppError::Extract(format!("Failed to create {}: {}", dest.display(), e)))?;
+    let status = Command::new("cp")
+        .arg("-a")
+        .arg(dir.join("."))
+        .arg(dest)
+        .status()
+        .map_err(|e| AppError::Extract(format!("Failed to run cp: {}", e)))?;
+    if !status.success() {
+        return Err(AppError::Extract(format!("Failed to copy {}", dir.display())));
+    }
+    Ok(())
+}
+
+/// Metadata of a directory input, which has no control file: name and
+/// version come from the directory name (`app-1.2.3`), the rest from the
+/// scan.
+fn read_directory_info(dir: &Path) -> Result<PackageInfo, AppError> {
+    let dir = fs::canonicalize(dir).map_err(|e| AppError::Input(format!("{}: {}", dir.display(), e)))?;
+    let base = dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let (name, version) = match base.rsplit_once('-') {
+        Some((name, version)) if !name.is_empty() && version.starts_with(|c: char| c.is_ascii_digit()) => {
+            (name.to_string(), version.to_string())
+        }
         _ => {
+            warn!("The directory name {} carries no version; pass --version.", base);
+            (base.clone(), "0".to_string())
+        }
+    };
+    Ok(PackageInfo {
+        package_type: PackageType::Directory,
+        description: format!("{} packaged from an unpacked directory", name),
+        name,
+        version,
+        ..PackageInfo::default()
+    })
+}
 
-            let cmd = format!("dpkg-deb -f '{}'", filename);
-            Command::new("nix-shell")
-                .args(["-p", "dpkg", "--run", &cmd])
-                .output()
-        }
-    }.map_err(|e| format!("Failed to read deb info: {}", e))?;
-
-    if output.status.success() {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        for line in stdout.lines() {
-            if let Some(value) = line.strip_prefix("Package: ") {
-                package_info.name = value.trim().to_string();
-            } else if let Some(value) = line.strip_prefix("Version: ") {
-                package_info.version = value.trim().to_string();
-            } else if let Some(value) = line.strip_prefix("Architecture: ") {
-                package_info.arch = match value.trim() {
-                    "amd64" => "x86_64-linux".to_string(),
-                    "arm64" => "aarch64-linux".to_string(),
-                    arch => arch.to_string(),
-                };
-            } else if let Some(value) = line.strip_prefix("Description: ") {
-                package_info.description = value.trim().to_string();
+/// Debian's `Architecture:` value for data/script-only packages.
+pub const ARCH_INDEPENDENT: &str = "all";
+
+/// Maps a Debian `Architecture:` value to the matching Nix system string.
+pub fn debian_arch_to_system(arch: &str) -> String {
+    match arch {
+        "amd64" => "x86_64-linux".to_string(),
+        "arm64" => "aarch64-linux".to_string(),
+        "i386" => "i686-linux".to_string(),
+        "armhf" => "armv7l-linux".to_string(),
+        "armel" => "armv6l-linux".to_string(),
+        "riscv64" => "riscv64-linux".to_string(),
+        "ppc64el" => "powerpc64le-linux".to_string(),
+        arch => arch.to_string(),
+    }
+}
+
+/// Debian architecture of a Nix system, the reverse of
+/// `debian_arch_to_system`.
+pub fn system_to_debian_arch(system: &str) -> String {
+    match system {
+        "x86_64-linux" => "amd64".to_string(),
+        "aarch64-linux" => "arm64".to_string(),
+        "i686-linux" => "i386".to_string(),
+        "armv7l-linux" => "armhf".to_string(),
+        "armv6l-linux" => "armel".to_string(),
+        "riscv64-linux" => "riscv64".to_string(),
+        "powerpc64le-linux" => "ppc64el".to_string(),
+        system => system.to_string(),
+    }
+}
+
+/// Package format of `filename`, from its extension.
+pub fn detect_package_type(filename: &str) -> Option<PackageType> {
+    if Path::new(filename).is_dir() {
+        Some(PackageType::Directory)
+    } else if filename.ends_with(".deb") {
+        Some(PackageType::Deb)
+    } else if filename.ends_with(".pkg.tar.zst") || filename.ends_with(".pkg.tar.xz") {
+        Some(PackageType::Pacman)
+    } else if filename.ends_with(".apk") {
+        Some(PackageType::Apk)
+    } else if filename.ends_with(".snap") {
+        Some(PackageType::Snap)
+    } else {
+        None
+    }
+}
+
+/// The text of a control file's extended description from the lines after
+/// its summary: ` .` lines (`.` once unfolded) separate paragraphs.
+pub fn extended_description(body: &str) -> String {
+    let text: Vec<&str> = body.lines().map(str::trim_end).map(|l| if l == "." { "" } else { l }).collect();
+    text.join("\n").trim_matches('\n').to_string()
+}
+
+/// Reads the metadata of the deb `filename` from its `control` file.
+fn read_deb_control(content: &str, filename: &str) -> Result<PackageInfo, AppError> {
+    let paragraphs = deb822::parse(content)
+        .map_err(|e| AppError::Extract(format!("Malformed control file in {}: {}", filename, e)))?;
+    let control = paragraphs
+        .first()
+        .ok_or_else(|| AppError::Extract(format!("Empty control file in {}", filename)))?;
+    let field = |name: &str| control.get(name).map(str::to_string).filter(|v| !v.is_empty());
+    let required = |name: &str| {
+        field(name).ok_or_else(|| AppError::Extract(format!("No {} field in the control file of {}", name, filename)))
+    };
+
+    // The first line is the summary, the rest the extended description
+    let description = field("Description").unwrap_or_default();
+    let (summary, body) = description.split_once('\n').unwrap_or((&description, ""));
+    Ok(PackageInfo {
+        name: required("Package")?,
+        version: required("Version")?,
+        arch: field("Architecture").map(|a| debian_arch_to_system(&a)).unwrap_or_default(),
+        installed_size: field("Installed-Size").and_then(|v| v.parse::<u64>().ok()).map(|kib| kib * 1024),
+        description: summary.trim().to_string(),
+        long_description: extended_description(body),
+        homepage: field("Homepage"),
+        maintainer: field("Maintainer"),
+        section: field("Section"),
+        priority: field("Priority"),
+        control_depends: control.folded("Depends").map(|d| deb822::relationship_names(&d)).unwrap_or_default(),
+        ..PackageInfo::default()
+    })
+}
+
+/// Where relative symlink `link` (payload-relative) points, resolved against
+/// its directory without touching the filesystem; `None` once `target`
+/// climbs out of the payload.
+fn resolve_relative(link: &str, target: &Path) -> Option<String> {
+    let mut parts: Vec<&str> = link.split('/').collect();
+    parts.pop();
+    for component in target.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::ParentDir => {
+                parts.pop()?;
             }
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(_) => return None,
         }
     }
+    Some(parts.join("/"))
+}
+
+/// Links a chain may follow before it counts as a loop, as in the kernel.
+const MAX_LINK_HOPS: usize = 40;
 
+/// The payload-relative file `rel` leads to, following every symlink along
+/// the way inside the payload at `root`: absolute targets are taken as
+/// payload paths, never as the host's. `None` if the chain climbs out of
+/// the payload, loops or ends at nothing.
+fn payload_target(root: &Path, rel: &str) -> Option<String> {
+    let mut pending: Vec<String> = rel.rsplit('/').map(str::to_string).collect();
+    let mut resolved: Vec<String> = Vec::new();
+    let mut hops = 0;
+    while let Some(part) = pending.pop() {
+        match part.as_str() {
+            "" | "." => continue,
+            ".." => {
+                resolved.pop()?;
+                continue;
+            }
+            _ => resolved.push(part),
+        }
+        // Every component before this one is a real directory, so nothing
+        // outside the payload is looked at
+        let path = root.join(resolved.join("/"));
+        if !fs::symlink_metadata(&path).ok()?.file_type().is_symlink() {
+            continue;
+        }
+        hops += 1;
+        if hops > MAX_LINK_HOPS {
+            return None;
+        }
+        let target = fs::read_link(&path).ok()?;
+        resolved.pop();
+        if target.is_absolute() {
+            resolved.clear();
+        }
+        pending.extend(target.to_str()?.rsplit('/').map(str::to_string));
+    }
+    Some(resolved.join("/"))
+}
 
-    if !skip_deps {
-        match scan_binary_and_resolve(filename) {
-            Ok((deps, missing)) => {
+/// `update-alternatives --install <link> <name> <path> <priority>` calls
+/// (and their `--slave <link> <name> <path>` parts) in a maintainer script,
+/// as links from `<link>` to `<path>`. Calls built from shell variables are
+/// skipped.
+fn alternatives_links(script: &str) -> Vec<PayloadLink> {
+    let joined = script.replace("\\\n", " ");
+    let mut links = Vec::new();
+    for line in joined.lines().filter(|l| l.contains("update-alternatives")) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        for (i, word) in words.iter().enumerate() {
+            if *word != "--install" && *word != "--slave" {
+                continue;
+            }
+            let (Some(link), Some(target)) = (words.get(i + 1), words.get(i + 3)) else {
+                continue;
+            };
+            let unquote = |w: &str| w.trim_matches(['"', '\'']).to_string();
+            let (link, target) = (unquote(link), unquote(target));
+            if link.contains('$') || target.contains('$') || !link.starts_with('/') || !target.starts_with('/') {
+                continue;
+            }
+            links.push(PayloadLink {
+                link: link.trim_start_matches('/').to_string(),
+                target: target.trim_start_matches('/').to_string(),
+            });
+        }
+    }
+    links
+}
+
+/// `setcap <capabilities> <path>` calls in a maintainer script, as the
+/// files they grant capabilities to. Removals (`-r`) and calls built from
+/// shell variables are skipped.
+fn setcap_calls(script: &str) -> Vec<PrivilegedFile> {
+    let joined = script.replace("\\\n", " ");
+    let mut files = Vec::new();
+    for line in joined.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some(start) = words.iter().position(|w| *w == "setcap" || w.ends_with("/setcap")) else {
+            continue;
+        };
+        let mut args = Vec::new();
+        let mut rest = words[start + 1..].iter();
+        while let Some(word) = rest.next() {
+            match *word {
+                "-n" => {
+                    rest.next();
+                }
+                "-q" | "-v" => {}
+                "-r" => break,
+                w if w.starts_with(['|', '&', ';', '>']) || w.starts_with("2>") => break,
+                w => args.push(w.trim_matches(['"', '\'']).trim_end_matches(';')),
+            }
+        }
+        if let [capabilities, path] = args[..]
+            && path.starts_with('/')
+            && !capabilities.contains('$')
+            && !path.contains('$')
+        {
+            files.push(PrivilegedFile {
+                path: path.trim_start_matches('/').to_string(),
+                capabilities: Some(capabilities.to_string()),
+                ..PrivilegedFile::default()
+            });
+        }
+    }
+    files
+}
+
+/// The metadata of `filename` (control file, `.PKGINFO`, `snap.yaml`)
+/// without unpacking or scanning its payload.
+pub fn read_package_info(filename: &str) -> Result<PackageInfo, AppError> {
+    if filename.is_empty() {
+        return Err(AppError::Input("Filename cannot be empty".to_string()));
+    }
+
+    let pkg_type = detect_package_type(filename)
+        .ok_or_else(|| AppError::Input(format!("Unsupported package format: {}", filename)))?;
+    match pkg_type {
+        PackageType::Deb => {
+            let files = archive::read_control_files(Path::new(filename))?;
+            let mut package_info = read_deb_control(&files.control, filename)?;
+            package_info.conffiles = files
+                .conffiles
+                .unwrap_or_default()
+                .lines()
+                // `remove-on-upgrade` entries name files the package no longer ships
+                .filter(|l| l.starts_with('/'))
+                .map(|l| l.trim_end().to_string())
+                .collect();
+            if let Some(postinst) = &files.postinst {
+                package_info.links = alternatives_links(postinst);
+                package_info.privileged = setcap_calls(postinst);
+            }
+            package_info.zstd = archive::member_names(Path::new(filename))?
+                .iter()
+                .any(|m| m.starts_with("data.tar") && m.ends_with(".zst"));
+            Ok(package_info)
+        }
+        PackageType::Pacman | PackageType::Apk => pkginfo::read_pkginfo(Path::new(filename), pkg_type),
+        PackageType::Snap => snap::read_snap_yaml(Path::new(filename)),
+        PackageType::Directory => read_directory_info(Path::new(filename)),
+    }
+}
+
+/// The Nix system `filename` is built for, `all` if it is
+/// architecture-independent.
+pub fn read_arch(filename: &str) -> Result<String, AppError> {
+    Ok(read_package_info(filename)?.arch)
+}
+
+/// Folds the metadata of an extra package (e.g. the `-data` or `-libs`
+/// deb of a split app) into the primary one: name, version and description
+/// stay the primary's, dependencies and sizes add up.
+fn merge_package_info(primary: &mut PackageInfo, extra: PackageInfo, filename: &str) -> Result<(), AppError> {
+    if extra.package_type != primary.package_type {
+        return Err(AppError::Input(format!(
+            "{} is not the same package format as the first input",
+            filename
+        )));
+    }
+    match (primary.arch.as_str(), extra.arch.as_str()) {
+        (a, b) if a == b || b == ARCH_INDEPENDENT => {}
+        (ARCH_INDEPENDENT, _) => primary.arch = extra.arch,
+        (a, b) => {
+            return Err(AppError::Input(format!(
+                "{} is built for {}, the first input for {}",
+                filename, b, a
+            )));
+        }
+    }
+    for dep in extra.control_depends {
+        if !primary.control_depends.contains(&dep) {
+            primary.control_depends.push(dep);
+        }
+    }
+    primary.installed_size = match (primary.installed_size, extra.installed_size) {
+        (Some(a), Some(b)) => Some(a + b),
+        _ => None,
+    };
+    primary.zstd |= extra.zstd;
+    primary.conffiles.extend(extra.conffiles);
+    primary.links.extend(extra.links);
+    primary.privileged.extend(extra.privileged);
+    primary.entry_points.extend(extra.entry_points);
+    Ok(())
+}
+
+/// Reads and scans `filenames` as one package; the first is the primary
+/// whose name and version are used, the rest are unpacked into the same
+/// tree.
+pub fn get_nix_shell(filenames: &[String], target_system: &str, options: &ScanOptions) -> Result<PackageInfo, AppError> {
+    let (first, rest) = filenames
+        .split_first()
+        .ok_or_else(|| AppError::Input("Filename cannot be empty".to_string()))?;
+    let mut package_info = read_package_info(first)?;
+    for filename in rest {
+        let extra = read_package_info(filename)?;
+        merge_package_info(&mut package_info, extra, filename)?;
+    }
+    let pkg_type = package_info.package_type;
+
+    if package_info.arch == ARCH_INDEPENDENT {
+        let mut files = Vec::new();
+        for filename in filenames {
+            files.extend(list_payload(filename, pkg_type)?);
+        }
+        package_info.integration = detect_integration(&files);
+        package_info.extra_files = detect_extra_files(&files);
+        package_info.kind = match payload_kind(&files)? {
+            Some(kind) => kind,
+            None => {
+                info!("Architecture-independent package, skipping binary scan.");
+                AppKind::ArchIndependent
+            }
+        };
+    } else if !options.skip_deps {
+        ensure_extraction_space(filenames, package_info.installed_size, options)?;
+        match scan_binary_and_resolve(filenames, pkg_type, &package_info.control_depends, target_system, options) {
+            Ok(ScanResult {
+                deps,
+                missing,
+                kind,
+                script_interpreters,
+                multilib,
+                musl,
+                needed_libs,
+                binaries,
+                bundled_libs,
+                referenced_libs,
+                links,
+                dangling_links,
+                privileged,
+                non_executable,
+                electron,
+                desktop_entries,
+                autostart,
+                integration,
+                extra_files,
+                lib_conflicts,
+                licenses,
+                changelog,
+            }) => {
+                package_info.lib_conflicts = lib_conflicts;
+                if !licenses.is_empty() {
+                    package_info.licenses = licenses;
+                }
+                package_info.changelog = changelog;
+                package_info.integration = integration;
+                package_info.extra_files = extra_files;
+                package_info.links.extend(links);
+                package_info.dangling_links = dangling_links;
+                package_info.non_executable = non_executable;
+                for file in privileged {
+                    match package_info.privileged.iter_mut().find(|p| p.path == file.path) {
+                        Some(known) => {
+                            known.setuid = file.setuid;
+                            known.setgid = file.setgid;
+                        }
+                        None => package_info.privileged.push(file),
+                    }
+                }
+                package_info.electron = electron;
+                package_info.desktop_entries = desktop_entries;
+                package_info.autostart = autostart;
                 package_info.deps = deps;
+                package_info.multilib = multilib;
+                package_info.musl = musl;
+                package_info.needed_libs = needed_libs;
+                package_info.binaries = binaries;
+                package_info.bundled_libs = bundled_libs;
+                package_info.referenced_libs = referenced_libs;
+                package_info.kind = kind;
+                package_info.script_interpreters = script_interpreters;
 
                 if !missing.is_empty() {
-                    println!("\n========================================================");
-                    println!(" WARNING: MISSING DEPENDENCIES DETECTED");
-                    println!("========================================================");
-                    for lib in &missing {
-                        println!(" - {}", lib);
-                    }
-                    println!("========================================================\n");
+                    warn!("Missing dependencies detected: {}", missing.join(", "));
                 }
+                package_info.missing_libs = missing;
             }
+            // A bad --keep-workdir is the user's to fix, not a reason to degrade
+            Err(e @ AppError::Input(_)) => return Err(e),
             Err(e) => {
-                eprintln!("Error during binary scan: {}. Generating minimal config.", e);
+                error!("Binary scan failed: {}. Generating minimal config.", e);
+                package_info.scan_error = Some(e.to_string());
             }
         }
     }
 
+    // Without a scan the copyright file and changelog are read from the deb itself
+    if package_info.licenses.is_empty() && pkg_type == PackageType::Deb {
+        let copyright = format!("usr/share/doc/{}/copyright", package_info.name);
+        if let Ok(Some(content)) = archive::read_member_file(Path::new(first), "data", &copyright) {
+            package_info.licenses = license::from_copyright(&content);
+        }
+    }
+    if package_info.changelog.is_none()
+        && pkg_type == PackageType::Deb
+        && let Ok(Some(bytes)) = archive::read_member_bytes(Path::new(first), "data", &changelog::path(&package_info.name))
+    {
+        package_info.changelog = changelog::latest_entry_gz(&bytes);
+    }
+
     Ok(package_info)
 }
@@ -0,0 +1,230 @@
+use std::process::Command;
+
+use log::warn;
+
+use crate::configuration::{get_pkg_for_lib, get_ranking};
+use crate::structs::ResolveCandidate;
+
+const SCORE_CONFIG: u32 = 100;
+const SCORE_LOCATE_ROOT: u32 = 80;
+const SCORE_LOCATE_ANYWHERE: u32 = 50;
+const SCORE_LOCATE_VERSIONED: u32 = 40;
+const SCORE_OTHER_VERSION: u32 = 10;
+/// Added to attrs the config's `ranking.prefer` lists, taken from those in
+/// `ranking.avoid`.
+const SCORE_PREFERRED: u32 = 15;
+const SCORE_AVOIDED: u32 = 30;
+
+/// Multiple outputs of the same package are one candidate.
+const NIX_OUTPUTS: [&str; 6] = ["out", "lib", "dev", "bin", "man", "doc"];
+
+fn has_nix_locate() -> bool {
+    Command::new("which")
+        .arg("nix-locate")
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(false)
+}
+
+/// Turns a `nix-locate --minimal` line (`xorg.libX11.out`) into an attr path.
+fn locate_attr(line: &str) -> Option<String> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+    let attr = match trimmed.rsplit_once('.') {
+        Some((attr, output)) if NIX_OUTPUTS.contains(&output) => attr,
+        _ => trimmed,
+    };
+    Some(attr.to_string())
+}
+
+fn nix_locate(args: &[&str]) -> Vec<String> {
+    let Ok(output) = Command::new("nix-locate").args(args).output() else {
+        return Vec::new();
+    };
+    if !output.status.success() {
+        return Vec::new();
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(locate_attr)
+        .collect()
+}
+
+/// Splits a soname into its name and ABI version: `libssl.so.1.1` into
+/// `libssl` and `1.1`, `libwebkit2gtk-4.0.so.37` into `libwebkit2gtk-4.0`
+/// and `37`.
+fn soname_version(lib_name: &str) -> Option<(&str, &str)> {
+    let (name, version) = lib_name.split_once(".so.")?;
+    (!name.is_empty() && !version.is_empty()).then_some((name, version))
+}
+
+/// Every versioned `<name>.so.*` under a `lib` directory nix-locate knows,
+/// as (attr, file name) pairs.
+fn locate_versions(name: &str) -> Vec<(String, String)> {
+    let pattern = format!("/lib/{}\\.so\\.[0-9][0-9.]*$", regex::escape(name));
+    let Ok(output) = Command::new("nix-locate").args(["--top-level", "--regex", &pattern]).output() else {
+        return Vec::new();
+    };
+    if !output.status.success() {
+        return Vec::new();
+    }
+    // `<attr>.<output>  <size> <type> <store path>`
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let attr = locate_attr(columns.next()?)?;
+            let file = columns.next_back()?.rsplit('/').next()?.to_string();
+            Some((attr, file))
+        })
+        .collect()
+}
+
+fn push_ranked(candidates: &mut Vec<ResolveCandidate>, attrs: Vec<String>, base: u32, source: &'static str) {
+    for (i, attr) in attrs.into_iter().enumerate() {
+        if candidates.iter().any(|c| c.attr == attr) {
+            continue;
+        }
+        // Earlier nix-locate hits are ranked higher, within the tier's range
+        let score = base.saturating_sub(i as u32).max(base - 20);
+        candidates.push(ResolveCandidate {
+            attr,
+            score,
+            source,
+            other_version: None,
+        });
+    }
+}
+
+/// Applies the config's ranking preferences and sorts best first.
+fn ranked(mut candidates: Vec<ResolveCandidate>) -> Vec<ResolveCandidate> {
+    let ranking = get_ranking();
+    for candidate in candidates.iter_mut().filter(|c| c.source != "config") {
+        if ranking.prefer.contains(&candidate.attr) {
+            candidate.score += SCORE_PREFERRED;
+        } else if ranking.avoid.contains(&candidate.attr) {
+            candidate.score = candidate.score.saturating_sub(SCORE_AVOIDED);
+        }
+    }
+    candidates.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.attr.cmp(&b.attr)));
+    candidates
+}
+
+/// Ranks nixpkgs attrs providing `lib_name`: the libraries config map first,
+/// then files at `/lib/<name>` of a store path, then the name anywhere,
+/// with the config's preferred attrs raised and avoided ones lowered in
+/// each. Unless `all_tiers` is set, lookup stops at the first tier with a
+/// result.
+pub fn resolve_candidates(lib_name: &str, all_tiers: bool) -> Vec<ResolveCandidate> {
+    let mut candidates = Vec::new();
+
+    if let Some(pkg) = get_pkg_for_lib(lib_name) {
+        candidates.push(ResolveCandidate {
+            attr: pkg.clone(),
+            score: SCORE_CONFIG,
+            source: "config",
+            other_version: None,
+        });
+        if !all_tiers {
+            return candidates;
+        }
+    }
+
+    if !has_nix_locate() {
+        return candidates;
+    }
+
+    let search_path = format!("/lib/{}", lib_name);
+    let at_root = nix_locate(&["--top-level", "--minimal", "--at-root", "--whole-name", &search_path]);
+    push_ranked(&mut candidates, at_root, SCORE_LOCATE_ROOT, "nix-locate");
+    if !all_tiers && !candidates.is_empty() {
+        return ranked(candidates);
+    }
+
+    let anywhere = nix_locate(&["--top-level", "--minimal", "--whole-name", lib_name]);
+    push_ranked(&mut candidates, anywhere, SCORE_LOCATE_ANYWHERE, "nix-locate (loose)");
+
+    // Only the file name's version can tell libssl.so.3 from libssl.so.1.1:
+    // a longer version of the same ABI (libfoo.so.1.2.3 for libfoo.so.1)
+    // is a match, any other is reported but never used
+    if let Some((name, version)) = soname_version(lib_name)
+        && (all_tiers || candidates.is_empty())
+    {
+        let mut matching = Vec::new();
+        let mut other: Vec<(String, String)> = Vec::new();
+        for (attr, file) in locate_versions(name) {
+            let found = file.strip_prefix(name).and_then(|f| f.strip_prefix(".so.")).unwrap_or_default();
+            if found == version || found.starts_with(&format!("{}.", version)) {
+                matching.push(attr);
+            } else if !other.iter().any(|(a, _)| *a == attr) {
+                other.push((attr, file));
+            }
+        }
+        push_ranked(&mut candidates, matching, SCORE_LOCATE_VERSIONED, "nix-locate (versioned file)");
+        for (attr, file) in other {
+            if !candidates.iter().any(|c| c.attr == attr) {
+                candidates.push(ResolveCandidate {
+                    attr,
+                    score: SCORE_OTHER_VERSION,
+                    source: "nix-locate (other version)",
+                    other_version: Some(file),
+                });
+            }
+        }
+    }
+
+    ranked(candidates)
+}
+
+/// The best attr providing `lib_name`; `None`, with a warning, if nixpkgs
+/// only has other versions of it.
+pub fn resolve_lib_via_locate(lib_name: &str) -> Option<String> {
+    let candidates = resolve_candidates(lib_name, false);
+    if let Some(candidate) = candidates.iter().find(|c| c.other_version.is_none()) {
+        return Some(candidate.attr.clone());
+    }
+    if !candidates.is_empty() {
+        let others: Vec<String> = candidates
+            .iter()
+            .map(|c| format!("pkgs.{} ({})", c.attr, c.other_version.as_deref().unwrap_or_default()))
+            .collect();
+        warn!(
+            "{}: nixpkgs only has other versions, with a different ABI: {}",
+            lib_name,
+            others.join(", ")
+        );
+    }
+    None
+}
+
+/// `app2nix resolve <soname>...`: prints every candidate with its score and
+/// returns the libraries without any.
+pub fn run_resolve_command(libs: &[String]) -> Vec<String> {
+    if !has_nix_locate() {
+        warn!("nix-locate not found, only the libraries config is consulted.");
+    }
+
+    let mut unresolved = Vec::new();
+    for lib in libs {
+        println!("{}", lib);
+        let candidates = resolve_candidates(lib, true);
+        if candidates.is_empty() {
+            println!("    (no candidates)");
+        }
+        if candidates.iter().all(|c| c.other_version.is_some()) {
+            unresolved.push(lib.clone());
+        }
+        for candidate in candidates {
+            match &candidate.other_version {
+                Some(file) => println!(
+                    "    {:>3}  {:<28} pkgs.{} (provides {})",
+                    candidate.score, candidate.source, candidate.attr, file
+                ),
+                None => println!("    {:>3}  {:<28} pkgs.{}", candidate.score, candidate.source, candidate.attr),
+            }
+        }
+    }
+    unresolved
+}
@@ -0,0 +1,283 @@
+use std::io;
+
+use ratatui::DefaultTerminal;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::Frame;
+
+use crate::errors::AppError;
+use crate::generation_nix;
+use crate::naming;
+use crate::structs::{AppKind, PackageInfo, PackageType};
+
+#[derive(PartialEq, Clone, Copy)]
+enum Focus {
+    Deps,
+    Missing,
+}
+
+/// What the text typed at the prompt is for.
+enum EditTarget {
+    /// Replace the dependency at this index.
+    Remap(usize),
+    /// Resolve the missing library at this index to a package.
+    Resolve(usize),
+    Add,
+}
+
+struct Edit {
+    target: EditTarget,
+    text: String,
+}
+
+struct Review<'a> {
+    info: &'a mut PackageInfo,
+    focus: Focus,
+    deps: ListState,
+    missing: ListState,
+    edit: Option<Edit>,
+    status: String,
+}
+
+/// Shows the scan result before anything is written: metadata, resolved
+/// dependencies (remap, remove, add), missing libraries (resolve by hand)
+/// and the template. Edits are applied to `info`; returns whether the user
+/// chose to write.
+pub fn run(info: &mut PackageInfo) -> Result<bool, AppError> {
+    let mut terminal = ratatui::init();
+    let mut review = Review {
+        info,
+        focus: Focus::Deps,
+        deps: ListState::default().with_selected(Some(0)),
+        missing: ListState::default().with_selected(Some(0)),
+        edit: None,
+        status: String::new(),
+    };
+    let result = review.event_loop(&mut terminal);
+    ratatui::restore();
+    result.map_err(|e| AppError::Generate(format!("Review screen failed: {}", e)))
+}
+
+fn kind_label(kind: &AppKind) -> &'static str {
+    match kind {
+        AppKind::Native => "native",
+        AppKind::DotNet { .. } => ".NET",
+        AppKind::Mono { .. } => "Mono",
+        AppKind::Python { .. } => "Python",
+        AppKind::Static { .. } => "static",
+        AppKind::ArchIndependent => "architecture-independent",
+        AppKind::Dkms { .. } => "DKMS module",
+        AppKind::Data { .. } => "data",
+    }
+}
+
+fn type_label(package_type: PackageType) -> &'static str {
+    match package_type {
+        PackageType::Deb => "deb",
+        PackageType::Pacman => "pacman",
+        PackageType::Apk => "apk",
+        PackageType::Snap => "snap",
+        PackageType::Directory => "directory",
+    }
+}
+
+/// A bordered list, highlighted when it has the focus.
+fn list(title: String, items: &[String], focused: bool) -> List<'_> {
+    let border = if focused { Style::new().cyan() } else { Style::new() };
+    List::new(items.iter().map(|i| ListItem::new(i.as_str())))
+        .block(Block::bordered().title(title).border_style(border))
+        .highlight_style(Style::new().reversed())
+        .highlight_symbol("> ")
+}
+
+impl Review<'_> {
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> io::Result<bool> {
+        loop {
+            terminal.draw(|frame| self.render(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if self.edit.is_some() {
+                self.edit_key(key.code);
+                continue;
+            }
+            self.status.clear();
+            match key.code {
+                KeyCode::Char('w') => return Ok(true),
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+                KeyCode::Tab | KeyCode::Left | KeyCode::Right => {
+                    self.focus = if self.focus == Focus::Deps { Focus::Missing } else { Focus::Deps };
+                }
+                KeyCode::Up | KeyCode::Char('k') => self.list_state().select_previous(),
+                KeyCode::Down | KeyCode::Char('j') => self.list_state().select_next(),
+                KeyCode::Char('a') => self.start_edit(EditTarget::Add, String::new()),
+                KeyCode::Char('d') | KeyCode::Delete if self.focus == Focus::Deps => {
+                    if let Some(i) = self.selected(Focus::Deps) {
+                        let removed = self.info.deps.remove(i);
+                        self.status = format!("Removed {}", removed);
+                    }
+                }
+                KeyCode::Char('e') | KeyCode::Enter => match self.focus {
+                    Focus::Deps => {
+                        if let Some(i) = self.selected(Focus::Deps) {
+                            self.start_edit(EditTarget::Remap(i), self.info.deps[i].clone());
+                        }
+                    }
+                    Focus::Missing => {
+                        if let Some(i) = self.selected(Focus::Missing) {
+                            self.start_edit(EditTarget::Resolve(i), String::new());
+                        }
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+
+    fn list_state(&mut self) -> &mut ListState {
+        match self.focus {
+            Focus::Deps => &mut self.deps,
+            Focus::Missing => &mut self.missing,
+        }
+    }
+
+    /// The selected index of `focus`'s list, clamped to its current length.
+    fn selected(&self, focus: Focus) -> Option<usize> {
+        let (state, len) = match focus {
+            Focus::Deps => (&self.deps, self.info.deps.len()),
+            Focus::Missing => (&self.missing, self.info.missing_libs.len()),
+        };
+        state.selected().filter(|_| len > 0).map(|i| i.min(len - 1))
+    }
+
+    fn start_edit(&mut self, target: EditTarget, text: String) {
+        self.edit = Some(Edit { target, text });
+    }
+
+    fn edit_key(&mut self, code: KeyCode) {
+        let Some(edit) = &mut self.edit else {
+            return;
+        };
+        match code {
+            KeyCode::Esc => self.edit = None,
+            KeyCode::Backspace => {
+                edit.text.pop();
+            }
+            KeyCode::Char(c) => edit.text.push(c),
+            KeyCode::Enter => {
+                if let Some(edit) = self.edit.take() {
+                    self.apply(edit);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn apply(&mut self, edit: Edit) {
+        let attr = edit.text.trim().trim_start_matches("pkgs.").to_string();
+        if !naming::is_attr_path(&attr) {
+            self.status = format!("Not a nixpkgs attribute: {:?}", edit.text);
+            return;
+        }
+        let duplicate = self.info.deps.contains(&attr);
+        match edit.target {
+            // Remapping onto another listed package just drops this one
+            EditTarget::Remap(i) if duplicate && self.info.deps[i] != attr => {
+                let old = self.info.deps.remove(i);
+                self.status = format!("{} -> {}", old, attr);
+            }
+            EditTarget::Remap(i) => {
+                let old = std::mem::replace(&mut self.info.deps[i], attr.clone());
+                self.status = format!("{} -> {}", old, attr);
+            }
+            EditTarget::Resolve(i) => {
+                let lib = self.info.missing_libs.remove(i);
+                if !duplicate {
+                    self.info.deps.push(attr.clone());
+                }
+                self.status = format!("{} -> {}", lib, attr);
+            }
+            EditTarget::Add if duplicate => self.status = format!("{} is already a dependency", attr),
+            EditTarget::Add => {
+                self.info.deps.push(attr.clone());
+                self.status = format!("Added {}", attr);
+            }
+        }
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        // An empty list loses its selection; pick it up again once it fills
+        for (state, len) in [(&mut self.deps, self.info.deps.len()), (&mut self.missing, self.info.missing_libs.len())] {
+            if state.selected().is_none() && len > 0 {
+                state.select(Some(0));
+            }
+        }
+        let [meta_area, lists_area, prompt_area, help_area] = Layout::vertical([
+            Constraint::Length(8),
+            Constraint::Min(5),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [deps_area, missing_area] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(lists_area);
+
+        let info = &self.info;
+        let (template, _) = generation_nix::template(&info.package_type, &info.kind);
+        let meta = vec![
+            Line::from(vec!["Name:        ".bold(), info.name.clone().into()]),
+            Line::from(vec!["Version:     ".bold(), info.version.clone().into()]),
+            Line::from(vec![
+                "Package:     ".bold(),
+                format!("{} for {}, {}", type_label(info.package_type), info.arch, kind_label(&info.kind)).into(),
+            ]),
+            Line::from(vec!["Template:    ".bold(), template.into()]),
+            Line::from(vec!["Description: ".bold(), info.description.clone().into()]),
+        ];
+        frame.render_widget(
+            Paragraph::new(meta)
+                .wrap(Wrap { trim: true })
+                .block(Block::bordered().title(" Review before writing default.nix ")),
+            meta_area,
+        );
+
+        let deps = list(
+            format!(" Dependencies ({}) ", info.deps.len()),
+            &info.deps,
+            self.focus == Focus::Deps,
+        );
+        let missing = list(
+            format!(" Missing libraries ({}) ", info.missing_libs.len()),
+            &info.missing_libs,
+            self.focus == Focus::Missing,
+        );
+        frame.render_stateful_widget(deps, deps_area, &mut self.deps);
+        frame.render_stateful_widget(missing, missing_area, &mut self.missing);
+
+        let prompt = match &self.edit {
+            Some(edit) => {
+                let label = match edit.target {
+                    EditTarget::Remap(_) => "Remap to: ",
+                    EditTarget::Resolve(_) => "Provided by: ",
+                    EditTarget::Add => "Add package: ",
+                };
+                Line::from(vec![label.bold(), edit.text.clone().into(), "_".slow_blink()])
+            }
+            None => Line::from(self.status.clone().yellow()),
+        };
+        frame.render_widget(Paragraph::new(prompt), prompt_area);
+
+        let help = match (&self.edit, self.focus) {
+            (Some(_), _) => "Enter confirm · Esc cancel",
+            (None, Focus::Deps) => "Tab switch list · ↑↓ select · e remap · d remove · a add · w write · q cancel",
+            (None, Focus::Missing) => "Tab switch list · ↑↓ select · Enter resolve · a add · w write · q cancel",
+        };
+        frame.render_widget(Paragraph::new(help.dim()), help_area);
+    }
+}
@@ -0,0 +1,236 @@
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::cli::TryOptions;
+use crate::errors::AppError;
+use crate::verify;
+
+/// How long stderr is still read after the program is gone.
+const STDERR_GRACE: Duration = Duration::from_secs(2);
+
+/// Failure signatures in a program's stderr and what to do about them.
+/// `$1` is replaced with the first capture group.
+static SIGNATURES: Lazy<Vec<(Regex, &'static str)>> = Lazy::new(|| {
+    [
+        (
+            r"error while loading shared libraries: (\S+?):",
+            "$1 is missing: find its package with `app2nix resolve $1` and add it with --extra-build-input <attr>",
+        ),
+        (
+            r"version `(GLIBC_[0-9.]+)' not found",
+            "The binary needs $1, newer than nixpkgs' glibc: use a newer nixpkgs or an older build of the app",
+        ),
+        (
+            r"No GSettings schemas are installed|Settings schema '[^']+' is not installed",
+            "GSettings schemas are missing: add --preset gtk",
+        ),
+        (
+            r"GLib-GIO-(?:ERROR|WARNING).*(?:TLS support is not available|GIO_EXTRA_MODULES)",
+            "GIO modules (e.g. TLS) are missing: add --preset gtk",
+        ),
+        (
+            r"SUID sandbox helper binary was found, but is not configured correctly|No usable sandbox!",
+            "Chromium's sandbox cannot start: wrap the program with --add-flags --no-sandbox, or install chrome-sandbox setuid via a NixOS module",
+        ),
+        (
+            r#"Could not load the Qt platform plugin "(\w+)""#,
+            "Qt cannot find its $1 plugin: add --preset qt6 (or qt5 for a Qt 5 app)",
+        ),
+        (
+            r"libGL error|MESA-LOADER: failed to open|failed to load driver",
+            "No OpenGL driver was found: on NixOS enable hardware.graphics; elsewhere run the app through nixGL",
+        ),
+        (
+            r"Fontconfig error: Cannot load default config file",
+            "Fontconfig has no configuration: add --extra-build-input fontconfig and set FONTCONFIG_FILE in the wrapper",
+        ),
+        (
+            r"Gtk-WARNING.*cannot open display|cannot open display|Failed to connect to Wayland display",
+            "The sandbox has no display: run `app2nix try` from a graphical session",
+        ),
+        (
+            r"dlopen\(?[^:]*\)?: ?(\S+\.so[.0-9]*)",
+            "$1 is loaded at runtime: add its package with --extra-build-input so it is on the wrapper's library path",
+        ),
+    ]
+    .into_iter()
+    .map(|(pattern, hint)| (Regex::new(pattern).unwrap(), hint))
+    .collect()
+});
+
+/// The program to start: `--bin`, the executable named like the package,
+/// or the only one in `bin/`.
+fn program(root: &Path, bin: Option<&str>) -> Result<PathBuf, AppError> {
+    let bin_dir = root.join("bin");
+    if let Some(bin) = bin {
+        let path = bin_dir.join(bin);
+        return path
+            .exists()
+            .then_some(path)
+            .ok_or_else(|| AppError::Input(format!("{} has no bin/{}", root.display(), bin)));
+    }
+    let mut programs: Vec<PathBuf> = fs::read_dir(&bin_dir)
+        .map_err(|e| AppError::Input(format!("{}: {}", bin_dir.display(), e)))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| !p.file_name().is_some_and(|n| n.to_string_lossy().starts_with('.')))
+        .collect();
+    programs.sort();
+    // Store paths are named <hash>-<pname>-<version>
+    let store_name = root.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let named = programs.iter().find(|p| {
+        p.file_name()
+            .is_some_and(|n| store_name.contains(&format!("-{}-", n.to_string_lossy())))
+    });
+    match (named, programs.as_slice()) {
+        (Some(path), _) => Ok(path.clone()),
+        (None, [only]) => Ok(only.clone()),
+        (None, []) => Err(AppError::Input(format!("{} has nothing in bin/", root.display()))),
+        (None, _) => Err(AppError::Usage(format!(
+            "{} has several programs; pick one with --bin",
+            root.display()
+        ))),
+    }
+}
+
+/// `bwrap` arguments: the store, system configuration and devices read
+/// only or as they are, a private /tmp and `home` as the only writable
+/// directory. The display sockets are passed through.
+fn bwrap_args(home: &Path) -> Vec<String> {
+    let mut args: Vec<String> = [
+        "--die-with-parent",
+        "--unshare-pid",
+        "--ro-bind",
+        "/nix",
+        "/nix",
+        "--ro-bind-try",
+        "/etc",
+        "/etc",
+        "--ro-bind-try",
+        "/run/opengl-driver",
+        "/run/opengl-driver",
+        "--ro-bind-try",
+        "/run/current-system",
+        "/run/current-system",
+        "--ro-bind-try",
+        "/usr/share/fonts",
+        "/usr/share/fonts",
+        "--ro-bind",
+        "/sys",
+        "/sys",
+        "--dev-bind",
+        "/dev",
+        "/dev",
+        "--proc",
+        "/proc",
+        "--tmpfs",
+        "/tmp",
+        "--bind-try",
+        "/tmp/.X11-unix",
+        "/tmp/.X11-unix",
+    ]
+    .iter()
+    .map(|a| a.to_string())
+    .collect();
+    if let Ok(runtime_dir) = env::var("XDG_RUNTIME_DIR") {
+        args.extend(["--bind-try".to_string(), runtime_dir.clone(), runtime_dir]);
+    }
+    let home = home.display().to_string();
+    args.extend(["--bind".to_string(), home.clone(), home.clone()]);
+    args.extend(["--setenv".to_string(), "HOME".to_string(), home.clone()]);
+    args.extend(["--chdir".to_string(), home]);
+    args
+}
+
+/// `app2nix try <store-path|default.nix>`: builds the expression if
+/// needed, starts its program in a bubblewrap sandbox with an empty HOME,
+/// echoes and keeps its stderr, and turns known failure messages into
+/// suggestions once it exits (or after `--timeout`).
+pub fn run_try_command(options: &TryOptions) -> Result<(), AppError> {
+    if !Command::new("bwrap").arg("--version").output().is_ok_and(|o| o.status.success()) {
+        return Err(AppError::Usage(
+            "try needs bubblewrap (bwrap); install pkgs.bubblewrap or run it in nix-shell -p bubblewrap".to_string(),
+        ));
+    }
+    let roots = verify::outputs(&options.target)?;
+    let root = roots
+        .first()
+        .ok_or_else(|| AppError::Generate(format!("{} built nothing", options.target)))?;
+    let program = program(root, options.bin.as_deref())?;
+    let home = tempfile::tempdir().map_err(|e| AppError::Generate(format!("Failed to create a scratch HOME: {}", e)))?;
+
+    info!("Starting {} in a sandbox (HOME={})...", program.display(), home.path().display());
+    let mut child = Command::new("bwrap")
+        .args(bwrap_args(home.path()))
+        .arg(&program)
+        .args(&options.args)
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| AppError::Generate(format!("Failed to run bwrap: {}", e)))?;
+
+    let stderr = child.stderr.take();
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let reader = {
+        let lines = Arc::clone(&lines);
+        thread::spawn(move || {
+            for line in stderr.into_iter().flat_map(|s| BufReader::new(s).lines()).map_while(Result::ok) {
+                eprintln!("{}", line);
+                lines.lock().unwrap_or_else(|e| e.into_inner()).push(line);
+            }
+        })
+    };
+
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Some(status),
+            Ok(None) if options.timeout.is_some_and(|t| started.elapsed() >= t) => {
+                info!("Still running after {}s; stopping it.", started.elapsed().as_secs());
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(100)),
+            Err(e) => return Err(AppError::Generate(format!("Failed to wait for the program: {}", e))),
+        }
+    };
+    // Something the program forked may still hold stderr open
+    let drained = Instant::now();
+    while !reader.is_finished() && drained.elapsed() < STDERR_GRACE {
+        thread::sleep(Duration::from_millis(50));
+    }
+    let lines = lines.lock().unwrap_or_else(|e| e.into_inner()).clone();
+
+    let mut hints: Vec<String> = Vec::new();
+    for line in &lines {
+        for (regex, hint) in SIGNATURES.iter() {
+            if let Some(captures) = regex.captures(line) {
+                let hint = hint.replace("$1", captures.get(1).map_or("", |m| m.as_str()));
+                if !hints.contains(&hint) {
+                    hints.push(hint);
+                }
+            }
+        }
+    }
+    for hint in &hints {
+        warn!("{}", hint);
+    }
+
+    if let Some(status) = status.filter(|s| !s.success()) {
+        warn!("{} exited with {}.", program.display(), status);
+    }
+    if hints.is_empty() {
+        info!("No known failure signatures in the program's output.");
+    }
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::structs::{PackageInfo, PackageType, SbomFormat};
+
+const TOOL: &str = concat!("app2nix-", env!("CARGO_PKG_VERSION"));
+
+/// Current UTC time as RFC 3339 (`2024-05-01T12:00:00Z`), or the time in
+/// `SOURCE_DATE_EPOCH` so reproducible builds get the same document.
+pub fn utc_timestamp() -> String {
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+/// The purl of the package as its distribution names it, before the name
+/// and version were normalized for Nix.
+fn package_purl(pkg_info: &PackageInfo) -> String {
+    let name = pkg_info.original_name.as_deref().unwrap_or(&pkg_info.name);
+    let version = pkg_info.original_version.as_deref().unwrap_or(&pkg_info.version);
+    match pkg_info.package_type {
+        PackageType::Deb => format!("pkg:deb/{}@{}?arch={}", name, version, pkg_info.arch),
+        PackageType::Pacman => format!("pkg:alpm/{}@{}?arch={}", name, version, pkg_info.arch),
+        PackageType::Apk => format!("pkg:apk/alpine/{}@{}?arch={}", name, version, pkg_info.arch),
+        // purl has no snap type, nor one for a bare tree
+        PackageType::Snap | PackageType::Directory => format!("pkg:generic/{}@{}?arch={}", name, version, pkg_info.arch),
+    }
+}
+
+fn spdx_id(kind: &str, name: &str) -> String {
+    let clean: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '-' })
+        .collect();
+    format!("SPDXRef-{}-{}", kind, clean)
+}
+
+fn spdx_document(pkg_info: &PackageInfo, sha256: &str) -> Value {
+    let app_id = spdx_id("App", &pkg_info.name);
+    let mut packages = vec![json!({
+        "SPDXID": app_id,
+        "name": pkg_info.name,
+        "versionInfo": pkg_info.version,
+        "description": pkg_info.description,
+        "downloadLocation": "NOASSERTION",
+        "checksums": [{ "algorithm": "SHA256", "checksumValue": sha256 }],
+        "externalRefs": [{
+            "referenceCategory": "PACKAGE-MANAGER",
+            "referenceType": "purl",
+            "referenceLocator": package_purl(pkg_info),
+        }],
+    })];
+    let mut relationships = vec![json!({
+        "spdxElementId": "SPDXRef-DOCUMENT",
+        "relationshipType": "DESCRIBES",
+        "relatedSpdxElement": app_id,
+    })];
+
+    for lib in &pkg_info.bundled_libs {
+        let id = spdx_id("Bundled", &lib.path);
+        packages.push(json!({
+            "SPDXID": id,
+            "name": lib.soname.as_deref().unwrap_or(&lib.path),
+            "versionInfo": lib.version.as_deref().unwrap_or("NOASSERTION"),
+            "downloadLocation": "NOASSERTION",
+            "comment": format!("Bundled at {}", lib.path),
+        }));
+        relationships.push(json!({
+            "spdxElementId": app_id,
+            "relationshipType": "CONTAINS",
+            "relatedSpdxElement": id,
+        }));
+    }
+
+    for dep in &pkg_info.deps {
+        let id = spdx_id("Nixpkgs", dep);
+        packages.push(json!({
+            "SPDXID": id,
+            "name": format!("nixpkgs.{}", dep),
+            "downloadLocation": "NOASSERTION",
+        }));
+        relationships.push(json!({
+            "spdxElementId": app_id,
+            "relationshipType": "DEPENDS_ON",
+            "relatedSpdxElement": id,
+        }));
+    }
+
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": format!("{}-{}", pkg_info.name, pkg_info.version),
+        "documentNamespace": format!("https://spdx.org/spdxdocs/{}-{}-{}", pkg_info.name, pkg_info.version, sha256),
+        "creationInfo": {
+            "created": utc_timestamp(),
+            "creators": [format!("Tool: {}", TOOL)],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+fn cyclonedx_document(pkg_info: &PackageInfo, sha256: &str) -> Value {
+    let app_ref = package_purl(pkg_info);
+    let mut components = Vec::new();
+    let mut depends_on = Vec::new();
+
+    for lib in &pkg_info.bundled_libs {
+        let bom_ref = format!("bundled:{}", lib.path);
+        components.push(json!({
+            "type": "library",
+            "bom-ref": bom_ref,
+            "name": lib.soname.as_deref().unwrap_or(&lib.path),
+            "version": lib.version.as_deref().unwrap_or(""),
+            "properties": [{ "name": "app2nix:path", "value": lib.path }],
+        }));
+        depends_on.push(bom_ref);
+    }
+
+    for dep in &pkg_info.deps {
+        let bom_ref = format!("nixpkgs:{}", dep);
+        components.push(json!({
+            "type": "library",
+            "bom-ref": bom_ref,
+            "name": dep,
+            "properties": [{ "name": "app2nix:source", "value": "nixpkgs" }],
+        }));
+        depends_on.push(bom_ref);
+    }
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": utc_timestamp(),
+            "tools": [{ "name": "app2nix", "version": env!("CARGO_PKG_VERSION") }],
+            "component": {
+                "type": "application",
+                "bom-ref": app_ref,
+                "name": pkg_info.name,
+                "version": pkg_info.version,
+                "description": pkg_info.description,
+                "purl": app_ref,
+                "hashes": [{ "alg": "SHA-256", "content": sha256 }],
+            },
+        },
+        "components": components,
+        "dependencies": [{ "ref": app_ref, "dependsOn": depends_on }],
+    })
+}
+
+pub fn default_sbom_path(format: SbomFormat) -> &'static str {
+    match format {
+        SbomFormat::Spdx => "sbom.spdx.json",
+        SbomFormat::CycloneDx => "sbom.cdx.json",
+    }
+}
+
+pub fn write_sbom(format: SbomFormat, pkg_info: &PackageInfo, sha256: &str, path: &str) -> Result<(), Box<dyn Error>> {
+    let document = match format {
+        SbomFormat::Spdx => spdx_document(pkg_info, sha256),
+        SbomFormat::CycloneDx => cyclonedx_document(pkg_info, sha256),
+    };
+    fs::write(path, serde_json::to_string_pretty(&document)?)?;
+    Ok(())
+}
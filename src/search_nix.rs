@@ -0,0 +1,85 @@
+use std::process::Command;
+
+use log::debug;
+
+use crate::configuration;
+
+/// A package already present in nixpkgs under the same name.
+pub struct ExistingPackage {
+    pub attr: String,
+    pub version: String,
+}
+
+/// Looks `name` up with `nix search nixpkgs` (or `APP2NIX_NIXPKGS`); `None` when nothing matches or
+/// the search itself is unavailable (no nix, no network, no registry).
+pub fn find_existing_package(name: &str, system: &str) -> Option<ExistingPackage> {
+    if name.is_empty() {
+        return None;
+    }
+
+    // A failed search leaves it unknown whether nixpkgs has the package
+    let output = match Command::new("nix")
+        .args(["search", &configuration::nixpkgs_flake(), &format!("^{}$", regex::escape(name)), "--json"])
+        .env("NIX_CONFIG", "experimental-features = nix-command flakes")
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) => {
+            debug!("nixpkgs check skipped: cannot run nix search: {}", e);
+            return None;
+        }
+    };
+    if !output.status.success() {
+        debug!(
+            "nixpkgs check skipped: nix search failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return None;
+    }
+
+    let json: serde_json::Value = match serde_json::from_slice(&output.stdout) {
+        Ok(json) => json,
+        Err(e) => {
+            debug!("nixpkgs check skipped: unreadable nix search output: {}", e);
+            return None;
+        }
+    };
+    let prefix = format!("legacyPackages.{}.", system);
+
+    let mut matches: Vec<ExistingPackage> = json
+        .as_object()?
+        .iter()
+        .filter_map(|(path, info)| {
+            let attr = path.strip_prefix(&prefix).unwrap_or(path);
+            let pname = info.get("pname").and_then(|p| p.as_str()).unwrap_or_default();
+            (pname == name || attr == name).then(|| ExistingPackage {
+                attr: attr.to_string(),
+                version: info
+                    .get("version")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+            })
+        })
+        .collect();
+
+    // Prefer the top-level attr over e.g. `python3Packages.<name>`
+    matches.sort_by_key(|m| (m.attr.matches('.').count(), m.attr.clone()));
+    matches.into_iter().next()
+}
+
+/// The revision `nix flake metadata` locks nixpkgs (or `APP2NIX_NIXPKGS`)
+/// to; `None` without nix or for an unlocked reference such as a path.
+pub fn nixpkgs_revision() -> Option<String> {
+    let output = Command::new("nix")
+        .args(["flake", "metadata", &configuration::nixpkgs_flake(), "--json"])
+        .env("NIX_CONFIG", "experimental-features = nix-command flakes")
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+    json.get("revision")
+        .or_else(|| json.pointer("/locked/rev"))
+        .and_then(|r| r.as_str())
+        .map(str::to_string)
+}
@@ -0,0 +1,298 @@
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+use serde_json::{Value, json};
+
+use crate::configuration;
+use crate::errors::AppError;
+
+/// Requests are a URL and a few options; anything bigger is refused.
+const MAX_BODY: usize = 64 * 1024;
+
+/// The request line and headers together; `read_line` would otherwise
+/// buffer an endless line.
+const MAX_HEAD: u64 = 16 * 1024;
+
+/// How long a client may take to send its request or read the answer;
+/// a stalled one would otherwise hold a worker forever.
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Generate options a client may pass. Options naming local files or
+/// commands (`--template`, `--post-install-file`, `--format-with`, ...)
+/// would let it read the server's files or run programs on it.
+const ALLOWED_FLAGS: &[&str] = &[
+    "--name",
+    "--pname",
+    "--version",
+    "--target-system",
+    "--preset",
+    "--extra-build-input",
+    "--extra-native-build-input",
+    "--exclude-dep",
+    "--wrapper-env",
+    "--opt-layout",
+    "--unpack-strategy",
+    "--bundled-conflicts",
+    "--audio",
+    "--fetch",
+    "--changelog-url",
+];
+const ALLOWED_SWITCHES: &[&str] = &[
+    "--skip-deps",
+    "--keep-baseline",
+    "--bin-links",
+    "--split-outputs",
+    "--prefer-system-libs",
+    "--wayland",
+    "--x11-only",
+    "--seed-comment",
+    "--provenance",
+    "--allow-unfree-note",
+    "--strict",
+    "--skip-nixpkgs-check",
+];
+
+struct Response {
+    status: u16,
+    body: Value,
+}
+
+impl Response {
+    fn error(status: u16, message: impl Into<String>) -> Response {
+        Response {
+            status,
+            body: json!({ "error": message.into() }),
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        422 => "Unprocessable Entity",
+        _ => "Internal Server Error",
+    }
+}
+
+/// The request line and body; headers other than Content-Length are
+/// ignored.
+fn read_request(stream: &TcpStream) -> Result<(String, String, String), Response> {
+    let mut reader = BufReader::new(stream);
+    let mut head = (&mut reader).take(MAX_HEAD);
+    // A line cut short by the limit or by the client hanging up has no
+    // newline
+    let mut read_line = || {
+        let mut line = String::new();
+        head.read_line(&mut line).map_err(|e| Response::error(400, e.to_string()))?;
+        if line.ends_with('\n') {
+            Ok(line)
+        } else if head.limit() == 0 {
+            Err(Response::error(431, format!("the request head is limited to {} bytes", MAX_HEAD)))
+        } else {
+            Err(Response::error(400, "incomplete request"))
+        }
+    };
+    let request_line = read_line()?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(Response::error(400, "malformed request line"));
+    };
+    let mut length = 0;
+    loop {
+        let header = read_line()?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            length = value.trim().parse().map_err(|_| Response::error(400, "bad Content-Length"))?;
+        }
+    }
+    if length > MAX_BODY {
+        return Err(Response::error(413, format!("bodies are limited to {} bytes", MAX_BODY)));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|e| Response::error(400, e.to_string()))?;
+    let body = String::from_utf8(body).map_err(|_| Response::error(400, "the body is not UTF-8"))?;
+    Ok((method.to_string(), path.to_string(), body))
+}
+
+/// The package URL and generate options from a `POST /generate` body:
+/// `{"url": "...", "args": ["--preset", "gtk"]}`, or the bare URL.
+fn generate_args(body: &str) -> Result<Vec<String>, Response> {
+    let (url, extra) = match serde_json::from_str::<Value>(body) {
+        Ok(request) => {
+            let url = request.get("url").and_then(Value::as_str).ok_or_else(|| Response::error(400, "url is missing"))?;
+            let extra = match request.get("args") {
+                None => Vec::new(),
+                Some(args) => args
+                    .as_array()
+                    .and_then(|a| a.iter().map(|v| v.as_str().map(str::to_string)).collect::<Option<Vec<_>>>())
+                    .ok_or_else(|| Response::error(400, "args must be a list of strings"))?,
+            };
+            (url.to_string(), extra)
+        }
+        Err(_) => (body.trim().to_string(), Vec::new()),
+    };
+    // Local paths would read the server's own files
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+        return Err(Response::error(400, "url must be an http(s) URL"));
+    }
+    let mut i = 0;
+    while i < extra.len() {
+        let arg = extra[i].as_str();
+        if ALLOWED_FLAGS.contains(&arg) {
+            i += 1;
+            if i == extra.len() {
+                return Err(Response::error(400, format!("{} requires a value", arg)));
+            }
+        } else if !ALLOWED_SWITCHES.contains(&arg) {
+            return Err(Response::error(400, format!("{} is not allowed through the API", arg)));
+        }
+        i += 1;
+    }
+    let mut args = vec![url];
+    args.extend(extra);
+    Ok(args)
+}
+
+/// Runs app2nix in a scratch directory of its own, so requests do not
+/// share files, and returns the expression, the analysis and the log.
+fn generate(args: &[String]) -> Response {
+    let workdir = match tempfile::tempdir() {
+        Ok(dir) => dir,
+        Err(e) => return Response::error(500, format!("Failed to create a work directory: {}", e)),
+    };
+    let program = match env::current_exe() {
+        Ok(program) => program,
+        Err(e) => return Response::error(500, format!("Cannot find app2nix itself: {}", e)),
+    };
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .args(["--force", "--analysis-json", "analysis.json"])
+        .current_dir(workdir.path())
+        .env("APP2NIX_NON_INTERACTIVE", "1")
+        .stdin(Stdio::null());
+    // The scratch directory is not where the server found its config
+    if let Some(config) = configuration::absolute_config_path() {
+        command.env("APP2NIX_CONFIG", config);
+    }
+    let output = match command.output() {
+        Ok(output) => output,
+        Err(e) => return Response::error(500, format!("Failed to run app2nix: {}", e)),
+    };
+    let log = String::from_utf8_lossy(&output.stderr).to_string();
+    if !output.status.success() {
+        return Response {
+            status: 422,
+            body: json!({
+                "error": log.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("generation failed"),
+                "exit_code": output.status.code(),
+                "log": log,
+            }),
+        };
+    }
+    let expression = fs::read_to_string(workdir.path().join("default.nix")).unwrap_or_default();
+    let analysis = fs::read_to_string(workdir.path().join("analysis.json"))
+        .ok()
+        .and_then(|a| serde_json::from_str::<Value>(&a).ok())
+        .unwrap_or(Value::Null);
+    Response {
+        status: 200,
+        body: json!({ "expression": expression, "analysis": analysis, "log": log }),
+    }
+}
+
+fn respond(mut stream: TcpStream) {
+    let response = match read_request(&stream) {
+        Err(response) => response,
+        Ok((method, path, body)) => match (method.as_str(), path.as_str()) {
+            ("GET", "/health") => Response {
+                status: 200,
+                body: json!({ "status": "ok", "version": env!("CARGO_PKG_VERSION") }),
+            },
+            ("POST", "/generate") => match generate_args(&body) {
+                Ok(args) => {
+                    info!("Generating for {}...", args[0]);
+                    generate(&args)
+                }
+                Err(response) => response,
+            },
+            (_, "/health" | "/generate") => Response::error(405, format!("{} is not supported on {}", method, path)),
+            _ => Response::error(404, format!("no such endpoint: {}", path)),
+        },
+    };
+    let body = format!("{}\n", response.body);
+    let written = write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason(response.status),
+        body.len(),
+        body
+    );
+    if let Err(e) = written {
+        warn!("Failed to answer a request: {}", e);
+    }
+}
+
+/// `app2nix serve`: answers `POST /generate` with the expression and
+/// analysis for a package URL, one app2nix process per request and as
+/// many at once as there are CPUs, and `GET /health`.
+pub fn run_serve_command(listen: &str) -> Result<(), AppError> {
+    let listener = TcpListener::bind(listen).map_err(|e| AppError::Usage(format!("Cannot listen on {}: {}", listen, e)))?;
+    // One connection, and so at most one generation, per worker; the rest
+    // wait in the queue and then in the listen backlog
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    info!(
+        "Listening on http://{} (POST /generate, GET /health), {} requests at a time...",
+        listen, workers
+    );
+    let (sender, receiver) = mpsc::sync_channel::<TcpStream>(workers);
+    let receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..workers {
+        let receiver = Arc::clone(&receiver);
+        thread::spawn(move || {
+            loop {
+                let Ok(stream) = receiver.lock().unwrap_or_else(|e| e.into_inner()).recv() else {
+                    break;
+                };
+                respond(stream);
+            }
+        });
+    }
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = stream
+                    .set_read_timeout(Some(IO_TIMEOUT))
+                    .and_then(|()| stream.set_write_timeout(Some(IO_TIMEOUT)))
+                {
+                    warn!("Failed to set a connection's timeouts: {}", e);
+                    continue;
+                }
+                if sender.send(stream).is_err() {
+                    break;
+                }
+            }
+            Err(e) => warn!("Failed to accept a connection: {}", e),
+        }
+    }
+    Ok(())
+}
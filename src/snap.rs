@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::errors::AppError;
+use crate::license;
+use crate::readfile_nix::{debian_arch_to_system, ARCH_INDEPENDENT};
+use crate::structs::{EntryPoint, PackageInfo, PackageType};
+
+/// The parts of `meta/snap.yaml` app2nix uses.
+#[derive(Deserialize)]
+struct SnapYaml {
+    name: String,
+    #[serde(default)]
+    version: String,
+    #[serde(default)]
+    summary: String,
+    #[serde(default)]
+    description: String,
+    /// An SPDX expression.
+    #[serde(default)]
+    license: String,
+    #[serde(default)]
+    website: Option<String>,
+    /// snapd treats a snap without `architectures` as `all`.
+    #[serde(default)]
+    architectures: Vec<String>,
+    #[serde(default)]
+    apps: BTreeMap<String, SnapApp>,
+}
+
+#[derive(Deserialize)]
+struct SnapApp {
+    command: Option<String>,
+}
+
+/// Extracts `paths` (everything if empty) of the squashfs image at `path`
+/// into `dest` with `unsquashfs` from squashfsTools.
+pub fn unsquash(path: &Path, dest: &Path, paths: &[&str]) -> Result<(), AppError> {
+    let output = Command::new("unsquashfs")
+        .args(["-no-progress", "-no-xattrs", "-f", "-d"])
+        .arg(dest)
+        .arg(path)
+        .args(paths)
+        .output()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => AppError::Extract(
+                "unsquashfs not found; install squashfsTools or pass --auto-shell to unpack snaps".to_string(),
+            ),
+            _ => AppError::Extract(format!("Failed to run unsquashfs: {}", e)),
+        })?;
+    if !output.status.success() {
+        return Err(AppError::Extract(format!(
+            "unsquashfs failed on {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Reads name, version, arch, descriptions and the `apps` entry points from
+/// the `meta/snap.yaml` of a `.snap`.
+pub fn read_snap_yaml(path: &Path) -> Result<PackageInfo, AppError> {
+    let dir = tempfile::Builder::new()
+        .prefix("app2nix-snap-")
+        .tempdir()
+        .map_err(|e| AppError::Extract(format!("Failed to create temp dir: {}", e)))?;
+    let meta_dir = dir.path().join("root");
+    unsquash(path, &meta_dir, &["meta/snap.yaml"])?;
+
+    let content = fs::read_to_string(meta_dir.join("meta/snap.yaml"))
+        .map_err(|_| AppError::Extract(format!("No meta/snap.yaml in {}", path.display())))?;
+    let snap: SnapYaml = serde_yaml::from_str(&content)
+        .map_err(|e| AppError::Extract(format!("Malformed meta/snap.yaml in {}: {}", path.display(), e)))?;
+
+    let arch = match snap.architectures.first().map(String::as_str) {
+        None | Some("all") => ARCH_INDEPENDENT.to_string(),
+        Some(arch) => debian_arch_to_system(arch),
+    };
+    // snapd runs the app named like the snap as `<snap>`, others as `<snap>.<app>`
+    let entry_points = snap
+        .apps
+        .iter()
+        .filter_map(|(app, spec)| {
+            let command = spec.command.as_deref()?;
+            let command = command.strip_prefix("$SNAP/").unwrap_or(command);
+            let name = if *app == snap.name { app.clone() } else { format!("{}.{}", snap.name, app) };
+            Some(EntryPoint {
+                name,
+                command: command.to_string(),
+            })
+        })
+        .collect();
+
+    // Without a summary the description is all there is
+    let (description, long_description) = if snap.summary.is_empty() {
+        (snap.description, String::new())
+    } else {
+        (snap.summary, snap.description.trim().to_string())
+    };
+    Ok(PackageInfo {
+        package_type: PackageType::Snap,
+        licenses: license::split_expression(&snap.license),
+        homepage: snap.website,
+        description,
+        long_description,
+        name: snap.name,
+        version: snap.version,
+        arch,
+        entry_points,
+        ..PackageInfo::default()
+    })
+}
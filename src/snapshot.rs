@@ -0,0 +1,232 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use log::{debug, info, warn};
+use serde_json::Value;
+
+use crate::errors::AppError;
+use crate::hashing;
+use crate::readfile_nix::system_to_debian_arch;
+use crate::structs::ArchivePackage;
+
+const SNAPSHOT_URL: &str = "https://snapshot.debian.org";
+const LAUNCHPAD_API: &str = "https://api.launchpad.net/1.0";
+
+/// A package file found in an archive: its immutable URL and the
+/// checksums the archive publishes for it.
+pub struct ArchiveFile {
+    pub url: String,
+    pub sha256: Option<Vec<u8>>,
+    pub sha1: Option<String>,
+}
+
+/// Splits `<pkg>[=<version>]`.
+fn name_and_version(spec: &str) -> (String, Option<String>) {
+    match spec.split_once('=') {
+        Some((name, version)) => (name.to_string(), Some(version.to_string())),
+        None => (spec.to_string(), None),
+    }
+}
+
+/// Parses the value of `--debian`: `<pkg>[=<version>]`.
+pub fn parse_debian(spec: &str) -> Result<ArchivePackage, String> {
+    let (name, version) = name_and_version(spec.trim());
+    if name.is_empty() || version.as_deref() == Some("") {
+        return Err(format!("--debian expects <package>[=<version>] (got: {})", spec));
+    }
+    Ok(ArchivePackage::Debian { name, version })
+}
+
+/// Parses the value of `--ppa`: `ppa:<owner>/<ppa>/<pkg>[=<version>]`.
+pub fn parse_ppa(spec: &str) -> Result<ArchivePackage, String> {
+    let usage = || format!("--ppa expects ppa:<owner>/<ppa>/<package>[=<version>] (got: {})", spec);
+    let rest = spec.trim().strip_prefix("ppa:").ok_or_else(usage)?;
+    let mut parts = rest.splitn(3, '/');
+    let (Some(owner), Some(ppa), Some(package)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(usage());
+    };
+    let (name, version) = name_and_version(package);
+    if owner.is_empty() || ppa.is_empty() || name.is_empty() || version.as_deref() == Some("") {
+        return Err(usage());
+    }
+    Ok(ArchivePackage::Ppa {
+        owner: owner.to_string(),
+        ppa: ppa.to_string(),
+        name,
+        version,
+    })
+}
+
+fn get_json(url: &str) -> Result<Value, AppError> {
+    debug!("GET {}", url);
+    let output = Command::new("curl")
+        .args(["-sS", "--fail", "-L", url])
+        .output()
+        .map_err(|e| AppError::Download(format!("Failed to run curl: {}", e)))?;
+    if !output.status.success() {
+        return Err(AppError::Download(format!(
+            "{}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    serde_json::from_slice(&output.stdout).map_err(|e| AppError::Download(format!("Invalid response from {}: {}", url, e)))
+}
+
+fn str_field<'a>(value: &'a Value, key: &str) -> Option<&'a str> {
+    value.get(key).and_then(Value::as_str)
+}
+
+/// Looks a binary package up on snapshot.debian.org: the requested (or
+/// newest) version's file for `arch`, or its `all` file.
+fn resolve_debian(name: &str, version: Option<&str>, arch: &str) -> Result<ArchiveFile, AppError> {
+    let versions = get_json(&format!("{}/mr/binary/{}/", SNAPSHOT_URL, name))?;
+    let results = versions.get("result").and_then(Value::as_array).cloned().unwrap_or_default();
+    // Newest first
+    let binary_version = results
+        .iter()
+        .filter_map(|r| str_field(r, "binary_version"))
+        .find(|v| version.is_none_or(|wanted| *v == wanted))
+        .ok_or_else(|| match version {
+            Some(wanted) => AppError::Input(format!("snapshot.debian.org has no {} {}", name, wanted)),
+            None => AppError::Input(format!("snapshot.debian.org has no binary package {}", name)),
+        })?
+        .to_string();
+
+    let files = get_json(&format!(
+        "{}/mr/binary/{}/{}/binfiles?fileinfo=1",
+        SNAPSHOT_URL, name, binary_version
+    ))?;
+    let hash = ["all", arch]
+        .iter()
+        .rev()
+        .find_map(|wanted| {
+            files
+                .get("result")
+                .and_then(Value::as_array)?
+                .iter()
+                .find(|f| str_field(f, "architecture") == Some(wanted))
+                .and_then(|f| str_field(f, "hash"))
+        })
+        .ok_or_else(|| AppError::Input(format!("{} {} is not built for {}", name, binary_version, arch)))?;
+    // The same file may be in several archives; debian's is the usual one
+    let seen = files
+        .get("fileinfo")
+        .and_then(|info| info.get(hash))
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let entry = seen
+        .iter()
+        .find(|e| str_field(e, "archive_name") == Some("debian"))
+        .or_else(|| seen.first())
+        .ok_or_else(|| AppError::Download(format!("snapshot.debian.org lists no location for {}", hash)))?;
+    let field = |key| {
+        str_field(entry, key).ok_or_else(|| AppError::Download(format!("snapshot.debian.org file info lacks {}", key)))
+    };
+    let url = format!(
+        "{}/archive/{}/{}{}/{}",
+        SNAPSHOT_URL,
+        field("archive_name")?,
+        field("first_seen")?,
+        field("path")?,
+        field("name")?
+    );
+    Ok(ArchiveFile {
+        url,
+        sha256: None,
+        sha1: Some(hash.to_string()),
+    })
+}
+
+/// `value` percent-encoded for a URL query: everything but RFC 3986's
+/// unreserved characters, so a version's `+` or `:` arrives as written.
+fn query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Looks a binary package up in a Launchpad PPA: the requested (or newest
+/// published) version for `arch`.
+fn resolve_ppa(owner: &str, ppa: &str, name: &str, version: Option<&str>, arch: &str) -> Result<ArchiveFile, AppError> {
+    let mut query = format!(
+        "{}/~{}/+archive/ubuntu/{}?ws.op=getPublishedBinaries&binary_name={}&exact_match=true&status=Published",
+        LAUNCHPAD_API,
+        owner,
+        ppa,
+        query_value(name)
+    );
+    if let Some(version) = version {
+        query.push_str(&format!("&version={}", query_value(version)));
+    }
+    let published = get_json(&query)?;
+    let entries = published.get("entries").and_then(Value::as_array).cloned().unwrap_or_default();
+    let entry = entries
+        .iter()
+        .find(|e| {
+            let series_arch = str_field(e, "distro_arch_series_link").and_then(|l| l.rsplit('/').next());
+            series_arch == Some(arch) || e.get("architecture_specific") == Some(&Value::Bool(false))
+        })
+        .ok_or_else(|| AppError::Input(format!("ppa:{}/{} publishes no {} for {}", owner, ppa, name, arch)))?;
+    let self_link =
+        str_field(entry, "self_link").ok_or_else(|| AppError::Download("Launchpad entry lacks self_link".to_string()))?;
+
+    let files = get_json(&format!("{}?ws.op=binaryFileUrls&include_meta=true", self_link))?;
+    let file = files
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|f| str_field(f, "url").is_some_and(|u| u.ends_with(".deb")))
+        .ok_or_else(|| AppError::Download(format!("Launchpad lists no .deb for {}", self_link)))?;
+    Ok(ArchiveFile {
+        url: str_field(file, "url").unwrap_or_default().to_string(),
+        sha256: str_field(file, "sha256").and_then(hashing::from_hex),
+        sha1: str_field(file, "sha1").map(str::to_string),
+    })
+}
+
+/// Resolves `package` to the immutable URL of its .deb for `target_system`.
+pub fn resolve(package: &ArchivePackage, target_system: &str) -> Result<ArchiveFile, AppError> {
+    let arch = system_to_debian_arch(target_system);
+    let file = match package {
+        ArchivePackage::Debian { name, version } => {
+            info!("Looking up {} on snapshot.debian.org...", name);
+            resolve_debian(name, version.as_deref(), &arch)?
+        }
+        ArchivePackage::Ppa {
+            owner,
+            ppa,
+            name,
+            version,
+        } => {
+            info!("Looking up {} in ppa:{}/{}...", name, owner, ppa);
+            resolve_ppa(owner, ppa, name, version.as_deref(), &arch)?
+        }
+    };
+    info!("Resolved to {}", file.url);
+    Ok(file)
+}
+
+/// Checks `path` against the SHA-1 the archive publishes, with `sha1sum`.
+/// A mismatching file is removed so the next run fetches it again.
+pub fn check_sha1(path: &str, sha1: &str) -> Result<(), AppError> {
+    let output = match Command::new("sha1sum").arg(Path::new(path)).output() {
+        Ok(output) if output.status.success() => output,
+        _ => {
+            warn!("sha1sum is not available; {} was not checked against the archive's SHA-1.", path);
+            return Ok(());
+        }
+    };
+    let actual = String::from_utf8_lossy(&output.stdout);
+    if actual.split_whitespace().next() != Some(sha1) {
+        let _ = fs::remove_file(path);
+        return Err(AppError::Download(format!("{} does not match the archive's SHA-1 {}", path, sha1)));
+    }
+    Ok(())
+}
@@ -4,18 +4,696 @@ use serde::{Serialize, Deserialize};
 pub struct LibrariesConfig {
     pub system_libs: Vec<String>,
     pub lib_to_pkg_map: std::collections::HashMap<String, String>,
+    /// Top-level Python import name -> `python3Packages` attribute.
+    #[serde(default)]
+    pub python_modules: std::collections::HashMap<String, String>,
+    /// Debian package name (`Build-Depends`) -> nixpkgs attribute.
+    #[serde(default)]
+    pub debian_packages: std::collections::HashMap<String, String>,
+    #[serde(default)]
+    pub ranking: RankingPreferences,
+    /// Per-package settings, keyed by the package's own name (deb
+    /// `Package:`, pacman `pkgname`).
+    #[serde(default)]
+    pub overrides: std::collections::HashMap<String, PackageOverride>,
+    #[serde(default)]
+    pub hooks: PluginHooks,
 }
 
-#[derive(Debug, Default)]
+/// Plugin command lines per pipeline stage, each run with its argument
+/// appended (see `plugins::PluginStage`).
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PluginHooks {
+    #[serde(default)]
+    pub post_extract: Vec<String>,
+    #[serde(default)]
+    pub deps: Vec<String>,
+    #[serde(default)]
+    pub render: Vec<String>,
+}
+
+/// Nixpkgs attrs to favour or pass over when nix-locate finds several
+/// providers of a library.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RankingPreferences {
+    #[serde(default)]
+    pub prefer: Vec<String>,
+    #[serde(default)]
+    pub avoid: Vec<String>,
+}
+
+/// Settings applied whenever one particular package is converted, as if
+/// the matching flags had been passed; flags given as well add to the
+/// lists and win over `preset` and same-named `env` entries.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PackageOverride {
+    /// Soname -> nixpkgs attribute, taking precedence over the global map.
+    #[serde(default)]
+    pub lib_to_pkg_map: std::collections::HashMap<String, String>,
+    #[serde(default)]
+    pub extra_build_inputs: Vec<String>,
+    #[serde(default)]
+    pub extra_native_build_inputs: Vec<String>,
+    /// Variables the wrapper sets (`--wrapper-env`).
+    #[serde(default)]
+    pub env: std::collections::BTreeMap<String, String>,
+    #[serde(default)]
+    pub preset: Option<String>,
+    /// Attrs left out of buildInputs (`--exclude-dep`).
+    #[serde(default)]
+    pub exclude_deps: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct PackageInfo {
+    pub package_type: PackageType,
     pub name: String,
     pub version: String,
     pub deps: Vec<String>,
     pub arch: String,
-    pub description: String
+    /// The one-line summary.
+    pub description: String,
+    /// The paragraphs after the summary (Debian's extended description,
+    /// snap's `description`), as lines.
+    #[serde(default)]
+    pub long_description: String,
+    #[serde(default)]
+    pub homepage: Option<String>,
+    /// Who maintains the package upstream, `Name <email>`.
+    #[serde(default)]
+    pub maintainer: Option<String>,
+    /// Debian's archive section (`utils`, `games`, `non-free/games`).
+    #[serde(default)]
+    pub section: Option<String>,
+    #[serde(default)]
+    pub priority: Option<String>,
+    pub control_depends: Vec<String>,
+    /// `Installed-Size:` from the control file, in bytes.
+    pub installed_size: Option<u64>,
+    pub kind: AppKind,
+    pub script_interpreters: Vec<String>,
+    /// 32-bit objects resolved against `pkgsi686Linux` on an x86_64 target.
+    pub multilib: bool,
+    /// Binaries are linked against musl; libraries come from `pkgsMusl`.
+    #[serde(default)]
+    pub musl: bool,
+    /// The deb's payload is `data.tar.zst`, which the unpackPhase's tar
+    /// needs zstd for.
+    #[serde(default)]
+    pub zstd: bool,
+    /// Non-system sonames the payload's binaries need, before resolution.
+    pub needed_libs: Vec<String>,
+    pub binaries: Vec<BinaryInfo>,
+    pub bundled_libs: Vec<BundledLib>,
+    /// Every soname linked or dlopen'ed by the payload, system libs included.
+    pub referenced_libs: Vec<String>,
+    /// Needed sonames no nixpkgs attr was found for.
+    pub missing_libs: Vec<String>,
+    /// Configuration files the package marks as user-editable (deb
+    /// `conffiles`, pacman `backup`), as absolute paths.
+    #[serde(default)]
+    pub conffiles: Vec<String>,
+    /// Links to re-point into `$out`.
+    #[serde(default)]
+    pub links: Vec<PayloadLink>,
+    /// Payload-relative symlinks whose target the package does not ship,
+    /// removed from `$out`.
+    #[serde(default)]
+    pub dangling_links: Vec<String>,
+    /// Files needing capabilities or setuid/setgid, which on NixOS only
+    /// `security.wrappers` can grant.
+    #[serde(default)]
+    pub privileged: Vec<PrivilegedFile>,
+    /// Payload-relative ELF executables shipped without an exec bit.
+    #[serde(default)]
+    pub non_executable: Vec<String>,
+    /// Commands the package declares itself (snap `apps`), wrapped into
+    /// `$out/bin`.
+    #[serde(default)]
+    pub entry_points: Vec<EntryPoint>,
+    /// Name and version as the package spelled them, when `name` and
+    /// `version` had to be normalized for Nix.
+    #[serde(default)]
+    pub original_name: Option<String>,
+    #[serde(default)]
+    pub original_version: Option<String>,
+    /// The payload is an Electron app.
+    #[serde(default)]
+    pub electron: bool,
+    /// `usr/share/applications` launchers.
+    #[serde(default)]
+    pub desktop_entries: Vec<DesktopEntry>,
+    /// Payload-relative `.desktop` files under `etc/xdg/autostart`.
+    #[serde(default)]
+    pub autostart: Vec<String>,
+    #[serde(default)]
+    pub integration: Integration,
+    #[serde(default)]
+    pub extra_files: ExtraFiles,
+    /// Needed libraries the package bundles and nixpkgs also provides.
+    #[serde(default)]
+    pub lib_conflicts: Vec<LibConflict>,
+    /// Licenses as the package states them (`GPL-2+`, `MIT`,
+    /// `custom:EULA`), mapped to `lib.licenses` when generating.
+    #[serde(default)]
+    pub licenses: Vec<String>,
+    /// The newest entry of `changelog.Debian.gz`.
+    #[serde(default)]
+    pub changelog: Option<ChangelogEntry>,
+    /// Why the binary scan was abandoned, if it was.
+    #[serde(skip)]
+    pub scan_error: Option<String>,
+}
+
+/// A package file the generated expression fetches.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub url: String,
+    /// Other URLs of the same file, listed after `url` in fetchurl's `urls`.
+    pub mirrors: Vec<String>,
+    /// SRI hash, `sha256-<base64>`.
+    pub sha256: String,
+    /// Nix system the file is built for, when one package per
+    /// architecture is given instead of several parts of one.
+    pub system: Option<String>,
+    /// For a directory input, where its contents go in the package root:
+    /// empty for a tree with its own `usr/` or `opt/`, else `opt/<dir>`.
+    pub tree: Option<String>,
+}
+
+/// A symlink the installPhase recreates inside `$out`: an absolute link in
+/// the payload or one registered with `update-alternatives`. Both paths are
+/// relative to the payload root.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PayloadLink {
+    pub link: String,
+    pub target: String,
+}
+
+/// A named command and the payload-relative path it runs, with any
+/// arguments after it (`bin/foo --flag`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntryPoint {
+    pub name: String,
+    pub command: String,
+}
+
+/// The unlocalized `[Desktop Entry]` keys of a shipped `.desktop` file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DesktopEntry {
+    /// File name without `.desktop`, the desktop file ID.
+    pub id: String,
+    pub name: String,
+    pub generic_name: Option<String>,
+    pub comment: Option<String>,
+    pub exec: Option<String>,
+    pub icon: Option<String>,
+    pub terminal: bool,
+    pub categories: Vec<String>,
+    pub mime_types: Vec<String>,
+}
+
+/// systemd, udev and D-Bus files the package hooks into the system with.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Integration {
+    /// Unit file names under `lib/systemd/system`.
+    pub system_units: Vec<String>,
+    /// Unit file names under `lib/systemd/user`.
+    pub user_units: Vec<String>,
+    pub udev_rules: Vec<String>,
+    /// D-Bus service activation files and bus policies.
+    pub dbus_files: Vec<String>,
+}
+
+impl Integration {
+    pub fn is_empty(&self) -> bool {
+        self.system_units.is_empty()
+            && self.user_units.is_empty()
+            && self.udev_rules.is_empty()
+            && self.dbus_files.is_empty()
+    }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// Man pages, shell completions and license files outside the places
+/// nixpkgs looks for them, as payload-relative paths.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExtraFiles {
+    pub man_pages: Vec<String>,
+    pub bash_completions: Vec<String>,
+    pub zsh_completions: Vec<String>,
+    pub fish_completions: Vec<String>,
+    pub licenses: Vec<String>,
+}
+
+impl ExtraFiles {
+    /// Whether installPhase needs `installShellFiles`.
+    pub fn needs_install_shell_files(&self) -> bool {
+        !self.man_pages.is_empty()
+            || !self.bash_completions.is_empty()
+            || !self.zsh_completions.is_empty()
+            || !self.fish_completions.is_empty()
+    }
+}
+
+/// Runtime layout detected in the extracted payload; selects the template.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub enum AppKind {
+    #[default]
+    Native,
+    /// `entry` is the main `.dll`, `apphost` the native launcher next to it
+    /// and `runtime` the nixpkgs attr for framework-dependent apps.
+    DotNet {
+        entry: String,
+        apphost: Option<String>,
+        runtime: Option<String>,
+    },
+    Mono {
+        entry: String,
+    },
+    /// `entries` are the scripts with a python shebang, `site_packages` the
+    /// bundled module directories and `modules` the mapped python3Packages.
+    Python {
+        entries: Vec<String>,
+        site_packages: Vec<String>,
+        modules: Vec<String>,
+    },
+    /// Every ELF object is statically linked; `binaries` are the executables.
+    Static {
+        binaries: Vec<String>,
+    },
+    /// `Architecture: all`: data and scripts only, nothing to scan.
+    ArchIndependent,
+    /// DKMS kernel module sources in `source_dir`, built against a
+    /// nixpkgs kernel instead of installed.
+    Dkms {
+        source_dir: String,
+    },
+    /// Fonts, icon themes, MIME data or firmware and nothing else to run.
+    Data {
+        kinds: Vec<DataKind>,
+    },
+}
+
+/// What a data-only package provides; each needs its own system wiring.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum DataKind {
+    Fonts,
+    Icons,
+    Mime,
+    /// `lib/firmware` blobs for `hardware.firmware`.
+    Firmware,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum ElfKind {
+    Dynamic,
+    SharedLibrary,
+    Static,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BinaryInfo {
+    pub path: String,
+    pub kind: ElfKind,
+    pub is_go: bool,
+    pub is_32bit: bool,
+    /// `e_machine`, the instruction set.
+    #[serde(default)]
+    pub machine: u16,
+    pub soname: Option<String>,
+    /// `PT_INTERP`, the dynamic loader path.
+    #[serde(default)]
+    pub interpreter: Option<String>,
+    /// `DT_NEEDED` entries.
+    #[serde(default)]
+    pub needed: Vec<String>,
+    /// Library search directories from `DT_RUNPATH`, or `DT_RPATH` if it
+    /// has none, as written (`$ORIGIN/../lib`).
+    #[serde(default)]
+    pub search_paths: Vec<String>,
+    /// Library names embedded as strings, i.e. likely `dlopen` targets.
+    pub dlopen_names: Vec<String>,
+}
+
+/// A file that needs more than the store's plain modes: capabilities a
+/// maintainer script sets with `setcap`, or a setuid/setgid bit.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PrivilegedFile {
+    /// Payload-relative path.
+    pub path: String,
+    /// As given to `setcap`, e.g. `cap_net_raw+ep`.
+    pub capabilities: Option<String>,
+    pub setuid: bool,
+    pub setgid: bool,
+}
+
+/// A shared library shipped inside the package itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundledLib {
+    pub path: String,
+    pub soname: Option<String>,
+    pub version: Option<String>,
+    /// The nixpkgs attr known to provide the same soname.
+    #[serde(default)]
+    pub nixpkgs: Option<String>,
+}
+
+/// The newest entry of a package's Debian changelog.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChangelogEntry {
+    pub version: String,
+    pub distribution: String,
+    /// The change lines, `* ` bullets included, without their indentation.
+    pub changes: Vec<String>,
+    /// `Name <email>` of whoever made the upload.
+    pub maintainer: String,
+    pub date: String,
+}
+
+/// A needed soname that is both shipped in the package and provided by
+/// nixpkgs; `--bundled-conflicts` decides which copy is linked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibConflict {
+    pub soname: String,
+    /// The nixpkgs attr providing it.
+    pub nixpkgs: String,
+    /// Payload-relative paths of the bundled copies.
+    pub bundled: Vec<String>,
+    /// The nixpkgs build is linked and the bundled copies are deleted.
+    #[serde(default)]
+    pub use_nixpkgs: bool,
+}
+
+/// How a `LibConflict` is settled (`--bundled-conflicts`).
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum ConflictPolicy {
+    PreferBundled,
+    /// Link the nixpkgs build, which gets security updates.
+    #[default]
+    PreferNix,
+    /// Ask about each conflict on the terminal.
+    Ask,
+}
+
+/// Command line knobs for unpacking and scanning the payload.
+#[derive(Debug, Default, Clone)]
+pub struct ScanOptions {
+    pub skip_deps: bool,
+    /// Leave the extracted tree on disk after the scan.
+    pub keep_workdir: bool,
+    /// Extract here instead of a fresh temporary directory; implies
+    /// `keep_workdir`.
+    pub workdir: Option<String>,
+    /// Parent directory for the temporary extraction tree.
+    pub tmpdir: Option<String>,
+    /// ELF files above this many bytes are not inspected.
+    pub max_scan_size: Option<u64>,
+}
+
+/// Where the installPhase puts a payload's `opt/<vendor>` tree.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum OptLayout {
+    /// `$out/<vendor>`, next to the `usr/` contents.
+    #[default]
+    Flatten,
+    /// `$out/opt/<vendor>`, the tree as the vendor shipped it.
+    Opt,
+    /// `$out/lib/<name>`, the vendor directory renamed after the package.
+    Lib,
+}
+
+/// How the unpackPhase extracts a deb (`--unpack-strategy`).
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum UnpackStrategy {
+    /// `ar -x` then `tar -xf data.tar*`.
+    #[default]
+    Tar,
+    /// `dpkg-deb -x`, as nixpkgs' own deb repackaging derivations do.
+    Dpkg,
+}
+
+/// Sound server the generated wrapper targets (`--audio`).
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AudioBackend {
+    Pipewire,
+    Pulse,
+    Jack,
+}
+
+/// Display server the wrapper steers the app's toolkit to (`--wayland`,
+/// `--x11-only`).
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DisplayServer {
+    /// Native Wayland, falling back to X11 outside a Wayland session.
+    Wayland,
+    X11,
+}
+
+/// A bundle of inputs a kind of app commonly needs beyond what the scan
+/// finds (`--preset`).
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Preset {
+    /// GSettings schemas, TLS for GIO and the GApps wrapper.
+    Gtk,
+    Qt5,
+    Qt6,
+    /// OpenGL, Vulkan, SDL2 and sound, mostly dlopen'ed.
+    Games,
+}
+
+impl Preset {
+    pub fn from_name(name: &str) -> Option<Preset> {
+        match name {
+            "gtk" => Some(Preset::Gtk),
+            "qt5" => Some(Preset::Qt5),
+            "qt6" | "qt" => Some(Preset::Qt6),
+            "games" | "game" => Some(Preset::Games),
+            _ => None,
+        }
+    }
+
+    pub fn build_inputs(self) -> &'static [&'static str] {
+        match self {
+            Preset::Gtk => &["gsettings-desktop-schemas", "glib-networking", "gtk3"],
+            Preset::Qt5 => &["qt5.qtbase", "qt5.qtwayland"],
+            Preset::Qt6 => &["qt6.qtbase", "qt6.qtwayland"],
+            Preset::Games => &["libGL", "vulkan-loader", "SDL2", "libpulseaudio", "alsa-lib"],
+        }
+    }
+
+    pub fn native_build_inputs(self) -> &'static [&'static str] {
+        match self {
+            Preset::Gtk => &["wrapGAppsHook3"],
+            Preset::Qt5 => &["qt5.wrapQtAppsHook"],
+            Preset::Qt6 => &["qt6.wrapQtAppsHook"],
+            Preset::Games => &[],
+        }
+    }
+}
+
+/// Pinning tool that owns the source reference instead of default.nix
+/// (`--pin`).
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SourcePin {
+    /// An `nvfetcher.toml` entry; default.nix reads `_sources/generated.nix`.
+    Nvfetcher,
+}
+
+/// The fetcher of default.nix's src (`--fetch`).
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum Fetcher {
+    #[default]
+    Fetchurl,
+    /// `requireFile`: the user downloads the file (behind a EULA or a
+    /// login) and adds it to the store by hand.
+    RequireFile,
+}
+
+/// A binary package looked up in an archive instead of given as a URL
+/// (`--debian`, `--ppa`).
+#[derive(Debug, Clone)]
+pub enum ArchivePackage {
+    /// On snapshot.debian.org; the newest version if none is given.
+    Debian { name: String, version: Option<String> },
+    /// In a Launchpad PPA, `ppa:<owner>/<ppa>`.
+    Ppa {
+        owner: String,
+        ppa: String,
+        name: String,
+        version: Option<String>,
+    },
+}
+
+/// Credentials for package downloads behind authentication (`--header`,
+/// `--user`, `--netrc`). Only the header names and whether a login is
+/// needed reach the generated expression, never the secrets.
+#[derive(Debug, Default, Clone)]
+pub struct DownloadAuth {
+    /// Raw `Name: value` request headers, e.g. a bearer token.
+    pub headers: Vec<String>,
+    /// `user` or `user:password` for basic auth.
+    pub user: Option<String>,
+    /// Log in with the host's entry in `~/.netrc`.
+    pub netrc: bool,
+}
+
+impl DownloadAuth {
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty() && self.user.is_none() && !self.netrc
+    }
+
+    /// Whether the server wants a login, from `--user` or `~/.netrc`.
+    pub fn login(&self) -> bool {
+        self.user.is_some() || self.netrc
+    }
+}
+
+/// Command line knobs that shape the generated expression.
+#[derive(Debug, Default, Clone)]
+pub struct GenerateOptions {
+    /// Ship the whole baseline even if no binary references it.
+    pub keep_baseline: bool,
+    pub opt_layout: OptLayout,
+    pub unpack_strategy: UnpackStrategy,
+    /// Symlink every installed executable into `$out/bin`.
+    pub bin_links: bool,
+    /// Move shared libraries and documentation to `lib` and `doc` outputs.
+    pub split_outputs: bool,
+    /// Delete bundled libraries nixpkgs provides and link against those.
+    pub prefer_system_libs: bool,
+    /// Overrides the sound server guessed from the audio libraries.
+    pub audio: Option<AudioBackend>,
+    pub display: Option<DisplayServer>,
+    pub pin: Option<SourcePin>,
+    pub fetcher: Fetcher,
+    /// Derivation pname to use instead of the package name (`--pname`).
+    pub pname: Option<String>,
+    /// Attrs added to buildInputs and the wrapper's library path, for
+    /// dependencies no scan can see (`--extra-build-input`).
+    pub extra_build_inputs: Vec<String>,
+    pub extra_native_build_inputs: Vec<String>,
+    pub preset: Option<Preset>,
+    /// Attrs kept out of buildInputs and the library path even if the
+    /// scan or the baseline brings them in (`--exclude-dep`).
+    pub exclude_deps: Vec<String>,
+    /// Variables the wrapper sets, in order (`--wrapper-env NAME=VALUE`).
+    pub wrapper_env: Vec<(String, String)>,
+    /// Hand-written snippets spliced into the build phases, in the order
+    /// given (`--post-install-file`, ...).
+    pub hooks: Vec<(Hook, String)>,
+    /// How the downloaded package authenticated, for the fetchurl.
+    pub auth: DownloadAuth,
+    /// How long the VM test's program must keep running
+    /// (`--vm-test-seconds`).
+    pub vm_test_seconds: Option<u64>,
+    /// Template used for default.nix instead of the built-in one
+    /// (`--template`).
+    pub template: Option<String>,
+    /// Recorded in `passthru.app2nix` with `--provenance`.
+    pub provenance: Option<Provenance>,
+    /// Explain how to build an `unfree` package (`--allow-unfree-note`).
+    pub unfree_note: bool,
+    /// Written as `passthru.changelog` (`--changelog-url`).
+    pub changelog_url: Option<String>,
+}
+
+/// How an expression was produced, for finding out long after why it
+/// looks the way it does.
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    pub tool_version: String,
+    /// The command line, with credentials masked.
+    pub command: String,
+    /// File name and SRI sha256 of each input.
+    pub inputs: Vec<(String, String)>,
+    /// The flake reference nixpkgs attrs were looked up in, and the
+    /// revision it was locked to then, if nix could tell.
+    pub nixpkgs: String,
+    pub nixpkgs_rev: Option<String>,
+    /// RFC 3339, from `SOURCE_DATE_EPOCH` if set.
+    pub date: String,
+}
+
+/// The build phase a `--*-file` snippet is spliced into.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Hook {
+    /// The end of installPhase.
+    PostInstall,
+    /// preFixup, before patchelf and the wrappers' fixups run.
+    PreFixup,
+    PostFixup,
+}
+
+/// Extra expressions written next to default.nix (`--format`).
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    /// `hm-module.nix`, a Home Manager module installing the package.
+    HomeManager,
+    /// `module.nix`, a NixOS module with `programs.<name>.enable`.
+    NixosModule,
+    /// `docker.nix`, an OCI image via `dockerTools.buildLayeredImage`.
+    Docker,
+    /// `flake.nix`, exposing default.nix for every system it has a source for.
+    Flake,
+    /// `test.nix`, a `nixosTest` VM that starts the main program.
+    VmTest,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SbomFormat {
+    Spdx,
+    CycloneDx,
+}
+
+#[derive(Debug, Default)]
+pub struct ScanResult {
+    pub deps: Vec<String>,
+    pub missing: Vec<String>,
+    pub kind: AppKind,
+    pub script_interpreters: Vec<String>,
+    pub multilib: bool,
+    pub musl: bool,
+    pub needed_libs: Vec<String>,
+    pub binaries: Vec<BinaryInfo>,
+    pub bundled_libs: Vec<BundledLib>,
+    /// Every soname linked or dlopen'ed by the payload, system libs included.
+    pub referenced_libs: Vec<String>,
+    pub links: Vec<PayloadLink>,
+    pub dangling_links: Vec<String>,
+    pub privileged: Vec<PrivilegedFile>,
+    pub non_executable: Vec<String>,
+    pub electron: bool,
+    pub desktop_entries: Vec<DesktopEntry>,
+    pub autostart: Vec<String>,
+    pub integration: Integration,
+    pub extra_files: ExtraFiles,
+    pub lib_conflicts: Vec<LibConflict>,
+    /// Licenses named by the payload's Debian copyright files.
+    pub licenses: Vec<String>,
+    pub changelog: Option<ChangelogEntry>,
+}
+
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub enum PackageType {
+    #[default]
     Deb,
+    /// Arch Linux `.pkg.tar.zst`/`.pkg.tar.xz`.
+    Pacman,
+    /// Alpine `.apk`: concatenated gzip'ed tar segments.
+    Apk,
+    /// `.snap`: a squashfs image with `meta/snap.yaml`.
+    Snap,
+    /// An already unpacked tree, e.g. an installed /opt directory or an
+    /// extracted AppImage.
+    Directory,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolveCandidate {
+    pub attr: String,
+    pub score: u32,
+    pub source: &'static str,
+    /// The differently versioned file the attr provides instead, e.g.
+    /// `libssl.so.1.1` for `libssl.so.3`; such a candidate is not used.
+    pub other_version: Option<String>,
 }
@@ -0,0 +1,42 @@
+use std::io::ErrorKind;
+use std::process::Command;
+
+use log::debug;
+
+/// Parses the expression at `path` with `nix-instantiate --parse`; passes
+/// when it parses or when nix-instantiate is not installed.
+pub fn check_syntax(path: &str) -> Result<(), String> {
+    let output = match Command::new("nix-instantiate").args(["--parse", path]).output() {
+        Ok(output) => output,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            debug!("nix-instantiate not found, not checking the syntax of {}", path);
+            return Ok(());
+        }
+        Err(e) => return Err(format!("Failed to run nix-instantiate: {}", e)),
+    };
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+}
+
+/// Formats `path` in place with `formatter`, a command line the path is
+/// appended to (`nixfmt`, `alejandra -q`).
+pub fn format_file(path: &str, formatter: &str) -> Result<(), String> {
+    let mut words = formatter.split_whitespace();
+    let program = words.next().ok_or("Empty --format-with command")?;
+    let output = Command::new(program)
+        .args(words)
+        .arg(path)
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed on {}: {}",
+            program,
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,201 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use walkdir::WalkDir;
+
+use crate::elf;
+use crate::errors::AppError;
+use crate::structs::{BinaryInfo, ElfKind};
+
+const STORE_DIR: &str = "/nix/store/";
+
+static STORE_PATH: Lazy<Regex> = Lazy::new(|| Regex::new(r#"/nix/store/[^'":\s$]+"#).unwrap());
+/// `libfoo.so.1 => /path/libfoo.so.1 (0x...)` or `libfoo.so.1 => not found`.
+static LDD_LINE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\s*(\S+) => (not found|\S+)").unwrap());
+
+/// A library an ELF file of the result cannot load, or loads from outside
+/// the store.
+enum Problem {
+    NotFound(String),
+    Impure { soname: String, path: String },
+}
+
+/// Builds `target` if it is an expression (a `.nix` file, or a directory
+/// with a default.nix outside the store) and returns the store paths to
+/// check.
+pub fn outputs(target: &str) -> Result<Vec<PathBuf>, AppError> {
+    let path = Path::new(target);
+    let expression = if path.is_dir() && !target.starts_with(STORE_DIR) && path.join("default.nix").is_file() {
+        Some(path.join("default.nix"))
+    } else if target.ends_with(".nix") {
+        Some(path.to_path_buf())
+    } else {
+        None
+    };
+    let Some(expression) = expression else {
+        let resolved = fs::canonicalize(path).map_err(|e| AppError::Input(format!("{}: {}", target, e)))?;
+        return Ok(vec![resolved]);
+    };
+
+    info!("Building {}...", expression.display());
+    let output = Command::new("nix-build")
+        .arg(&expression)
+        .arg("--no-out-link")
+        .output()
+        .map_err(|e| AppError::Generate(format!("Failed to run nix-build: {}", e)))?;
+    if !output.status.success() {
+        return Err(AppError::Generate(format!(
+            "nix-build {} failed:\n{}",
+            expression.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|l| l.starts_with(STORE_DIR))
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Library directories the result's wrappers put on `LD_LIBRARY_PATH`, so
+/// wrapped programs are checked as they are started.
+fn wrapper_lib_dirs(root: &Path) -> Vec<String> {
+    let mut dirs: Vec<String> = WalkDir::new(root)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && !elf::has_elf_magic(e.path()))
+        .filter_map(|e| fs::read_to_string(e.path()).ok())
+        .flat_map(|script| {
+            script
+                .lines()
+                .filter(|l| l.contains("LD_LIBRARY_PATH"))
+                .flat_map(|l| STORE_PATH.find_iter(l).map(|m| m.as_str().to_string()).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    dirs
+}
+
+/// Asks the loader with `ldd`. `None` if it cannot run the file, e.g. one
+/// for another architecture.
+fn ldd(path: &Path, lib_path: &str) -> Option<Vec<Problem>> {
+    let output = Command::new("ldd")
+        .arg(path)
+        .env("LD_LIBRARY_PATH", lib_path)
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+    let problems = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| LDD_LINE.captures(line))
+        .filter_map(|c| match &c[2] {
+            "not found" => Some(Problem::NotFound(c[1].to_string())),
+            resolved if !resolved.starts_with(STORE_DIR) => Some(Problem::Impure {
+                soname: c[1].to_string(),
+                path: resolved.to_string(),
+            }),
+            _ => None,
+        })
+        .collect();
+    Some(problems)
+}
+
+/// Without `ldd`: each `DT_NEEDED` entry is looked up in the file's own
+/// search path (`$ORIGIN` expanded), the wrapper directories and its
+/// directory, the way `patchelf --print-needed` and `--print-rpath` would
+/// be read.
+fn needed_in_search_path(path: &Path, binary: &BinaryInfo, wrapper_dirs: &[String]) -> Vec<Problem> {
+    let origin = path.parent().map(|p| p.display().to_string()).unwrap_or_default();
+    let mut dirs: Vec<String> = binary
+        .search_paths
+        .iter()
+        .map(|p| p.replace("${ORIGIN}", &origin).replace("$ORIGIN", &origin))
+        .collect();
+    dirs.extend(wrapper_dirs.iter().cloned());
+    dirs.push(origin);
+    let mut problems: Vec<Problem> = binary
+        .needed
+        .iter()
+        .filter(|soname| !dirs.iter().any(|d| Path::new(d).join(soname).exists()))
+        .map(|soname| Problem::NotFound(soname.clone()))
+        .collect();
+    problems.extend(binary.search_paths.iter().filter(|p| p.starts_with('/') && !p.starts_with(STORE_DIR)).map(|p| {
+        Problem::Impure {
+            soname: "search path".to_string(),
+            path: p.clone(),
+        }
+    }));
+    problems
+}
+
+/// `app2nix verify <store-path|default.nix>`: builds the expression if
+/// needed, then checks every dynamically linked ELF file of the result
+/// with `ldd` for libraries that are not found or come from outside
+/// /nix/store, such as /usr/lib.
+pub fn run_verify_command(target: &str) -> Result<(), AppError> {
+    let mut missing: Vec<String> = Vec::new();
+    let mut checked = 0;
+    let mut ldd_unavailable = false;
+    for root in outputs(target)? {
+        let wrapper_dirs = wrapper_lib_dirs(&root);
+        let lib_path = wrapper_dirs.join(":");
+        for entry in WalkDir::new(&root).into_iter().filter_map(|e| e.ok()) {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let rel = entry.path().strip_prefix(&root).unwrap_or(entry.path()).display().to_string();
+            let Some(binary) = elf::inspect(entry.path(), &rel) else {
+                continue;
+            };
+            if binary.kind == ElfKind::Static {
+                continue;
+            }
+            checked += 1;
+
+            let mut problems = ldd(entry.path(), &lib_path).unwrap_or_else(|| {
+                ldd_unavailable = true;
+                needed_in_search_path(entry.path(), &binary, &wrapper_dirs)
+            });
+            if let Some(interpreter) = binary.interpreter.as_ref().filter(|i| !i.starts_with(STORE_DIR)) {
+                problems.push(Problem::Impure {
+                    soname: "interpreter".to_string(),
+                    path: interpreter.clone(),
+                });
+            }
+            if problems.is_empty() {
+                continue;
+            }
+
+            println!("{}:", entry.path().display());
+            for problem in problems {
+                match problem {
+                    Problem::NotFound(soname) => {
+                        println!("  {} => not found", soname);
+                        missing.push(soname);
+                    }
+                    Problem::Impure { soname, path } => {
+                        println!("  {} => {} (outside /nix/store)", soname, path);
+                        missing.push(format!("{} (impure: {})", soname, path));
+                    }
+                }
+            }
+        }
+    }
+    if ldd_unavailable {
+        warn!("ldd could not run some files; their DT_NEEDED entries were only looked up in their search paths.");
+    }
+
+    missing.sort();
+    missing.dedup();
+    if !missing.is_empty() {
+        return Err(AppError::Resolve(missing));
+    }
+    info!("All {} dynamically linked ELF files load their libraries from /nix/store.", checked);
+    Ok(())
+}
@@ -0,0 +1,199 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use log::{info, warn};
+use serde_json::{json, Value};
+use walkdir::WalkDir;
+
+use crate::structs::BundledLib;
+
+const OSV_QUERY_URL: &str = "https://api.osv.dev/v1/query";
+const OSV_ECOSYSTEM: &str = "OSS-Fuzz";
+
+pub struct VulnFinding {
+    pub lib_path: String,
+    pub project: String,
+    pub version: String,
+    pub ids: Vec<String>,
+}
+
+/// Upstream project name of a bundled library as OSV knows it.
+fn osv_project(soname_or_file: &str) -> String {
+    let stem = soname_or_file.split(".so").next().unwrap_or(soname_or_file);
+    let project = match stem {
+        "libssl" | "libcrypto" => "openssl",
+        "libz" => "zlib",
+        "libpng16" | "libpng" => "libpng",
+        "libcurl" => "curl",
+        "libsqlite3" => "sqlite3",
+        "libjpeg" | "libturbojpeg" => "libjpeg-turbo",
+        "libfreetype" => "freetype2",
+        "libexpat" => "expat",
+        "libavcodec" | "libavformat" | "libavutil" | "libswscale" | "libswresample" => "ffmpeg",
+        s if s.starts_with("libicu") => "icu",
+        s => s.strip_prefix("lib").unwrap_or(s),
+    };
+    project.to_string()
+}
+
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .trim_start_matches('v')
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|p| !p.is_empty())
+        .filter_map(|p| p.parse().ok())
+        .collect()
+}
+
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    parse_version(a).cmp(&parse_version(b))
+}
+
+/// Whether one `affected[]` entry of an OSV record covers `version`, via the
+/// explicit version list or introduced/fixed events of its ranges.
+fn affects(affected: &Value, version: &str) -> bool {
+    let listed = affected
+        .get("versions")
+        .and_then(|v| v.as_array())
+        .is_some_and(|versions| versions.iter().any(|v| v.as_str() == Some(version)));
+    if listed {
+        return true;
+    }
+
+    let ranges = affected.get("ranges").and_then(|r| r.as_array());
+    ranges.into_iter().flatten().any(|range| {
+        if range.get("type").and_then(|t| t.as_str()) == Some("GIT") {
+            return false;
+        }
+        let mut vulnerable = false;
+        for event in range.get("events").and_then(|e| e.as_array()).into_iter().flatten() {
+            if let Some(introduced) = event.get("introduced").and_then(|v| v.as_str())
+                && (introduced == "0" || compare_versions(version, introduced) != Ordering::Less)
+            {
+                vulnerable = true;
+            }
+            if let Some(fixed) = event.get("fixed").and_then(|v| v.as_str())
+                && compare_versions(version, fixed) != Ordering::Less
+            {
+                vulnerable = false;
+            }
+        }
+        vulnerable
+    })
+}
+
+fn record_matches(record: &Value, project: &str, version: &str) -> bool {
+    record
+        .get("affected")
+        .and_then(|a| a.as_array())
+        .into_iter()
+        .flatten()
+        .any(|affected| {
+            let name = affected
+                .get("package")
+                .and_then(|p| p.get("name"))
+                .and_then(|n| n.as_str())
+                .unwrap_or_default();
+            name.eq_ignore_ascii_case(project) && affects(affected, version)
+        })
+}
+
+fn record_id(record: &Value) -> Option<String> {
+    record.get("id").and_then(|i| i.as_str()).map(|i| i.to_string())
+}
+
+/// Loads every `*.json` OSV record below `db` (e.g. an unpacked `all.zip`).
+fn load_offline_db(db: &Path) -> Vec<Value> {
+    WalkDir::new(db)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && e.path().extension().is_some_and(|x| x == "json"))
+        .filter_map(|e| fs::read_to_string(e.path()).ok())
+        .filter_map(|content| serde_json::from_str(&content).ok())
+        .collect()
+}
+
+fn query_online(project: &str, version: &str) -> Result<Vec<String>, String> {
+    let body = json!({
+        "package": { "name": project, "ecosystem": OSV_ECOSYSTEM },
+        "version": version,
+    });
+    let output = Command::new("curl")
+        .args(["-sS", "--fail", "-X", "POST", "-H", "Content-Type: application/json"])
+        .args(["-d", &body.to_string(), OSV_QUERY_URL])
+        .output()
+        .map_err(|e| format!("Failed to run curl: {}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+
+    let response: Value =
+        serde_json::from_slice(&output.stdout).map_err(|e| format!("Invalid OSV response: {}", e))?;
+    Ok(response
+        .get("vulns")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(record_id)
+        .collect())
+}
+
+/// Looks up known vulnerabilities for every bundled library with a detected
+/// version, against the OSV API or, with `osv_db`, a local OSV export.
+pub fn check_bundled_libs(libs: &[BundledLib], osv_db: Option<&Path>) -> Vec<VulnFinding> {
+    let offline = osv_db.map(load_offline_db);
+    let mut findings = Vec::new();
+
+    for lib in libs {
+        let Some(version) = &lib.version else {
+            continue;
+        };
+        let file = lib.path.rsplit('/').next().unwrap_or(&lib.path);
+        let project = osv_project(lib.soname.as_deref().unwrap_or(file));
+
+        let ids = match &offline {
+            Some(records) => records
+                .iter()
+                .filter(|r| record_matches(r, &project, version))
+                .filter_map(record_id)
+                .collect(),
+            None => match query_online(&project, version) {
+                Ok(ids) => ids,
+                Err(e) => {
+                    warn!("OSV query for {} {} failed: {}", project, version, e);
+                    continue;
+                }
+            },
+        };
+
+        if !ids.is_empty() {
+            findings.push(VulnFinding {
+                lib_path: lib.path.clone(),
+                project,
+                version: version.clone(),
+                ids,
+            });
+        }
+    }
+
+    findings
+}
+
+pub fn print_report(findings: &[VulnFinding], checked: usize) {
+    if findings.is_empty() {
+        info!("No known vulnerabilities found in {} bundled libraries.", checked);
+        return;
+    }
+
+    for finding in findings {
+        warn!(
+            "Known vulnerabilities in bundled {} ({} {}): {}",
+            finding.lib_path,
+            finding.project,
+            finding.version,
+            finding.ids.join(", ")
+        );
+    }
+}
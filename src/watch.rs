@@ -0,0 +1,119 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::SystemTime;
+
+use log::{info, warn};
+
+use crate::cli::{self, CliCommand, WatchOptions};
+use crate::configuration;
+use crate::errors::AppError;
+use crate::verify;
+
+/// The options in the args file: words separated by whitespace, with `#`
+/// starting a comment.
+fn file_args(path: &str) -> Result<Vec<String>, AppError> {
+    let content = fs::read_to_string(path).map_err(|e| AppError::Input(format!("{}: {}", path, e)))?;
+    Ok(content
+        .lines()
+        .map(|l| l.split_once('#').map_or(l, |(before, _)| before))
+        .flat_map(str::split_whitespace)
+        .map(str::to_string)
+        .collect())
+}
+
+/// The generate command line for this round. `--overwrite`, since the
+/// files being replaced are the previous round's.
+fn command_line(options: &WatchOptions) -> Result<Vec<String>, AppError> {
+    let mut args = vec!["app2nix".to_string()];
+    args.extend(options.args.iter().cloned());
+    if let Some(path) = &options.args_file {
+        args.extend(file_args(path)?);
+    }
+    args.push("--overwrite".to_string());
+    Ok(args)
+}
+
+/// Files a change of which calls for a regeneration: the libraries
+/// config, the args file, the template, phase snippets and local inputs.
+fn watched_files(options: &WatchOptions, args: &[String]) -> Vec<PathBuf> {
+    let mut files = vec![PathBuf::from(configuration::get_config_path())];
+    files.extend(options.args_file.iter().map(PathBuf::from));
+    if let Ok(CliCommand::Generate(generate)) = cli::parse_args(args) {
+        files.extend(generate.template_file.iter().map(PathBuf::from));
+        files.extend(generate.hook_files.iter().map(|(_, path)| PathBuf::from(path)));
+        files.extend(generate.inputs.iter().filter(|i| Path::new(i).is_file()).map(PathBuf::from));
+    }
+    files
+}
+
+fn modification_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files.iter().map(|f| fs::metadata(f).and_then(|m| m.modified()).ok()).collect()
+}
+
+/// Runs app2nix again as a new process, so the config and template are
+/// read afresh while the scan comes from the cache, then builds the
+/// result with `--build`. Failures are reported and watching goes on.
+fn regenerate(program: &Path, args: &[String], build: bool) {
+    match Command::new(program).args(&args[1..]).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => {
+            warn!("Generation failed ({}); fix the inputs and save again.", status);
+            return;
+        }
+        Err(e) => {
+            warn!("Failed to run {}: {}", program.display(), e);
+            return;
+        }
+    }
+    if build {
+        match verify::outputs("default.nix") {
+            Ok(paths) => {
+                for path in paths {
+                    info!("Built {}", path.display());
+                }
+            }
+            Err(e) => warn!("{}", e),
+        }
+    }
+}
+
+/// `app2nix watch <input>...`: generates, then watches the files the
+/// generation read and generates again each time one of them changes,
+/// until interrupted.
+pub fn run_watch_command(options: &WatchOptions) -> Result<(), AppError> {
+    let program = env::current_exe().map_err(|e| AppError::Generate(format!("Cannot find app2nix itself: {}", e)))?;
+    loop {
+        let (files, before) = match command_line(options) {
+            Ok(args) => {
+                let files = watched_files(options, &args);
+                let before = modification_times(&files);
+                regenerate(&program, &args, options.build);
+                (files, before)
+            }
+            // A broken args file is waited out like a failed generation
+            Err(e) => {
+                warn!("{}", e);
+                let args: Vec<String> = ["app2nix".to_string()].into_iter().chain(options.args.iter().cloned()).collect();
+                let files = watched_files(options, &args);
+                let before = modification_times(&files);
+                (files, before)
+            }
+        };
+        let names: Vec<String> = files.iter().map(|f| f.display().to_string()).collect();
+        info!("Watching {} (Ctrl-C stops)...", names.join(", "));
+        while modification_times(&files) == before {
+            thread::sleep(options.interval);
+        }
+        let changed: Vec<&str> = files
+            .iter()
+            .zip(&before)
+            .zip(modification_times(&files))
+            .filter(|((_, old), new)| **old != *new)
+            .map(|((file, _), _)| file.to_str().unwrap_or_default())
+            .collect();
+        info!("{} changed; regenerating.", changed.join(", "));
+    }
+}